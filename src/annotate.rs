@@ -0,0 +1,246 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::board::BitBoardState;
+use crate::moves::Move;
+use crate::pgn::{Annotation, Game};
+use crate::piece::Color;
+use crate::search::{SearchLimits, SearchScore, Searcher};
+
+/// How much worse than the best move, in centipawns, a move must score to be
+/// marked as an inaccuracy, a mistake or a blunder.
+const INACCURACY: i32 = 50;
+const MISTAKE: i32 = 100;
+const BLUNDER: i32 = 300;
+/// Scores are capped at this many centipawns when judging moves, so that
+/// giving up some of a winning advantage isn't taken for a blunder.
+const SCORE_CAP: i32 = 1000;
+/// The hash size of each searcher annotating games, in megabytes.
+const ANNOTATE_HASH_MB: usize = 64;
+
+/// What the search made of a position.
+struct Analysis
+{
+    /// For the side to move.
+    score: SearchScore,
+    /// The best line, empty if the game is over.
+    pv:    Vec<Move>,
+}
+
+/// Annotates many games on several threads, returning them in the same order.
+/// Each game is annotated on a thread of its own while there are enough of
+/// them, and the threads left over help with the searches.
+pub fn annotate_games(
+    games: &[Game], limits: &SearchLimits, threads: usize,
+) -> Vec<Game>
+{
+    let workers = threads.max(1).min(games.len());
+    let next = AtomicUsize::new(0);
+    let annotated = Mutex::new(vec![None; games.len()]);
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                let mut searcher = Searcher::default();
+                searcher.set_hash_size(ANNOTATE_HASH_MB);
+                searcher.set_threads((threads / workers).max(1));
+
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= games.len() {
+                        break;
+                    }
+
+                    let game =
+                        annotate_game(&mut searcher, &games[index], limits);
+                    annotated.lock().unwrap()[index] = Some(game);
+                }
+            });
+        }
+    });
+
+    annotated.into_inner().unwrap().into_iter().flatten().collect()
+}
+
+/// Searches every position of a game, adding the evaluation after each move
+/// as an `[%eval]` comment. Moves that lose ground are marked `?!`, `?` or
+/// `??`, with the best move instead as a variation.
+pub fn annotate_game(
+    searcher: &mut Searcher, game: &Game, limits: &SearchLimits,
+) -> Game
+{
+    searcher.clear();
+    let mut board = game.start.clone();
+    let mut boards = vec![board.clone()];
+    let mut analyses = vec![analyse(searcher, &board, limits)];
+    for mv in &game.moves {
+        board.make_move(*mv);
+        analyses.push(analyse(searcher, &board, limits));
+        boards.push(board.clone());
+    }
+
+    let mut annotated = game.clone();
+    annotated.set_tag("Annotator", "bcld");
+    for (i, mv) in game.moves.iter().enumerate() {
+        let (before, after) = (&analyses[i], &analyses[i + 1]);
+        let best = centipawns(before.score);
+        let played = match before.pv.first() {
+            Some(best_move) if best_move == mv => best,
+            _ => -centipawns(after.score),
+        };
+
+        // a mate ending the game is written as `#0`, and stalemate as 0.00
+        let mut comment = vec![eval(flip(after.score), boards[i].turn())];
+        let mut annotation = Annotation::default();
+        let judgement = match best - played {
+            drop if drop >= BLUNDER => Some(("??", "Blunder.")),
+            drop if drop >= MISTAKE => Some(("?", "Mistake.")),
+            drop if drop >= INACCURACY => Some(("?!", "Inaccuracy.")),
+            _ => None,
+        };
+        if let Some((glyph, name)) = judgement {
+            annotation.glyph = Some(glyph.to_string());
+            match before.pv.first() {
+                Some(best_move) => {
+                    let best_move = boards[i].to_san(*best_move);
+                    comment.push(format!("{} {} was best.", name, best_move));
+                    annotation.variation = before.pv.clone();
+                    annotation.variation_comment =
+                        Some(eval(before.score, boards[i].turn()));
+                },
+                None => comment.push(name.to_string()),
+            }
+        }
+
+        annotation.comment = Some(comment.join(" "));
+        annotated.annotations.insert(i, annotation);
+    }
+
+    annotated
+}
+
+fn analyse(
+    searcher: &mut Searcher, board: &BitBoardState, limits: &SearchLimits,
+) -> Analysis
+{
+    if board.legal_moves().is_empty() {
+        let score = match board.in_check() {
+            true => SearchScore::Mate(0),
+            false => SearchScore::Centipawns(0),
+        };
+        return Analysis { score, pv: Vec::new() };
+    }
+
+    let result = searcher.search(board, limits);
+    Analysis { score: result.score, pv: result.pv }
+}
+
+/// Returns a score in centipawns for judging moves, counting mates as the
+/// largest advantage there is.
+fn centipawns(score: SearchScore) -> i32
+{
+    match score {
+        SearchScore::Centipawns(cp) => cp.clamp(-SCORE_CAP, SCORE_CAP),
+        SearchScore::Mate(moves) if moves > 0 => SCORE_CAP,
+        SearchScore::Mate(_) => -SCORE_CAP,
+    }
+}
+
+/// Returns the score for the other side.
+fn flip(score: SearchScore) -> SearchScore
+{
+    match score {
+        SearchScore::Centipawns(cp) => SearchScore::Centipawns(-cp),
+        SearchScore::Mate(moves) => SearchScore::Mate(-moves),
+    }
+}
+
+/// Writes a score for the given side as an `[%eval]` command, which is always
+/// from white's point of view, in pawns or as moves to mate (`#-3`).
+fn eval(score: SearchScore, side: Color) -> String
+{
+    let score = match side {
+        Color::White => score,
+        Color::Black => flip(score),
+    };
+
+    match score {
+        SearchScore::Centipawns(cp) =>
+            format!("[%eval {:.2}]", cp as f64 / 100.0),
+        SearchScore::Mate(moves) => format!("[%eval #{}]", moves),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::pgn::parse_pgn;
+
+    #[test]
+    fn test_annotate()
+    {
+        let pgn =
+            "1. e4 e5 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# 1-0\n\n1. d4 d5 2. c4 *\n";
+        let games = parse_pgn(pgn).unwrap();
+        let limits = SearchLimits { depth: Some(4), ..Default::default() };
+        let annotated = annotate_games(&games, &limits, 2);
+        assert_eq!(annotated.len(), 2);
+        assert_eq!(annotated[1].moves, games[1].moves);
+
+        // letting the queen mate on f7 is the worst move there is
+        let blunder = &annotated[0].annotations[&5];
+        assert_eq!(blunder.glyph.as_deref(), Some("??"));
+        assert!(blunder.comment.as_ref().unwrap().starts_with("[%eval #1]"));
+        assert!(!blunder.variation.is_empty());
+        let mate = &annotated[0].annotations[&6];
+        assert_eq!(mate.comment.as_deref(), Some("[%eval #0]"));
+        assert_eq!(mate.glyph, None);
+        assert_eq!(annotated[1].annotations.len(), 3);
+        assert_eq!(annotated[0].tag("Annotator"), Some("bcld"));
+
+        let written = annotated[0].to_string();
+        assert!(written.contains("Nf6??"));
+        assert!(written.contains("{[%eval #1] Blunder."));
+        assert_eq!(parse_pgn(&written).unwrap()[0].moves, games[0].moves);
+
+        // throwing away the win to stalemate is still given an eval
+        let stalemate = parse_pgn(
+            "[FEN \"k7/8/8/2Q5/8/8/8/K7 w - - 0 1\"]\n\n1. Qc7 1/2-1/2",
+        )
+        .unwrap();
+        let annotated =
+            annotate_game(&mut Searcher::default(), &stalemate[0], &limits);
+        let blunder = &annotated.annotations[&0];
+        assert_eq!(blunder.glyph.as_deref(), Some("??"));
+        assert!(blunder.comment.as_ref().unwrap().starts_with("[%eval 0.00]"));
+
+        // even the shortest search finds a move, and so the blunder
+        let limits = SearchLimits {
+            movetime: Some(std::time::Duration::from_millis(1)),
+            ..Default::default()
+        };
+        let annotated =
+            annotate_game(&mut Searcher::default(), &games[0], &limits);
+        let blunder = &annotated.annotations[&5];
+        assert_eq!(blunder.glyph.as_deref(), Some("??"));
+        assert!(!blunder.variation.is_empty());
+    }
+
+    #[test]
+    fn test_eval()
+    {
+        assert_eq!(
+            eval(SearchScore::Centipawns(35), Color::White),
+            "[%eval 0.35]"
+        );
+        assert_eq!(
+            eval(SearchScore::Centipawns(35), Color::Black),
+            "[%eval -0.35]"
+        );
+        assert_eq!(eval(SearchScore::Mate(2), Color::Black), "[%eval #-2]");
+        assert_eq!(centipawns(SearchScore::Mate(-1)), -SCORE_CAP);
+        assert_eq!(centipawns(SearchScore::Centipawns(-40)), -40);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::piece::Color::{self, *};
+
+/// Squares are indexed from 0 (a1) to 63 (h8), rank by rank.
+const KNIGHT_OFFSETS: [(i8, i8); 8] =
+    [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_OFFSETS: [(i8, i8); 8] =
+    [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+
+/// The eight ray directions, starting north and going clockwise.
+const DIRECTIONS: [(i8, i8); 8] = KING_OFFSETS;
+
+pub const KNIGHT_ATTACKS: [u64; 64] = leaper_table(&KNIGHT_OFFSETS);
+pub const KING_ATTACKS: [u64; 64] = leaper_table(&KING_OFFSETS);
+pub const PAWN_ATTACKS: [[u64; 64]; 2] =
+    [leaper_table(&[(-1, 1), (1, 1)]), leaper_table(&[(-1, -1), (1, -1)])];
+
+const RAYS: [[u64; 64]; 8] = ray_table();
+
+pub const FILE_A: u64 = 0x0101010101010101;
+pub const FILE_H: u64 = FILE_A << 7;
+
+/// Returns the bitboard of every square reachable from `offsets` by a single
+/// jump, for each starting square.
+const fn leaper_table(offsets: &[(i8, i8)]) -> [u64; 64]
+{
+    let mut table = [0; 64];
+    let mut square = 0;
+
+    while square < 64 {
+        let file = (square % 8) as i8;
+        let rank = (square / 8) as i8;
+
+        let mut i = 0;
+        while i < offsets.len() {
+            let f = file + offsets[i].0;
+            let r = rank + offsets[i].1;
+            if 0 <= f && f < 8 && 0 <= r && r < 8 {
+                table[square] |= 1 << (r * 8 + f);
+            }
+            i += 1;
+        }
+        square += 1;
+    }
+
+    table
+}
+
+const fn ray_table() -> [[u64; 64]; 8]
+{
+    let mut table = [[0; 64]; 8];
+    let mut dir = 0;
+
+    while dir < 8 {
+        let mut square = 0;
+        while square < 64 {
+            let mut f = (square % 8) as i8 + DIRECTIONS[dir].0;
+            let mut r = (square / 8) as i8 + DIRECTIONS[dir].1;
+            while 0 <= f && f < 8 && 0 <= r && r < 8 {
+                table[dir][square] |= 1 << (r * 8 + f);
+                f += DIRECTIONS[dir].0;
+                r += DIRECTIONS[dir].1;
+            }
+            square += 1;
+        }
+        dir += 1;
+    }
+
+    table
+}
+
+/// Returns the attacks along a single ray, stopping at the first blocker.
+fn ray_attacks(dir: usize, square: usize, occupied: u64) -> u64
+{
+    let ray = RAYS[dir][square];
+    let blockers = ray & occupied;
+    if blockers == 0 {
+        return ray;
+    }
+
+    // north, north-east, east and north-west rays move towards higher squares
+    let blocker = match dir {
+        0 | 1 | 2 | 7 => blockers.trailing_zeros(),
+        _ => 63 - blockers.leading_zeros(),
+    };
+
+    ray ^ RAYS[dir][blocker as usize]
+}
+
+pub fn bishop_attacks(square: usize, occupied: u64) -> u64
+{
+    ray_attacks(1, square, occupied)
+        | ray_attacks(3, square, occupied)
+        | ray_attacks(5, square, occupied)
+        | ray_attacks(7, square, occupied)
+}
+
+pub fn rook_attacks(square: usize, occupied: u64) -> u64
+{
+    ray_attacks(0, square, occupied)
+        | ray_attacks(2, square, occupied)
+        | ray_attacks(4, square, occupied)
+        | ray_attacks(6, square, occupied)
+}
+
+pub fn queen_attacks(square: usize, occupied: u64) -> u64
+{
+    bishop_attacks(square, occupied) | rook_attacks(square, occupied)
+}
+
+/// Returns the squares strictly between two aligned squares, or nothing if
+/// they do not share a line.
+pub fn between(a: usize, b: usize) -> u64
+{
+    (0..8)
+        .find(|dir| RAYS[*dir][a] & 1 << b != 0)
+        .map_or(0, |dir| RAYS[dir][a] & !RAYS[dir][b] & !(1 << b))
+}
+
+/// Returns the full line through two aligned squares, or nothing if they do
+/// not share a line.
+pub fn line(a: usize, b: usize) -> u64
+{
+    (0..4)
+        .map(|dir| RAYS[dir][a] | RAYS[dir + 4][a] | 1 << a)
+        .find(|line| line & 1 << b != 0)
+        .unwrap_or(0)
+}
+
+/// Returns the squares attacked by a pawn of the given color.
+pub fn pawn_attacks(color: Color, square: usize) -> u64
+{
+    match color {
+        White => PAWN_ATTACKS[0][square],
+        Black => PAWN_ATTACKS[1][square],
+    }
+}
+
+/// Returns every square attacked by a set of pawns of the given color.
+pub fn pawn_attacks_bb(color: Color, pawns: u64) -> u64
+{
+    match color {
+        White => (pawns << 7) & !FILE_H | (pawns << 9) & !FILE_A,
+        Black => (pawns >> 9) & !FILE_H | (pawns >> 7) & !FILE_A,
+    }
+}
+
+/// Returns the squares of a file.
+pub fn file_mask(file: usize) -> u64 { FILE_A << file }
+
+/// Returns the squares of the files next to the given one.
+pub fn adjacent_files(file: usize) -> u64
+{
+    (if file > 0 { file_mask(file - 1) } else { 0 })
+        | (if file < 7 { file_mask(file + 1) } else { 0 })
+}
+
+/// Returns every square on the ranks in front of a rank, as seen by the given
+/// color.
+pub fn forward_ranks(color: Color, rank: usize) -> u64
+{
+    match color {
+        White if rank < 7 => !0 << (8 * (rank + 1)),
+        Black => (1 << (8 * rank)) - 1,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_leaper_attacks()
+    {
+        // a1 knight reaches b3 and c2
+        assert_eq!(KNIGHT_ATTACKS[0], 1 << 17 | 1 << 10);
+        assert_eq!(KING_ATTACKS[0], 1 << 1 | 1 << 8 | 1 << 9);
+        assert_eq!(KNIGHT_ATTACKS[27].count_ones(), 8);
+        assert_eq!(pawn_attacks(White, 12), 1 << 19 | 1 << 21);
+        assert_eq!(pawn_attacks(Black, 8), 1 << 1);
+    }
+
+    #[test]
+    fn test_slider_attacks()
+    {
+        assert_eq!(rook_attacks(0, 0).count_ones(), 14);
+        assert_eq!(bishop_attacks(27, 0).count_ones(), 13);
+        assert_eq!(queen_attacks(27, 0).count_ones(), 27);
+
+        // a rook on a1 blocked by a piece on a4 and another on d1
+        let occupied = 1 << 24 | 1 << 3;
+        let expected = 1 << 8 | 1 << 16 | 1 << 24 | 1 << 1 | 1 << 2 | 1 << 3;
+        assert_eq!(rook_attacks(0, occupied), expected);
+
+        // a bishop on h8 blocked on e5
+        let occupied = 1 << 36;
+        assert_eq!(bishop_attacks(63, occupied), 1 << 54 | 1 << 45 | 1 << 36);
+    }
+
+    #[test]
+    fn test_pawn_and_file_masks()
+    {
+        // pawns on a2 and h2 attack b3 and g3 only
+        assert_eq!(pawn_attacks_bb(White, 1 << 8 | 1 << 15), 1 << 17 | 1 << 22);
+        assert_eq!(pawn_attacks_bb(Black, 1 << 48), 1 << 41);
+
+        assert_eq!(adjacent_files(0), file_mask(1));
+        assert_eq!(adjacent_files(4), file_mask(3) | file_mask(5));
+        assert_eq!(forward_ranks(White, 6), 0xff << 56);
+        assert_eq!(forward_ranks(White, 7), 0);
+        assert_eq!(forward_ranks(Black, 1), 0xff);
+        assert_eq!(forward_ranks(Black, 0), 0);
+    }
+
+    #[test]
+    fn test_between_and_line()
+    {
+        assert_eq!(between(0, 3), 1 << 1 | 1 << 2);
+        assert_eq!(between(3, 0), 1 << 1 | 1 << 2);
+        assert_eq!(between(0, 27), 1 << 9 | 1 << 18);
+        assert_eq!(between(0, 17), 0);
+
+        assert_eq!(line(9, 18), line(0, 63));
+        assert_eq!(line(0, 63).count_ones(), 8);
+        assert_eq!(line(0, 17), 0);
+    }
+}
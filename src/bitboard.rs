@@ -26,6 +26,8 @@ pub enum BitBoardType
     BlackRooks,
     BlackQueens,
     BlackKings,
+    WhitePieces,
+    BlackPieces,
     AllPieces,
 }
 
@@ -50,7 +52,7 @@ impl From<Piece> for BitBoardType
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct BitBoard
 {
     pub board_type: BitBoardType,
@@ -80,6 +82,8 @@ impl BitBoard
             BlackRooks => 0b10000001 << (8 * 7),
             BlackQueens => 0b00001000 << (8 * 7),
             BlackKings => 0b00010000 << (8 * 7),
+            WhitePieces => 0x000000000000ffff,
+            BlackPieces => 0xffff000000000000,
             AllPieces => 0xffff00000000ffff,
         };
 
@@ -1,11 +1,17 @@
-use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
 
 use strum::IntoEnumIterator;
 
+use crate::attacks::pawn_attacks;
+use crate::bitboard::BitBoardType::*;
 use crate::bitboard::{BitBoard, BitBoardType};
-use crate::piece::{Color, Piece};
+use crate::moves::Move;
+use crate::nnue::{Accumulators, Network};
+use crate::piece::{Color, Piece, PieceType};
+use crate::zobrist::*;
 use crate::{CastleAvailability, Square};
 
 /// A trait representing anything that can represent a full game state.
@@ -20,6 +26,7 @@ pub trait GameState
 }
 
 /// A collection of BitBoards representing a full game state.
+#[derive(Clone)]
 pub struct BitBoardState
 {
     state:               Vec<BitBoard>,
@@ -28,9 +35,441 @@ pub struct BitBoardState
     en_passant_target:   Option<Square>,
     halfmove_clock:      u8,
     move_number:         u16,
+    mailbox:             [Option<Piece>; 64],
+    hash:                u64,
+    history:             Vec<Undo>,
+    /// The network's first layer, kept up to date by making moves once a
+    /// network is set.
+    accumulators:        Option<Accumulators>,
 }
 
-impl BitBoardState {}
+/// Everything needed to take back a move.
+#[derive(Clone)]
+struct Undo
+{
+    mv:                  Option<Move>,
+    captured:            Option<(Piece, usize)>,
+    castle_availability: CastleAvailability,
+    en_passant_target:   Option<Square>,
+    halfmove_clock:      u8,
+    hash:                u64,
+}
+
+impl BitBoardState
+{
+    /// Returns the color whose turn it is to move.
+    pub fn turn(&self) -> Color { self.turn }
+
+    /// Returns the bitboard of every piece of the given kind.
+    pub fn pieces(&self, piece: Piece) -> u64
+    {
+        self.state[BitBoardType::from(piece) as usize].bits
+    }
+
+    /// Returns the bitboard of every piece of the given type, of either color.
+    pub fn pieces_of_type(&self, piece: PieceType) -> u64
+    {
+        self.pieces(Piece { color: Color::White, piece })
+            | self.pieces(Piece { color: Color::Black, piece })
+    }
+
+    /// Returns the bitboard of every piece of the given color.
+    pub fn color_occupancy(&self, color: Color) -> u64
+    {
+        match color {
+            Color::White => self.state[WhitePieces as usize].bits,
+            Color::Black => self.state[BlackPieces as usize].bits,
+        }
+    }
+
+    /// Returns the bitboard of every piece on the board.
+    pub fn occupancy(&self) -> u64 { self.state[AllPieces as usize].bits }
+
+    /// Returns the piece standing on a square, if any.
+    pub fn piece_at(&self, square: usize) -> Option<Piece>
+    {
+        self.mailbox[square]
+    }
+
+    /// Returns the square of the given color's king.
+    pub fn king_square(&self, color: Color) -> usize
+    {
+        self.pieces(Piece { color, piece: PieceType::King }).trailing_zeros()
+            as usize
+    }
+
+    pub fn castle_availability(&self) -> &CastleAvailability
+    {
+        &self.castle_availability
+    }
+
+    pub fn en_passant_target(&self) -> Option<&Square>
+    {
+        self.en_passant_target.as_ref()
+    }
+
+    pub fn halfmove_clock(&self) -> u8 { self.halfmove_clock }
+
+    pub fn move_number(&self) -> u16 { self.move_number }
+
+    /// Returns the Zobrist hash of the position.
+    pub fn hash(&self) -> u64 { self.hash }
+
+    /// Returns the number of moves made since the position was set up.
+    pub fn ply(&self) -> usize { self.history.len() }
+
+    /// Returns the last move made, if any.
+    pub fn last_move(&self) -> Option<Move>
+    {
+        self.history.last().and_then(|u| u.mv)
+    }
+
+    /// Returns true if the side to move is in check.
+    pub fn in_check(&self) -> bool
+    {
+        let king = self.king_square(self.turn);
+        self.attackers_to(king, self.occupancy())
+            & self.color_occupancy(self.turn.opposite())
+            != 0
+    }
+
+    /// Returns true if the position is drawn by the fifty move rule, by
+    /// repetition since the position was set up, or because neither side has
+    /// enough material left to mate.
+    pub fn is_draw(&self) -> bool
+    {
+        if self.halfmove_clock >= 100 {
+            return true;
+        }
+
+        self.repetitions() > 0 || self.insufficient_material()
+    }
+
+    /// Returns true if the game is drawn under the rules of chess: by the
+    /// fifty move rule, by the position occurring for the third time, or
+    /// because neither side has enough material left to mate. Unlike
+    /// `is_draw`, a position seen only twice can still be played on from.
+    pub fn is_draw_by_rule(&self) -> bool
+    {
+        self.halfmove_clock >= 100
+            || self.repetitions() >= 2
+            || self.insufficient_material()
+    }
+
+    /// Returns how many times the position occurred before, since the last
+    /// irreversible move.
+    fn repetitions(&self) -> usize
+    {
+        let reversible = (self.halfmove_clock as usize).min(self.history.len());
+        self.history[self.history.len() - reversible..]
+            .iter()
+            .rev()
+            .skip(1)
+            .step_by(2)
+            .filter(|undo| undo.hash == self.hash)
+            .count()
+    }
+
+    fn insufficient_material(&self) -> bool
+    {
+        use PieceType::*;
+
+        let heavy = self.pieces_of_type(Pawn)
+            | self.pieces_of_type(Rook)
+            | self.pieces_of_type(Queen);
+        let minors = self.pieces_of_type(Knight) | self.pieces_of_type(Bishop);
+
+        heavy == 0 && minors.count_ones() <= 1
+    }
+
+    fn castle_index(&self) -> [bool; 4]
+    {
+        let castling = &self.castle_availability;
+        [castling.white.0, castling.white.1, castling.black.0, castling.black.1]
+    }
+
+    fn castle_hash(&self) -> u64
+    {
+        self.castle_index()
+            .iter()
+            .zip(CASTLE_KEYS.iter())
+            .filter(|(right, _)| **right)
+            .fold(0, |acc, (_, key)| acc ^ key)
+    }
+
+    fn en_passant_hash(&self) -> u64
+    {
+        self.en_passant_target.map_or(0, |sq| EN_PASSANT_KEYS[sq.index() % 8])
+    }
+
+    /// Rebuilds the aggregate bitboards, mailbox and hash from the piece
+    /// bitboards.
+    fn refresh(mut self) -> Self
+    {
+        let mut white = 0;
+        let mut black = 0;
+        self.mailbox = [None; 64];
+        self.hash = 0;
+
+        for bitboard in self.state.iter() {
+            if let Ok(piece) = Piece::try_from(bitboard.board_type) {
+                match piece.color {
+                    Color::White => white |= bitboard.bits,
+                    Color::Black => black |= bitboard.bits,
+                }
+                let mut bits = bitboard.bits;
+                while bits != 0 {
+                    let square = bits.trailing_zeros() as usize;
+                    bits &= bits - 1;
+                    self.mailbox[square] = Some(piece);
+                    self.hash ^=
+                        PIECE_KEYS[bitboard.board_type as usize][square];
+                }
+            }
+        }
+
+        self.state[WhitePieces as usize].bits = white;
+        self.state[BlackPieces as usize].bits = black;
+        self.state[AllPieces as usize].bits = white | black;
+
+        self.hash ^= self.castle_hash() ^ self.en_passant_hash();
+        if self.turn == Color::Black {
+            self.hash ^= SIDE_KEY;
+        }
+
+        self
+    }
+
+    fn toggle_piece(&mut self, piece: Piece, square: usize)
+    {
+        let board_type = BitBoardType::from(piece);
+        let color_type = match piece.color {
+            Color::White => WhitePieces,
+            Color::Black => BlackPieces,
+        };
+
+        self.state[board_type as usize].bits ^= 1 << square;
+        self.state[color_type as usize].bits ^= 1 << square;
+        self.state[AllPieces as usize].bits ^= 1 << square;
+        self.hash ^= PIECE_KEYS[board_type as usize][square];
+    }
+
+    fn put_piece(&mut self, piece: Piece, square: usize)
+    {
+        self.toggle_piece(piece, square);
+        self.mailbox[square] = Some(piece);
+        if let Some(accumulators) = &mut self.accumulators {
+            accumulators.record(piece, square, true);
+        }
+    }
+
+    fn remove_piece(&mut self, piece: Piece, square: usize)
+    {
+        self.toggle_piece(piece, square);
+        self.mailbox[square] = None;
+        if let Some(accumulators) = &mut self.accumulators {
+            accumulators.record(piece, square, false);
+        }
+    }
+
+    /// Returns the network's first layer for the position, if a network has
+    /// been set.
+    pub fn accumulators(&self) -> Option<&Accumulators>
+    {
+        self.accumulators.as_ref()
+    }
+
+    /// Keeps the first layer of a network up to date as moves are made, or
+    /// stops doing so given `None`.
+    pub fn set_network(&mut self, network: Option<Arc<Network>>)
+    {
+        let current = self.accumulators.as_ref().map(Accumulators::network);
+        self.accumulators = match (network, current) {
+            (Some(network), Some(current))
+                if Arc::ptr_eq(&network, current) =>
+                return,
+            (Some(network), _) => Some(Accumulators::new(network, self)),
+            (None, _) => None,
+        };
+    }
+
+    /// Returns the rook's origin and destination when a king move castles.
+    fn castling_rook(from: usize, to: usize) -> Option<(usize, usize)>
+    {
+        match (from, to) {
+            (4, 6) => Some((7, 5)),
+            (4, 2) => Some((0, 3)),
+            (60, 62) => Some((63, 61)),
+            (60, 58) => Some((56, 59)),
+            _ => None,
+        }
+    }
+
+    /// Plays a move, which must be legal in the position.
+    pub fn make_move(&mut self, mv: Move)
+    {
+        use PieceType::*;
+
+        let from = mv.from as usize;
+        let to = mv.to as usize;
+        let us = self.turn;
+        let piece = self.mailbox[from].expect("no piece to move!");
+
+        let captured = match self.mailbox[to] {
+            Some(captured) => Some((captured, to)),
+            None if piece.piece == Pawn && from % 8 != to % 8 => {
+                let square = if us == Color::White { to - 8 } else { to + 8 };
+                Some((Piece { color: us.opposite(), piece: Pawn }, square))
+            },
+            None => None,
+        };
+
+        self.history.push(Undo {
+            mv: Some(mv),
+            captured,
+            castle_availability: self.castle_availability,
+            en_passant_target: self.en_passant_target,
+            halfmove_clock: self.halfmove_clock,
+            hash: self.hash,
+        });
+        self.hash ^= self.castle_hash() ^ self.en_passant_hash();
+
+        if let Some((captured, square)) = captured {
+            self.remove_piece(captured, square);
+        }
+        self.remove_piece(piece, from);
+        match mv.promotion {
+            Some(promotion) =>
+                self.put_piece(Piece { color: us, piece: promotion }, to),
+            None => self.put_piece(piece, to),
+        }
+
+        if piece.piece == King {
+            if let Some((rook_from, rook_to)) = Self::castling_rook(from, to) {
+                let rook = Piece { color: us, piece: Rook };
+                self.remove_piece(rook, rook_from);
+                self.put_piece(rook, rook_to);
+            }
+        }
+
+        // moving a king or rook, or capturing a rook, gives up castling
+        for square in [from, to] {
+            let castling = &mut self.castle_availability;
+            match square {
+                4 => castling.white = (false, false),
+                7 => castling.white.0 = false,
+                0 => castling.white.1 = false,
+                60 => castling.black = (false, false),
+                63 => castling.black.0 = false,
+                56 => castling.black.1 = false,
+                _ => (),
+            }
+        }
+
+        // only remember en passant targets that can actually be captured
+        self.en_passant_target = None;
+        if piece.piece == Pawn && (from as i32 - to as i32).abs() == 16 {
+            let target = (from + to) / 2;
+            let enemy_pawns =
+                self.pieces(Piece { color: us.opposite(), piece: Pawn });
+            if pawn_attacks(us, target) & enemy_pawns != 0 {
+                self.en_passant_target = Some(Square::from_index(target));
+            }
+        }
+
+        self.halfmove_clock = match piece.piece == Pawn || captured.is_some() {
+            true => 0,
+            false => self.halfmove_clock.saturating_add(1),
+        };
+        if us == Color::Black {
+            self.move_number += 1;
+        }
+
+        self.turn = us.opposite();
+        self.hash ^= SIDE_KEY ^ self.castle_hash() ^ self.en_passant_hash();
+
+        if let Some(mut accumulators) = self.accumulators.take() {
+            accumulators.push(self);
+            self.accumulators = Some(accumulators);
+        }
+    }
+
+    /// Takes back the last move made.
+    pub fn unmake_move(&mut self)
+    {
+        use PieceType::*;
+
+        let undo = self.history.pop().expect("no move to take back!");
+        let mv = undo.mv.expect("cannot take back a null move as a move!");
+        let from = mv.from as usize;
+        let to = mv.to as usize;
+        let us = self.turn.opposite();
+
+        let moved = self.mailbox[to].unwrap();
+        self.remove_piece(moved, to);
+        match mv.promotion {
+            Some(_) => self.put_piece(Piece { color: us, piece: Pawn }, from),
+            None => self.put_piece(moved, from),
+        }
+
+        if moved.piece == King {
+            if let Some((rook_from, rook_to)) = Self::castling_rook(from, to) {
+                let rook = Piece { color: us, piece: Rook };
+                self.remove_piece(rook, rook_to);
+                self.put_piece(rook, rook_from);
+            }
+        }
+
+        if let Some((captured, square)) = undo.captured {
+            self.put_piece(captured, square);
+        }
+
+        if us == Color::Black {
+            self.move_number -= 1;
+        }
+        self.turn = us;
+        self.castle_availability = undo.castle_availability;
+        self.en_passant_target = undo.en_passant_target;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.hash = undo.hash;
+
+        if let Some(mut accumulators) = self.accumulators.take() {
+            accumulators.pop(self);
+            self.accumulators = Some(accumulators);
+        }
+    }
+
+    /// Passes the turn to the other side without moving, as the search does
+    /// when testing whether a position is good even without a move.
+    pub fn make_null_move(&mut self)
+    {
+        self.history.push(Undo {
+            mv:                  None,
+            captured:            None,
+            castle_availability: self.castle_availability,
+            en_passant_target:   self.en_passant_target,
+            halfmove_clock:      self.halfmove_clock,
+            hash:                self.hash,
+        });
+
+        self.hash ^= SIDE_KEY ^ self.en_passant_hash();
+        self.en_passant_target = None;
+        self.halfmove_clock = self.halfmove_clock.saturating_add(1);
+        self.turn = self.turn.opposite();
+    }
+
+    /// Takes back a null move.
+    pub fn unmake_null_move(&mut self)
+    {
+        let undo = self.history.pop().expect("no move to take back!");
+        assert!(undo.mv.is_none(), "cannot take back a move as a null move!");
+
+        self.turn = self.turn.opposite();
+        self.en_passant_target = undo.en_passant_target;
+        self.halfmove_clock = undo.halfmove_clock;
+        self.hash = undo.hash;
+    }
+}
 
 impl GameState for BitBoardState
 {
@@ -43,7 +482,7 @@ impl GameState for BitBoardState
             state.push(BitBoard::default_for_type(bitboard_type));
         }
 
-        BitBoardState { state, ..Default::default() }
+        BitBoardState { state, ..Default::default() }.refresh()
     }
 
     fn as_piece_array(&self) -> [Option<Piece>; 64]
@@ -101,7 +540,7 @@ impl GameState for BitBoardState
         out.push_str(&format!(
             " {} {} {} {} {}",
             self.turn,
-            self.castle_availability.to_string(),
+            self.castle_availability,
             en_passant_target,
             self.halfmove_clock,
             self.move_number,
@@ -139,7 +578,7 @@ impl GameState for BitBoardState
             .parse::<u16>()?;
 
         let mut i: u8 = 0;
-        let mut state: HashMap<BitBoardType, BitBoard> = HashMap::new();
+        let mut state = Self::default().state;
         for piece in pieces.chars() {
             if piece == '/' {
                 continue;
@@ -148,33 +587,44 @@ impl GameState for BitBoardState
                 i += piece as u8 - b'0';
                 continue;
             }
+            else if i >= 64 {
+                return Err("too many squares in fen!".into());
+            }
             else {
                 let bitboard_type =
                     BitBoardType::from(Piece::from_str(&piece.to_string())?);
 
-                let bitboard = state
-                    .entry(bitboard_type)
-                    .or_insert_with(|| BitBoard::empty(bitboard_type));
-
                 let file = i % 8;
                 let rank = 7 - (i / 8);
 
                 let square_index = rank * 8 + file;
-                bitboard.bits |= 1 << square_index;
+                state[bitboard_type as usize].bits |= 1 << square_index;
 
                 i += 1;
             }
         }
-        let state = state.into_values().collect();
 
-        Ok(Box::new(Self {
-            state,
-            turn,
-            castle_availability,
-            en_passant_target,
-            halfmove_clock,
-            move_number,
-        }))
+        for color in [Color::White, Color::Black] {
+            let king = Piece { color, piece: PieceType::King };
+            if state[BitBoardType::from(king) as usize].bits.count_ones() != 1 {
+                return Err(
+                    format!("fen needs exactly one {} king!", color).into()
+                );
+            }
+        }
+
+        Ok(Box::new(
+            Self {
+                state,
+                turn,
+                castle_availability,
+                en_passant_target,
+                halfmove_clock,
+                move_number,
+                ..Default::default()
+            }
+            .refresh(),
+        ))
     }
 }
 
@@ -194,6 +644,10 @@ impl Default for BitBoardState
             castle_availability: CastleAvailability::default(),
             halfmove_clock: 0,
             move_number: 1,
+            mailbox: [None; 64],
+            hash: 0,
+            history: Vec::new(),
+            accumulators: None,
         }
     }
 }
@@ -230,7 +684,7 @@ mod tests
 
     use super::{BitBoardState, GameState};
     use crate::bitboard::BitBoard;
-    use crate::piece::Piece;
+    use crate::piece::{Color, Piece};
 
     #[test]
     fn test_start_of_game_state()
@@ -304,4 +758,22 @@ mod tests
             assert_eq!(board.as_fen(), test.to_string())
         }
     }
+
+    #[test]
+    fn test_null_move()
+    {
+        let fen =
+            "rnbqkbnr/ppp1pppp/8/8/3pP3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 3";
+        let mut board = BitBoardState::from_fen(fen).unwrap();
+        let hash = board.hash();
+
+        board.make_null_move();
+        assert_eq!(board.turn(), Color::White);
+        assert_eq!(board.en_passant_target(), None);
+        assert_ne!(board.hash(), hash);
+
+        board.unmake_null_move();
+        assert_eq!(board.as_fen(), fen);
+        assert_eq!(board.hash(), hash);
+    }
 }
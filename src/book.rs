@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use crate::board::BitBoardState;
+use crate::moves::Move;
+use crate::pgn::{parse_pgn, Game};
+
+/// How many plies into each game its moves are taken for the book.
+const BOOK_PLIES: usize = 20;
+
+/// Moves to play straight away in familiar positions, gathered from the
+/// openings of a collection of games.
+#[derive(Default)]
+pub struct Book
+{
+    /// The moves played from each position, by hash, and how often.
+    moves: HashMap<u64, Vec<(Move, u32)>>,
+}
+
+impl Book
+{
+    pub fn from_games(games: &[Game]) -> Self
+    {
+        let mut book = Book::default();
+        for game in games {
+            let mut board = game.start.clone();
+            for &mv in game.moves.iter().take(BOOK_PLIES) {
+                let moves = book.moves.entry(board.hash()).or_default();
+                match moves.iter_mut().find(|(known, _)| *known == mv) {
+                    Some((_, count)) => *count += 1,
+                    None => moves.push((mv, 1)),
+                }
+                board.make_move(mv);
+            }
+        }
+
+        book
+    }
+
+    /// Reads a book from a PGN file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>>
+    {
+        let games = parse_pgn(&fs::read_to_string(path)?)?;
+        if games.is_empty() {
+            return Err(format!("no games in {}!", path.display()).into());
+        }
+
+        Ok(Book::from_games(&games))
+    }
+
+    /// Returns the number of positions in the book.
+    pub fn len(&self) -> usize { self.moves.len() }
+
+    pub fn is_empty(&self) -> bool { self.moves.is_empty() }
+
+    /// Picks a move for a position given a random number, favouring the
+    /// moves played most often.
+    pub fn probe(&self, board: &BitBoardState, random: u64) -> Option<Move>
+    {
+        // a hash collision could suggest moves from another position
+        let legal = board.legal_moves();
+        let moves: Vec<(Move, u32)> = self
+            .moves
+            .get(&board.hash())?
+            .iter()
+            .filter(|(mv, _)| legal.contains(mv))
+            .copied()
+            .collect();
+
+        let total: u64 = moves.iter().map(|(_, count)| *count as u64).sum();
+        let mut roll = random % total.max(1);
+        for (mv, count) in moves {
+            match roll.checked_sub(count as u64) {
+                Some(rest) => roll = rest,
+                None => return Some(mv),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::board::GameState;
+
+    #[test]
+    fn test_book()
+    {
+        let games = parse_pgn(
+            "1. e4 e5 2. Nf3 *\n\n1. e4 c5 *\n\n1. e4 e5 *\n\n1. d4 d5 *\n",
+        )
+        .unwrap();
+        let book = Book::from_games(&games);
+        assert_eq!(book.len(), 4);
+
+        let mut board = BitBoardState::start_of_game();
+        let e4 = Move::from_str("e2e4").unwrap();
+        let chosen: Vec<Move> =
+            (0..4).filter_map(|i| book.probe(&board, i)).collect();
+        assert_eq!(chosen.iter().filter(|&&mv| mv == e4).count(), 3);
+
+        board.make_move(e4);
+        assert!(book.probe(&board, 7).is_some());
+        board.make_move(Move::from_str("c7c5").unwrap());
+        assert_eq!(book.probe(&board, 0), None);
+    }
+}
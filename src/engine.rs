@@ -0,0 +1,265 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::board::BitBoardState;
+use crate::search::{SearchInfo, SearchLimits, SearchResult, Searcher};
+use crate::strength::Strength;
+
+/// Something happening in a running search.
+#[derive(Clone, Debug)]
+pub enum SearchEvent
+{
+    Info(SearchInfo),
+    Finished(SearchResult),
+}
+
+/// Receives progress from a search, on the search's own thread.
+pub trait SearchListener: Send + 'static
+{
+    /// Called after each iteration of the search.
+    fn on_info(&mut self, info: &SearchInfo);
+
+    /// Called once with the final result, before it is handed back by
+    /// `Engine::wait`.
+    fn on_finished(&mut self, _result: &SearchResult) {}
+}
+
+/// Ignores the search's progress.
+impl SearchListener for ()
+{
+    fn on_info(&mut self, _info: &SearchInfo) {}
+}
+
+/// Sends the search's progress down a channel, ignoring a closed receiver.
+impl SearchListener for Sender<SearchEvent>
+{
+    fn on_info(&mut self, info: &SearchInfo)
+    {
+        let _ = self.send(SearchEvent::Info(info.clone()));
+    }
+
+    fn on_finished(&mut self, result: &SearchResult)
+    {
+        let _ = self.send(SearchEvent::Finished(result.clone()));
+    }
+}
+
+/// Controls the engine's searches from any thread.
+#[derive(Clone, Default)]
+pub struct SearchControl
+{
+    stop:      Arc<AtomicBool>,
+    ponderhit: Arc<AtomicBool>,
+}
+
+impl SearchControl
+{
+    /// Asks the running search to finish as soon as it can.
+    pub fn stop(&self) { self.stop.store(true, Ordering::Relaxed); }
+
+    /// Tells a pondering search that the expected move was played, so that it
+    /// should start watching the clock.
+    pub fn ponderhit(&self) { self.ponderhit.store(true, Ordering::Relaxed); }
+
+    pub fn is_stopped(&self) -> bool { self.stop.load(Ordering::Relaxed) }
+
+    pub fn has_ponderhit(&self) -> bool
+    {
+        self.ponderhit.load(Ordering::Relaxed)
+    }
+}
+
+/// Runs searches on a background thread, without any protocol in the way.
+///
+/// Only one search runs at a time. Starting another waits for the last one
+/// to finish, so it should be stopped first unless it has limits.
+pub struct Engine
+{
+    /// The searcher, unless a search thread has it.
+    searcher: Option<Searcher>,
+    search:   Option<JoinHandle<(Searcher, SearchResult)>>,
+    control:  SearchControl,
+}
+
+impl Default for Engine
+{
+    fn default() -> Self { Engine::new(Searcher::default()) }
+}
+
+impl Drop for Engine
+{
+    fn drop(&mut self)
+    {
+        self.control.stop();
+        self.wait();
+    }
+}
+
+impl Engine
+{
+    pub fn new(searcher: Searcher) -> Self
+    {
+        Engine {
+            searcher: Some(searcher),
+            search:   None,
+            control:  SearchControl::default(),
+        }
+    }
+
+    /// Returns a handle for stopping searches from other threads.
+    pub fn control(&self) -> SearchControl { self.control.clone() }
+
+    pub fn stop(&self) { self.control.stop(); }
+
+    pub fn ponderhit(&self) { self.control.ponderhit(); }
+
+    /// Returns whether a search is still running, rather than finished and
+    /// waiting to hand back its result.
+    pub fn is_searching(&self) -> bool
+    {
+        self.search.as_ref().is_some_and(|search| !search.is_finished())
+    }
+
+    /// Sets how many threads each search uses, once no search is running.
+    pub fn set_threads(&mut self, threads: usize)
+    {
+        self.searcher().set_threads(threads);
+    }
+
+    /// Sets how many of the best lines each search looks for, once no search
+    /// is running. They are ranked in the result's `lines`.
+    pub fn set_multi_pv(&mut self, lines: usize)
+    {
+        self.searcher().set_multi_pv(lines);
+    }
+
+    /// Plays at around the given rating, or at full strength given `None`,
+    /// once no search is running.
+    pub fn set_elo(&mut self, elo: Option<u32>)
+    {
+        self.searcher().set_strength(elo.map(Strength::from_elo));
+    }
+
+    /// Returns the searcher, for changing its settings, once no search is
+    /// using it.
+    pub fn searcher(&mut self) -> &mut Searcher
+    {
+        self.wait();
+        self.searcher.as_mut().unwrap()
+    }
+
+    /// Starts searching a position in the background. The limits' stop and
+    /// ponderhit flags are replaced by the engine's own.
+    pub fn start(
+        &mut self, board: &BitBoardState, mut limits: SearchLimits,
+        mut listener: impl SearchListener,
+    )
+    {
+        self.wait();
+        let mut searcher = self.searcher.take().unwrap();
+
+        self.control.stop.store(false, Ordering::Relaxed);
+        self.control.ponderhit.store(false, Ordering::Relaxed);
+        limits.stop = self.control.stop.clone();
+        limits.ponderhit = self.control.ponderhit.clone();
+        let board = board.clone();
+
+        self.search = Some(thread::spawn(move || {
+            let result =
+                searcher.search_with_info(&board, &limits, &mut |info| {
+                    listener.on_info(info)
+                });
+            listener.on_finished(&result);
+            (searcher, result)
+        }));
+    }
+
+    /// Waits for the running search to finish, returning its result.
+    pub fn wait(&mut self) -> Option<SearchResult>
+    {
+        let (searcher, result) =
+            self.search.take()?.join().expect("search thread panicked!");
+        self.searcher = Some(searcher);
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::board::GameState;
+    use crate::moves::Move;
+
+    #[test]
+    fn test_search_events()
+    {
+        let board =
+            *BitBoardState::from_fen("7k/8/6K1/8/8/8/8/R7 w - - 0 1").unwrap();
+        let limits = SearchLimits { depth: Some(4), ..Default::default() };
+        let (sender, receiver) = mpsc::channel();
+
+        let mut engine = Engine::default();
+        engine.start(&board, limits, sender);
+        let result = engine.wait().unwrap();
+
+        let events: Vec<SearchEvent> = receiver.iter().collect();
+        assert_eq!(events.len(), 5);
+        assert!(
+            matches!(&events[0], SearchEvent::Info(info) if info.depth == 1)
+        );
+        assert!(matches!(events[4], SearchEvent::Finished(_)));
+
+        assert_eq!(result.best_move, Some("a1a8".parse::<Move>().unwrap()));
+        assert_eq!(result.ponder_move, None);
+        assert_eq!(result.pv, vec![result.best_move.unwrap()]);
+    }
+
+    #[test]
+    fn test_stop_from_another_thread()
+    {
+        let board = BitBoardState::start_of_game();
+        let limits = SearchLimits { infinite: true, ..Default::default() };
+
+        let mut engine = Engine::default();
+        engine.start(&board, limits, ());
+        assert!(engine.is_searching());
+
+        let control = engine.control();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            control.stop();
+        });
+
+        let result = engine.wait().unwrap();
+        assert!(result.best_move.is_some());
+        assert!(result.ponder_move.is_some());
+        assert!(!engine.is_searching());
+    }
+
+    #[test]
+    fn test_ponderhit()
+    {
+        let board = BitBoardState::start_of_game();
+        let limits = SearchLimits {
+            movetime: Some(Duration::from_millis(20)),
+            ponder: true,
+            ..Default::default()
+        };
+
+        // the clock is ignored until the opponent plays the expected move
+        let mut engine = Engine::default();
+        engine.start(&board, limits, ());
+        thread::sleep(Duration::from_millis(100));
+        assert!(engine.is_searching());
+        assert!(!engine.search.as_ref().unwrap().is_finished());
+
+        engine.ponderhit();
+        assert!(engine.wait().unwrap().best_move.is_some());
+    }
+}
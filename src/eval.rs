@@ -0,0 +1,1241 @@
+use std::error::Error;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub};
+use std::path::Path;
+use std::str::FromStr;
+use std::{fmt, fs};
+
+use strum::{EnumIter, IntoEnumIterator};
+
+use crate::attacks::*;
+use crate::board::BitBoardState;
+use crate::piece::Color::{self, *};
+use crate::piece::Piece;
+use crate::piece::PieceType::{self, *};
+
+/// The game phase of the starting position, counting knights and bishops as
+/// one, rooks as two and queens as four.
+pub const MAX_PHASE: i32 = 24;
+
+/// A pair of middlegame and endgame values, blended by the game phase.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Score
+{
+    pub mg: i32,
+    pub eg: i32,
+}
+
+/// Anything that can score a position.
+pub trait Evaluator
+{
+    /// Returns the score of a position in centipawns, from the side to move's
+    /// perspective.
+    fn evaluate(&self, board: &BitBoardState) -> i32;
+
+    /// Sets up anything the evaluator keeps on a board, before a search
+    /// starts making moves on it.
+    fn prepare(&self, _board: &mut BitBoardState) {}
+}
+
+/// The terms making up the handcrafted evaluation.
+#[derive(EnumIter, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Term
+{
+    Material,
+    PieceSquares,
+    Mobility,
+    Pawns,
+    KingSafety,
+    Rooks,
+    Bishops,
+}
+
+/// Every term of an evaluation, for white and black, before tapering.
+pub struct EvalTrace
+{
+    pub terms: Vec<(Term, [Score; 2])>,
+    pub phase: i32,
+    pub turn:  Color,
+}
+
+/// Every weight used by the handcrafted evaluation. Piece-square tables are
+/// laid out from white's point of view, with a8 first.
+#[derive(Clone, PartialEq, Debug)]
+pub struct EvalParams
+{
+    pub material:       [Score; 6],
+    pub piece_squares:  [[Score; 64]; 6],
+    /// Per extra square reached by knights, bishops, rooks and queens.
+    pub mobility:       [Score; 4],
+    pub passed_pawn:    [Score; 8],
+    pub isolated_pawn:  Score,
+    pub doubled_pawn:   Score,
+    pub backward_pawn:  Score,
+    /// Per knight, bishop, rook and queen attacking the enemy king's zone.
+    pub king_attack:    [Score; 4],
+    pub rook_open_file: Score,
+    pub rook_semi_open: Score,
+    pub bishop_pair:    Score,
+}
+
+/// The default evaluation, built from hand-picked terms.
+#[derive(Clone, Default)]
+pub struct HandcraftedEvaluator
+{
+    pub params: EvalParams,
+}
+
+/// The typical number of squares reached by each piece, which scores zero.
+const MOBILITY_BASELINE: [i32; 4] = [4, 7, 7, 14];
+
+/// The percentage of the king attack weights applied, by number of attackers.
+const ATTACK_SCALE: [i32; 8] = [0, 0, 50, 75, 88, 94, 97, 99];
+
+/// The name and number of weights of each parameter, in the order they're
+/// listed.
+pub const PARAM_LAYOUT: [(&str, usize); 16] = [
+    ("material", 6),
+    ("pawn_squares", 64),
+    ("knight_squares", 64),
+    ("bishop_squares", 64),
+    ("rook_squares", 64),
+    ("queen_squares", 64),
+    ("king_squares", 64),
+    ("mobility", 4),
+    ("passed_pawn", 8),
+    ("isolated_pawn", 1),
+    ("doubled_pawn", 1),
+    ("backward_pawn", 1),
+    ("king_attack", 4),
+    ("rook_open_file", 1),
+    ("rook_semi_open", 1),
+    ("bishop_pair", 1),
+];
+
+/// The total number of weights in the evaluation.
+pub const PARAM_COUNT: usize = 412;
+
+// where each parameter starts in the list of weights
+const MATERIAL: usize = 0;
+const PIECE_SQUARES: usize = 6;
+const MOBILITY: usize = 390;
+const PASSED_PAWN: usize = 394;
+const ISOLATED_PAWN: usize = 402;
+const DOUBLED_PAWN: usize = 403;
+const BACKWARD_PAWN: usize = 404;
+const KING_ATTACK: usize = 405;
+const ROOK_OPEN_FILE: usize = 409;
+const ROOK_SEMI_OPEN: usize = 410;
+const BISHOP_PAIR: usize = 411;
+
+impl Score
+{
+    pub const fn new(mg: i32, eg: i32) -> Self { Score { mg, eg } }
+
+    /// Blends the middlegame and endgame values by the game phase.
+    pub fn taper(self, phase: i32) -> i32
+    {
+        let phase = phase.min(MAX_PHASE);
+        (self.mg * phase + self.eg * (MAX_PHASE - phase)) / MAX_PHASE
+    }
+}
+
+impl Add for Score
+{
+    type Output = Score;
+
+    fn add(self, other: Score) -> Score
+    {
+        Score::new(self.mg + other.mg, self.eg + other.eg)
+    }
+}
+
+impl AddAssign for Score
+{
+    fn add_assign(&mut self, other: Score) { *self = *self + other }
+}
+
+impl Sub for Score
+{
+    type Output = Score;
+
+    fn sub(self, other: Score) -> Score
+    {
+        Score::new(self.mg - other.mg, self.eg - other.eg)
+    }
+}
+
+impl Neg for Score
+{
+    type Output = Score;
+
+    fn neg(self) -> Score { Score::new(-self.mg, -self.eg) }
+}
+
+impl Mul<i32> for Score
+{
+    type Output = Score;
+
+    fn mul(self, n: i32) -> Score { Score::new(self.mg * n, self.eg * n) }
+}
+
+/// Builds a table that scores the same in the middlegame and endgame.
+const fn flat(values: [i32; 64]) -> [Score; 64]
+{
+    let mut table = [Score::new(0, 0); 64];
+    let mut i = 0;
+    while i < 64 {
+        table[i] = Score::new(values[i], values[i]);
+        i += 1;
+    }
+    table
+}
+
+const fn tapered(mg: [i32; 64], eg: [i32; 64]) -> [Score; 64]
+{
+    let mut table = [Score::new(0, 0); 64];
+    let mut i = 0;
+    while i < 64 {
+        table[i] = Score::new(mg[i], eg[i]);
+        i += 1;
+    }
+    table
+}
+
+#[rustfmt::skip]
+const PAWN_TABLE: [Score; 64] = tapered(
+    [
+          0,   0,   0,   0,   0,   0,   0,   0,
+         50,  50,  50,  50,  50,  50,  50,  50,
+         10,  10,  20,  30,  30,  20,  10,  10,
+          5,   5,  10,  25,  25,  10,   5,   5,
+          0,   0,   0,  20,  20,   0,   0,   0,
+          5,  -5, -10,   0,   0, -10,  -5,   5,
+          5,  10,  10, -20, -20,  10,  10,   5,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    [
+          0,   0,   0,   0,   0,   0,   0,   0,
+         80,  80,  80,  80,  80,  80,  80,  80,
+         50,  50,  50,  50,  50,  50,  50,  50,
+         30,  30,  30,  30,  30,  30,  30,  30,
+         20,  20,  20,  20,  20,  20,  20,  20,
+         10,  10,  10,  10,  10,  10,  10,  10,
+          0,   0,   0,   0,   0,   0,   0,   0,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+);
+
+#[rustfmt::skip]
+const KNIGHT_TABLE: [Score; 64] = flat([
+    -50, -40, -30, -30, -30, -30, -40, -50,
+    -40, -20,   0,   0,   0,   0, -20, -40,
+    -30,   0,  10,  15,  15,  10,   0, -30,
+    -30,   5,  15,  20,  20,  15,   5, -30,
+    -30,   0,  15,  20,  20,  15,   0, -30,
+    -30,   5,  10,  15,  15,  10,   5, -30,
+    -40, -20,   0,   5,   5,   0, -20, -40,
+    -50, -40, -30, -30, -30, -30, -40, -50,
+]);
+
+#[rustfmt::skip]
+const BISHOP_TABLE: [Score; 64] = flat([
+    -20, -10, -10, -10, -10, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,  10,  10,   5,   0, -10,
+    -10,   5,   5,  10,  10,   5,   5, -10,
+    -10,   0,  10,  10,  10,  10,   0, -10,
+    -10,  10,  10,  10,  10,  10,  10, -10,
+    -10,   5,   0,   0,   0,   0,   5, -10,
+    -20, -10, -10, -10, -10, -10, -10, -20,
+]);
+
+#[rustfmt::skip]
+const ROOK_TABLE: [Score; 64] = flat([
+      0,   0,   0,   0,   0,   0,   0,   0,
+      5,  10,  10,  10,  10,  10,  10,   5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+      0,   0,   0,   5,   5,   0,   0,   0,
+]);
+
+#[rustfmt::skip]
+const QUEEN_TABLE: [Score; 64] = flat([
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+    -10,   0,   0,   0,   0,   0,   0, -10,
+    -10,   0,   5,   5,   5,   5,   0, -10,
+     -5,   0,   5,   5,   5,   5,   0,  -5,
+      0,   0,   5,   5,   5,   5,   0,  -5,
+    -10,   5,   5,   5,   5,   5,   0, -10,
+    -10,   0,   5,   0,   0,   0,   0, -10,
+    -20, -10, -10,  -5,  -5, -10, -10, -20,
+]);
+
+#[rustfmt::skip]
+const KING_TABLE: [Score; 64] = tapered(
+    [
+        -30, -40, -40, -50, -50, -40, -40, -30,
+        -30, -40, -40, -50, -50, -40, -40, -30,
+        -30, -40, -40, -50, -50, -40, -40, -30,
+        -30, -40, -40, -50, -50, -40, -40, -30,
+        -20, -30, -30, -40, -40, -30, -30, -20,
+        -10, -20, -20, -20, -20, -20, -20, -10,
+         20,  20,   0,   0,   0,   0,  20,  20,
+         20,  30,  10,   0,   0,  10,  30,  20,
+    ],
+    [
+        -50, -40, -30, -20, -20, -30, -40, -50,
+        -30, -20, -10,   0,   0, -10, -20, -30,
+        -30, -10,  20,  30,  30,  20, -10, -30,
+        -30, -10,  30,  40,  40,  30, -10, -30,
+        -30, -10,  30,  40,  40,  30, -10, -30,
+        -30, -10,  20,  30,  30,  20, -10, -30,
+        -30, -30,   0,   0,   0,   0, -30, -30,
+        -50, -30, -30, -30, -30, -30, -30, -50,
+    ],
+);
+
+impl Default for EvalParams
+{
+    fn default() -> Self
+    {
+        EvalParams {
+            material:       [
+                Score::new(100, 120),
+                Score::new(320, 300),
+                Score::new(330, 320),
+                Score::new(500, 520),
+                Score::new(900, 940),
+                Score::new(0, 0),
+            ],
+            piece_squares:  [
+                PAWN_TABLE,
+                KNIGHT_TABLE,
+                BISHOP_TABLE,
+                ROOK_TABLE,
+                QUEEN_TABLE,
+                KING_TABLE,
+            ],
+            mobility:       [
+                Score::new(4, 4),
+                Score::new(5, 5),
+                Score::new(2, 4),
+                Score::new(1, 2),
+            ],
+            passed_pawn:    [
+                Score::new(0, 0),
+                Score::new(5, 10),
+                Score::new(10, 20),
+                Score::new(15, 35),
+                Score::new(25, 60),
+                Score::new(40, 100),
+                Score::new(70, 150),
+                Score::new(0, 0),
+            ],
+            isolated_pawn:  Score::new(-10, -15),
+            doubled_pawn:   Score::new(-10, -20),
+            backward_pawn:  Score::new(-8, -10),
+            king_attack:    [
+                Score::new(20, 0),
+                Score::new(20, 0),
+                Score::new(40, 0),
+                Score::new(80, 0),
+            ],
+            rook_open_file: Score::new(25, 10),
+            rook_semi_open: Score::new(12, 5),
+            bishop_pair:    Score::new(30, 50),
+        }
+    }
+}
+
+impl EvalParams
+{
+    /// Iterates over every weight, in the order of `PARAM_LAYOUT`.
+    fn values(&self) -> impl Iterator<Item = &Score>
+    {
+        self.material
+            .iter()
+            .chain(self.piece_squares.iter().flatten())
+            .chain(self.mobility.iter())
+            .chain(self.passed_pawn.iter())
+            .chain([
+                &self.isolated_pawn,
+                &self.doubled_pawn,
+                &self.backward_pawn,
+            ])
+            .chain(self.king_attack.iter())
+            .chain([
+                &self.rook_open_file,
+                &self.rook_semi_open,
+                &self.bishop_pair,
+            ])
+    }
+
+    fn values_mut(&mut self) -> impl Iterator<Item = &mut Score>
+    {
+        self.material
+            .iter_mut()
+            .chain(self.piece_squares.iter_mut().flatten())
+            .chain(self.mobility.iter_mut())
+            .chain(self.passed_pawn.iter_mut())
+            .chain([
+                &mut self.isolated_pawn,
+                &mut self.doubled_pawn,
+                &mut self.backward_pawn,
+            ])
+            .chain(self.king_attack.iter_mut())
+            .chain([
+                &mut self.rook_open_file,
+                &mut self.rook_semi_open,
+                &mut self.bishop_pair,
+            ])
+    }
+
+    /// Lists every weight, in the order of `PARAM_LAYOUT`.
+    pub fn to_vec(&self) -> Vec<Score> { self.values().copied().collect() }
+
+    /// Builds parameters from a list of weights, in the order of
+    /// `PARAM_LAYOUT`.
+    pub fn from_slice(weights: &[Score]) -> Result<Self, Box<dyn Error>>
+    {
+        if weights.len() != PARAM_COUNT {
+            return Err(format!(
+                "expected {} weights but found {}!",
+                PARAM_COUNT,
+                weights.len()
+            )
+            .into());
+        }
+
+        let mut params = EvalParams::default();
+        for (value, weight) in params.values_mut().zip(weights) {
+            *value = *weight;
+        }
+
+        Ok(params)
+    }
+
+    /// Reads parameters from a text file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>>
+    {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+}
+
+impl fmt::Display for EvalParams
+{
+    /// Lists each parameter by name, followed by its middlegame/endgame
+    /// pairs eight to a line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let weights = self.to_vec();
+        let mut start = 0;
+
+        writeln!(
+            f,
+            "# bcld evaluation parameters, as middlegame/endgame pairs"
+        )?;
+
+        for (name, len) in PARAM_LAYOUT.iter() {
+            writeln!(f, "{}", name)?;
+            for row in weights[start..start + len].chunks(8) {
+                let pairs: Vec<String> = row
+                    .iter()
+                    .map(|score| format!("{}/{}", score.mg, score.eg))
+                    .collect();
+                writeln!(f, "    {}", pairs.join(" "))?;
+            }
+            start += len;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads parameters written as text, in the form `Display` writes them.
+///
+/// Each parameter is named as in `PARAM_LAYOUT`, followed by exactly as many
+/// weights as it holds, each written `mg/eg` in centipawns. Piece-square
+/// tables run from a8 to h1 as seen by white, and multi-valued parameters
+/// follow the order of the fields of `EvalParams`. Names and weights are
+/// separated by any whitespace, and anything after a `#` on a line is
+/// ignored. Parameters may come in any order, and those left out keep their
+/// built-in defaults.
+impl FromStr for EvalParams
+{
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let mut weights = EvalParams::default().to_vec();
+        let mut seen = [false; PARAM_LAYOUT.len()];
+        // the parameter being read, where its weights start and how many
+        // have been read so far
+        let mut current: Option<(usize, usize, usize)> = None;
+
+        let finish = |current: Option<(usize, usize, usize)>| match current {
+            Some((param, _, read)) if read != PARAM_LAYOUT[param].1 =>
+                Err(format!(
+                    "{} needs {} weights but has {}!",
+                    PARAM_LAYOUT[param].0, PARAM_LAYOUT[param].1, read
+                )),
+            _ => Ok(()),
+        };
+
+        for (number, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            for token in line.split_whitespace() {
+                let weight = match token.split_once('/') {
+                    Some((mg, eg)) => mg.parse().ok().zip(eg.parse().ok()),
+                    None => {
+                        finish(current)?;
+                        let param = PARAM_LAYOUT
+                            .iter()
+                            .position(|(name, _)| *name == token)
+                            .ok_or_else(|| {
+                                format!("unknown parameter {}!", token)
+                            })?;
+                        if seen[param] {
+                            return Err(
+                                format!("{} is given twice!", token).into()
+                            );
+                        }
+                        seen[param] = true;
+
+                        let start = PARAM_LAYOUT[..param]
+                            .iter()
+                            .map(|(_, len)| len)
+                            .sum();
+                        current = Some((param, start, 0));
+                        continue;
+                    },
+                };
+
+                let (param, start, read, weight) = match (current, weight) {
+                    (Some((param, start, read)), Some((mg, eg))) =>
+                        (param, start, read, Score::new(mg, eg)),
+                    (Some(_), _) =>
+                        return Err(format!(
+                            "invalid weight {} on line {}!",
+                            token,
+                            number + 1
+                        )
+                        .into()),
+                    (None, _) =>
+                        return Err(format!(
+                            "weight {} on line {} has no parameter!",
+                            token,
+                            number + 1
+                        )
+                        .into()),
+                };
+                if read == PARAM_LAYOUT[param].1 {
+                    return Err(format!(
+                        "{} has more than {} weights!",
+                        PARAM_LAYOUT[param].0, PARAM_LAYOUT[param].1
+                    )
+                    .into());
+                }
+
+                weights[start + read] = weight;
+                current = Some((param, start, read + 1));
+            }
+        }
+        finish(current)?;
+
+        EvalParams::from_slice(&weights)
+    }
+}
+
+/// Returns the index of a piece type into the evaluation tables.
+fn piece_index(piece: PieceType) -> usize
+{
+    match piece {
+        Pawn => 0,
+        Knight => 1,
+        Bishop => 2,
+        Rook => 3,
+        Queen => 4,
+        King => 5,
+    }
+}
+
+/// Returns the rank of a square as seen from the given color's side.
+fn relative_rank(color: Color, square: usize) -> usize
+{
+    match color {
+        White => square / 8,
+        Black => 7 - square / 8,
+    }
+}
+
+/// Iterates over the squares of a bitboard.
+fn squares(mut bits: u64) -> impl Iterator<Item = usize>
+{
+    std::iter::from_fn(move || {
+        if bits == 0 {
+            return None;
+        }
+        let square = bits.trailing_zeros() as usize;
+        bits &= bits - 1;
+        Some(square)
+    })
+}
+
+/// Returns the game phase, from 0 (bare kings and pawns) up to `MAX_PHASE`.
+pub fn game_phase(board: &BitBoardState) -> i32
+{
+    let count = |piece| board.pieces_of_type(piece).count_ones() as i32;
+
+    (count(Knight) + count(Bishop) + 2 * count(Rook) + 4 * count(Queen))
+        .min(MAX_PHASE)
+}
+
+/// How many of a side's pawns each pawn structure weight applies to.
+#[derive(Default)]
+struct PawnCounts
+{
+    /// Passed pawns by relative rank.
+    passed:   [i32; 8],
+    isolated: i32,
+    /// Pawns behind another on the same file.
+    doubled:  i32,
+    backward: i32,
+}
+
+impl PawnCounts
+{
+    fn new(board: &BitBoardState, color: Color) -> Self
+    {
+        let own = board.pieces(Piece { color, piece: Pawn });
+        let enemy =
+            board.pieces(Piece { color: color.opposite(), piece: Pawn });
+        let enemy_attacks = pawn_attacks_bb(color.opposite(), enemy);
+        let mut counts = PawnCounts::default();
+
+        for file in 0..8 {
+            let count = (own & file_mask(file)).count_ones() as i32;
+            if count > 1 {
+                counts.doubled += count - 1;
+            }
+        }
+
+        for square in squares(own) {
+            let file = square % 8;
+            let rank = square / 8;
+            let ahead = forward_ranks(color, rank);
+            let neighbours = adjacent_files(file);
+
+            if enemy & ahead & (neighbours | file_mask(file)) == 0 {
+                counts.passed[relative_rank(color, square)] += 1;
+            }
+
+            if own & neighbours == 0 {
+                counts.isolated += 1;
+                continue;
+            }
+
+            // a backward pawn has no neighbours level with or behind it, and
+            // cannot advance without being taken by a pawn
+            let stop = match color {
+                White => square + 8,
+                Black => square - 8,
+            };
+            if own & neighbours & !ahead == 0 && enemy_attacks & 1 << stop != 0
+            {
+                counts.backward += 1;
+            }
+        }
+
+        counts
+    }
+}
+
+/// Returns the squares attacked by a knight, bishop, rook or queen.
+fn piece_attacks(piece: PieceType, square: usize, occupied: u64) -> u64
+{
+    match piece {
+        Knight => KNIGHT_ATTACKS[square],
+        Bishop => bishop_attacks(square, occupied),
+        Rook => rook_attacks(square, occupied),
+        _ => queen_attacks(square, occupied),
+    }
+}
+
+/// Returns the squares reached by a side's knights, bishops, rooks and
+/// queens, each less its baseline.
+fn mobility_counts(board: &BitBoardState, color: Color) -> [i32; 4]
+{
+    let occupied = board.occupancy();
+    let enemy_pawns =
+        board.pieces(Piece { color: color.opposite(), piece: Pawn });
+    let area = !board.color_occupancy(color)
+        & !pawn_attacks_bb(color.opposite(), enemy_pawns);
+    let mut counts = [0; 4];
+
+    for (i, piece) in [Knight, Bishop, Rook, Queen].iter().enumerate() {
+        for square in squares(board.pieces(Piece { color, piece: *piece })) {
+            let attacks = piece_attacks(*piece, square, occupied);
+            counts[i] +=
+                (attacks & area).count_ones() as i32 - MOBILITY_BASELINE[i];
+        }
+    }
+
+    counts
+}
+
+/// Returns how many enemy knights, bishops, rooks and queens attack the zone
+/// around a side's king, and the percentage of their weights that applies.
+fn king_attackers(board: &BitBoardState, color: Color) -> ([i32; 4], i32)
+{
+    let king = board.king_square(color);
+    let zone = KING_ATTACKS[king] | 1 << king;
+    let occupied = board.occupancy();
+    let enemy = color.opposite();
+
+    let mut counts = [0; 4];
+    for (i, piece) in [Knight, Bishop, Rook, Queen].iter().enumerate() {
+        for square in
+            squares(board.pieces(Piece { color: enemy, piece: *piece }))
+        {
+            if piece_attacks(*piece, square, occupied) & zone != 0 {
+                counts[i] += 1;
+            }
+        }
+    }
+
+    let attackers = counts.iter().sum::<i32>() as usize;
+    (counts, ATTACK_SCALE[attackers.min(ATTACK_SCALE.len() - 1)])
+}
+
+/// Returns how many of a side's rooks stand on open and half-open files.
+fn rook_files(board: &BitBoardState, color: Color) -> (i32, i32)
+{
+    let own_pawns = board.pieces(Piece { color, piece: Pawn });
+    let all_pawns = board.pieces_of_type(Pawn);
+    let (mut open, mut semi_open) = (0, 0);
+
+    for square in squares(board.pieces(Piece { color, piece: Rook })) {
+        let file = file_mask(square % 8);
+        if all_pawns & file == 0 {
+            open += 1;
+        }
+        else if own_pawns & file == 0 {
+            semi_open += 1;
+        }
+    }
+
+    (open, semi_open)
+}
+
+/// Returns how many times each weight counts towards a position's evaluation
+/// from white's perspective, before tapering. Weights are indexed as in
+/// `EvalParams::to_vec`, and those that don't count are left out.
+pub fn coefficients(board: &BitBoardState) -> Vec<(usize, f64)>
+{
+    let mut dense = vec![0.0; PARAM_COUNT];
+
+    for (color, sign) in [(White, 1.0), (Black, -1.0)] {
+        for piece in [Pawn, Knight, Bishop, Rook, Queen, King] {
+            let index = piece_index(piece);
+            for square in squares(board.pieces(Piece { color, piece })) {
+                let square = match color {
+                    White => square ^ 56,
+                    Black => square,
+                };
+                dense[MATERIAL + index] += sign;
+                dense[PIECE_SQUARES + 64 * index + square] += sign;
+            }
+        }
+
+        let mobility = mobility_counts(board, color);
+        let (attackers, scale) = king_attackers(board, color);
+        for i in 0..4 {
+            dense[MOBILITY + i] += sign * mobility[i] as f64;
+            dense[KING_ATTACK + i] -=
+                sign * (attackers[i] * scale) as f64 / 100.0;
+        }
+
+        let pawns = PawnCounts::new(board, color);
+        for rank in 0..8 {
+            dense[PASSED_PAWN + rank] += sign * pawns.passed[rank] as f64;
+        }
+        dense[ISOLATED_PAWN] += sign * pawns.isolated as f64;
+        dense[DOUBLED_PAWN] += sign * pawns.doubled as f64;
+        dense[BACKWARD_PAWN] += sign * pawns.backward as f64;
+
+        let (open, semi_open) = rook_files(board, color);
+        dense[ROOK_OPEN_FILE] += sign * open as f64;
+        dense[ROOK_SEMI_OPEN] += sign * semi_open as f64;
+        if board.pieces(Piece { color, piece: Bishop }).count_ones() >= 2 {
+            dense[BISHOP_PAIR] += sign;
+        }
+    }
+
+    dense
+        .into_iter()
+        .enumerate()
+        .filter(|&(_, coefficient)| coefficient != 0.0)
+        .collect()
+}
+
+impl HandcraftedEvaluator
+{
+    pub fn new(params: EvalParams) -> Self { HandcraftedEvaluator { params } }
+
+    fn material(&self, board: &BitBoardState, color: Color) -> Score
+    {
+        [Pawn, Knight, Bishop, Rook, Queen].iter().fold(
+            Score::default(),
+            |acc, piece| {
+                let count =
+                    board.pieces(Piece { color, piece: *piece }).count_ones();
+                acc + self.params.material[piece_index(*piece)] * count as i32
+            },
+        )
+    }
+
+    fn piece_squares(&self, board: &BitBoardState, color: Color) -> Score
+    {
+        let mut score = Score::default();
+
+        for piece in [Pawn, Knight, Bishop, Rook, Queen, King] {
+            let table = &self.params.piece_squares[piece_index(piece)];
+            for square in squares(board.pieces(Piece { color, piece })) {
+                // the tables are written with a8 first, as seen by white
+                let index = match color {
+                    White => square ^ 56,
+                    Black => square,
+                };
+                score += table[index];
+            }
+        }
+
+        score
+    }
+
+    fn mobility(&self, board: &BitBoardState, color: Color) -> Score
+    {
+        let counts = mobility_counts(board, color);
+        (0..4).fold(Score::default(), |acc, i| {
+            acc + self.params.mobility[i] * counts[i]
+        })
+    }
+
+    fn pawns(&self, board: &BitBoardState, color: Color) -> Score
+    {
+        let counts = PawnCounts::new(board, color);
+        let passed = (0..8).fold(Score::default(), |acc, rank| {
+            acc + self.params.passed_pawn[rank] * counts.passed[rank]
+        });
+
+        passed
+            + self.params.isolated_pawn * counts.isolated
+            + self.params.doubled_pawn * counts.doubled
+            + self.params.backward_pawn * counts.backward
+    }
+
+    fn king_safety(&self, board: &BitBoardState, color: Color) -> Score
+    {
+        let (counts, scale) = king_attackers(board, color);
+        let weight = (0..4).fold(Score::default(), |acc, i| {
+            acc + self.params.king_attack[i] * counts[i]
+        });
+
+        Score::new(-weight.mg * scale / 100, -weight.eg * scale / 100)
+    }
+
+    fn rooks(&self, board: &BitBoardState, color: Color) -> Score
+    {
+        let (open, semi_open) = rook_files(board, color);
+        self.params.rook_open_file * open
+            + self.params.rook_semi_open * semi_open
+    }
+
+    fn bishops(&self, board: &BitBoardState, color: Color) -> Score
+    {
+        match board.pieces(Piece { color, piece: Bishop }).count_ones() >= 2 {
+            true => self.params.bishop_pair,
+            false => Score::default(),
+        }
+    }
+
+    /// Returns a single term for one color, before tapering.
+    pub fn term(&self, term: Term, board: &BitBoardState, color: Color)
+        -> Score
+    {
+        match term {
+            Term::Material => self.material(board, color),
+            Term::PieceSquares => self.piece_squares(board, color),
+            Term::Mobility => self.mobility(board, color),
+            Term::Pawns => self.pawns(board, color),
+            Term::KingSafety => self.king_safety(board, color),
+            Term::Rooks => self.rooks(board, color),
+            Term::Bishops => self.bishops(board, color),
+        }
+    }
+
+    /// Returns the sum of every term from white's perspective, before
+    /// tapering, without keeping the terms apart as a trace does.
+    pub fn total(&self, board: &BitBoardState) -> Score
+    {
+        Term::iter().fold(Score::default(), |acc, term| {
+            acc + self.term(term, board, White) - self.term(term, board, Black)
+        })
+    }
+
+    /// Returns every term for both colors, along with the game phase.
+    pub fn trace(&self, board: &BitBoardState) -> EvalTrace
+    {
+        let terms = Term::iter()
+            .map(|term| {
+                (term, [
+                    self.term(term, board, White),
+                    self.term(term, board, Black),
+                ])
+            })
+            .collect();
+
+        EvalTrace { terms, phase: game_phase(board), turn: board.turn() }
+    }
+}
+
+impl EvalTrace
+{
+    /// Returns the sum of every term, from white's perspective.
+    pub fn total(&self) -> Score
+    {
+        self.terms.iter().fold(Score::default(), |acc, (_, [white, black])| {
+            acc + *white - *black
+        })
+    }
+
+    /// Returns the tapered evaluation in centipawns, from white's perspective.
+    pub fn eval(&self) -> i32 { self.total().taper(self.phase) }
+}
+
+/// Returns the breakdown of the default evaluation of a position.
+pub fn eval_trace(board: &BitBoardState) -> EvalTrace
+{
+    HandcraftedEvaluator::default().trace(board)
+}
+
+impl Evaluator for HandcraftedEvaluator
+{
+    fn evaluate(&self, board: &BitBoardState) -> i32
+    {
+        let eval = self.total(board).taper(game_phase(board));
+
+        match board.turn() {
+            White => eval,
+            Black => -eval,
+        }
+    }
+}
+
+impl fmt::Display for Term
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        use Term::*;
+
+        write!(f, "{}", match self {
+            Material => "Material",
+            PieceSquares => "Piece squares",
+            Mobility => "Mobility",
+            Pawns => "Pawns",
+            KingSafety => "King safety",
+            Rooks => "Rooks",
+            Bishops => "Bishops",
+        })
+    }
+}
+
+impl fmt::Display for EvalTrace
+{
+    /// Prints the terms as a table, in pawns.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let pawns = |cp: i32| format!("{:6.2}", cp as f64 / 100.0);
+        let row = |f: &mut fmt::Formatter<'_>,
+                   name: &str,
+                   white: Score,
+                   black: Score| {
+            let total = white - black;
+            writeln!(
+                f,
+                "{:>13} | {} {} | {} {} | {} {}",
+                name,
+                pawns(white.mg),
+                pawns(white.eg),
+                pawns(black.mg),
+                pawns(black.eg),
+                pawns(total.mg),
+                pawns(total.eg)
+            )
+        };
+
+        writeln!(
+            f,
+            "         Term |     White     |     Black     |     Total"
+        )?;
+        writeln!(
+            f,
+            "              |   MG     EG   |   MG     EG   |   MG     EG"
+        )?;
+        writeln!(
+            f,
+            "--------------+---------------+---------------+--------------"
+        )?;
+        for (term, [white, black]) in self.terms.iter() {
+            row(f, &term.to_string(), *white, *black)?;
+        }
+        writeln!(
+            f,
+            "--------------+---------------+---------------+--------------"
+        )?;
+
+        let (white, black) = self.terms.iter().fold(
+            (Score::default(), Score::default()),
+            |(white, black), (_, [w, b])| (white + *w, black + *b),
+        );
+        row(f, "Total", white, black)?;
+
+        writeln!(f)?;
+        writeln!(f, "Phase: {}/{}", self.phase, MAX_PHASE)?;
+        write!(
+            f,
+            "Final evaluation: {:+.2} (white side)",
+            self.eval() as f64 / 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::board::GameState;
+
+    fn evaluate(fen: &str) -> i32
+    {
+        HandcraftedEvaluator::default()
+            .evaluate(&BitBoardState::from_fen(fen).unwrap())
+    }
+
+    /// Mirrors a position vertically and swaps the colors of every piece.
+    fn flip_fen(fen: &str) -> String
+    {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let swap_case = |s: &str| -> String {
+            s.chars()
+                .map(|c| match c.is_uppercase() {
+                    true => c.to_ascii_lowercase(),
+                    false => c.to_ascii_uppercase(),
+                })
+                .collect()
+        };
+
+        let pieces: Vec<String> =
+            fields[0].split('/').rev().map(swap_case).collect();
+        let turn = if fields[1] == "w" { "b" } else { "w" };
+        let mut castling: Vec<char> = swap_case(fields[2]).chars().collect();
+        castling.sort_unstable();
+        let castling: String = castling.into_iter().collect();
+        let en_passant = match fields[3] {
+            "-" => "-".to_string(),
+            square => {
+                let rank = if &square[1..] == "3" { "6" } else { "3" };
+                format!("{}{}", &square[..1], rank)
+            },
+        };
+
+        format!(
+            "{} {} {} {} {} {}",
+            pieces.join("/"),
+            turn,
+            castling,
+            en_passant,
+            fields[4],
+            fields[5]
+        )
+    }
+
+    #[test]
+    fn test_score_taper()
+    {
+        let score = Score::new(100, 200);
+
+        assert_eq!(score.taper(MAX_PHASE), 100);
+        assert_eq!(score.taper(0), 200);
+        assert_eq!(score.taper(MAX_PHASE / 2), 150);
+        assert_eq!(score * 2 - Score::new(50, 50), Score::new(150, 350));
+    }
+
+    #[test]
+    fn test_start_position_is_balanced()
+    {
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(evaluate(fen), 0);
+        assert_eq!(
+            game_phase(&BitBoardState::from_fen(fen).unwrap()),
+            MAX_PHASE
+        );
+    }
+
+    #[test]
+    fn test_material_advantage()
+    {
+        // white is missing the queen
+        let fen = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNB1KBNR w KQkq - 0 1";
+        assert!(evaluate(fen) < -700);
+        assert!(evaluate(&fen.replace(" w ", " b ")) > 700);
+    }
+
+    #[test]
+    fn test_pawn_structure()
+    {
+        let evaluator = HandcraftedEvaluator::default();
+        let pawns = |fen: &str, color| {
+            evaluator.pawns(&BitBoardState::from_fen(fen).unwrap(), color)
+        };
+        let params = &evaluator.params;
+
+        // isolated and doubled pawns on the a-file
+        let fen = "4k3/8/8/8/8/P7/P7/4K3 w - - 0 1";
+        assert_eq!(
+            pawns(fen, White),
+            params.doubled_pawn
+                + params.isolated_pawn * 2
+                + params.passed_pawn[1]
+                + params.passed_pawn[2]
+        );
+
+        // the d-pawn cannot advance past the black c-pawn
+        let fen = "4k3/8/8/2p5/4P3/3P4/8/4K3 w - - 0 1";
+        assert_eq!(
+            pawns(fen, White),
+            params.backward_pawn + params.passed_pawn[3]
+        );
+    }
+
+    #[test]
+    fn test_eval_trace()
+    {
+        let fen =
+            "r1bq1rk1/pp2ppbp/2np1np1/8/3NP3/2N1BP2/PPPQ2PP/R3KB1R b KQ - 0 9";
+        let board = BitBoardState::from_fen(fen).unwrap();
+        let trace = eval_trace(&board);
+
+        assert_eq!(trace.terms.len(), Term::iter().count());
+        assert_eq!(
+            trace.eval(),
+            -HandcraftedEvaluator::default().evaluate(&board)
+        );
+
+        let table = trace.to_string();
+        for term in Term::iter() {
+            assert!(table.contains(&term.to_string()));
+        }
+        assert!(table.contains("Final evaluation"));
+    }
+
+    #[test]
+    fn test_evaluation_is_symmetric()
+    {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - \
+             0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r1bq1rk1/pp2ppbp/2np1np1/8/3NP3/2N1BP2/PPPQ2PP/R3KB1R w KQ - 0 9",
+            "6k1/5ppp/8/8/8/8/1B3PPP/1B4K1 b - - 0 1",
+        ];
+
+        for fen in fens.iter() {
+            assert_eq!(evaluate(fen), evaluate(&flip_fen(fen)), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_param_layout()
+    {
+        let params = EvalParams::default();
+        let weights = params.to_vec();
+        assert_eq!(weights.len(), PARAM_COUNT);
+        assert_eq!(
+            PARAM_LAYOUT.iter().map(|(_, len)| len).sum::<usize>(),
+            PARAM_COUNT
+        );
+        assert_eq!(weights[KING_ATTACK + 3], params.king_attack[3]);
+        assert_eq!(weights[BISHOP_PAIR], params.bishop_pair);
+        assert_eq!(EvalParams::from_slice(&weights).unwrap(), params);
+        assert!(EvalParams::from_slice(&weights[1..]).is_err());
+
+        let text = params.to_string();
+        assert!(text.contains("\nmaterial\n    100/120 320/300"));
+        assert!(text.ends_with("bishop_pair\n    30/50\n"));
+    }
+
+    #[test]
+    fn test_coefficients()
+    {
+        let weights = EvalParams::default().to_vec();
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - \
+             0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r1bq1rk1/pp2ppbp/2np1np1/8/3NP3/2N1BP2/PPPQ2PP/R3KB1R b KQ - 0 9",
+        ];
+
+        for fen in fens.iter() {
+            let board = BitBoardState::from_fen(fen).unwrap();
+            let (mg, eg) = coefficients(&board).into_iter().fold(
+                (0.0, 0.0),
+                |(mg, eg), (i, coefficient)| {
+                    (
+                        mg + coefficient * weights[i].mg as f64,
+                        eg + coefficient * weights[i].eg as f64,
+                    )
+                },
+            );
+
+            // king safety is rounded once per side rather than per weight
+            let total = eval_trace(&board).total();
+            assert!((mg - total.mg as f64).abs() < 2.0, "{}", fen);
+            assert!((eg - total.eg as f64).abs() < 2.0, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_param_file()
+    {
+        let params = EvalParams::default();
+        assert_eq!(params.to_string().parse::<EvalParams>().unwrap(), params);
+
+        // parameters left out keep their defaults
+        let text = "# a bolder personality\nbishop_pair 50/70\nking_attack \
+                    30/0 30/0 # minor pieces\n    60/0 120/0\n";
+        let parsed: EvalParams = text.parse().unwrap();
+        assert_eq!(parsed.bishop_pair, Score::new(50, 70));
+        assert_eq!(parsed.king_attack[3], Score::new(120, 0));
+        assert_eq!(parsed.material, params.material);
+
+        for text in [
+            "mobility 1/1 2/2 3/3",
+            "mobility 1/1 2/2 3/3 4/4 5/5",
+            "bishop_pair 1/x",
+            "bishop_pairs 1/1",
+            "1/1 bishop_pair 1/1",
+            "bishop_pair 1/1 bishop_pair 2/2",
+        ] {
+            assert!(text.parse::<EvalParams>().is_err(), "{}", text);
+        }
+    }
+}
@@ -1,19 +1,55 @@
+pub mod annotate;
+mod attacks;
 mod bitboard;
-mod board;
+pub mod board;
+pub mod book;
 mod display;
-mod piece;
+pub mod engine;
+pub mod eval;
+pub mod movegen;
+mod movepick;
+pub mod moves;
+pub mod nnue;
+pub mod options;
+pub mod pgn;
+pub mod piece;
+pub mod search;
+mod see;
+pub mod selfplay;
+pub mod spsa;
+pub mod strength;
+pub mod tablebase;
+#[cfg(test)]
+mod test_util;
+pub mod time;
+pub mod tournament;
+pub mod tt;
+pub mod tune;
+pub mod uci;
+pub mod uci_client;
+pub mod wdl;
+pub mod xboard;
+mod zobrist;
 
+use std::io::BufRead;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 
 use board::{BitBoardState, GameState};
 use display::*;
+use tablebase::{Material, Tablebase};
+use uci::Uci;
+use xboard::Xboard;
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Square
 {
     file: u8,
     rank: u8,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct CastleAvailability
 {
     white: (bool, bool),
@@ -21,6 +57,21 @@ pub struct CastleAvailability
 }
 
 
+impl Square
+{
+    /// Returns the square for a bitboard index, counting from a1 = 0.
+    pub fn from_index(index: usize) -> Self
+    {
+        Square { file: (index % 8) as u8 + 1, rank: (index / 8) as u8 + 1 }
+    }
+
+    /// Returns the bitboard index of the square, counting from a1 = 0.
+    pub fn index(&self) -> usize
+    {
+        (self.rank as usize - 1) * 8 + self.file as usize - 1
+    }
+}
+
 impl FromStr for Square
 {
     type Err = Box<dyn std::error::Error>;
@@ -31,9 +82,13 @@ impl FromStr for Square
             return Err(format!("invalid square {}!", s).into());
         }
 
-        let mut s = s.chars();
-        let file = s.next().unwrap() as u8 - b'a' + 1;
-        let rank = s.next().unwrap() as u8 - b'1' + 1;
+        let mut chars = s.chars();
+        let file = (chars.next().unwrap() as u8).wrapping_sub(b'a') + 1;
+        let rank = (chars.next().unwrap() as u8).wrapping_sub(b'1') + 1;
+
+        if !(1..=8).contains(&file) || !(1..=8).contains(&rank) {
+            return Err(format!("invalid square {}!", s).into());
+        }
 
         Ok(Square { file, rank })
     }
@@ -52,6 +107,10 @@ impl FromStr for CastleAvailability
         let mut out =
             CastleAvailability { white: (false, false), black: (false, false) };
 
+        if s == "-" {
+            return Ok(out);
+        }
+
         for c in s.chars() {
             let result: Result<(), Self::Err> = match c {
                 'K' => {
@@ -120,6 +179,15 @@ impl std::fmt::Display for CastleAvailability
     }
 }
 
+impl CastleAvailability
+{
+    /// Returns true if either side may still castle.
+    pub fn any(&self) -> bool
+    {
+        self.white.0 || self.white.1 || self.black.0 || self.black.1
+    }
+}
+
 impl Default for CastleAvailability
 {
     fn default() -> Self
@@ -129,10 +197,306 @@ impl Default for CastleAvailability
 }
 
 pub fn run()
+{
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("tablebase") => generate_tablebases(&args[1..]),
+        Some("eval") => print_eval(&args[1..]),
+        Some("board") => print_board(),
+        Some("wdl") => fit_wdl(&args[1..]),
+        Some("tune") => tune(&args[1..]),
+        Some("spsa") => tune_search(&args[1..]),
+        Some("match") => play_match(&args[1..]),
+        Some("annotate") => annotate_pgn(&args[1..]),
+        Some(command) => Err(format!("unknown command {}!", command).into()),
+        None => play(),
+    };
+
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Talks to a GUI on stdin and stdout, speaking XBoard if the first command
+/// asks for it and UCI otherwise.
+fn play() -> Result<(), Box<dyn std::error::Error>>
+{
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let out = Arc::new(Mutex::new(std::io::stdout()));
+
+    let mut first = String::new();
+    input.read_line(&mut first)?;
+
+    if first.trim() == "xboard" {
+        return Xboard::new(out).run(input);
+    }
+
+    let mut uci = Uci::new(out);
+    match uci.handle(first.trim()) {
+        true => uci.run(input),
+        false => Ok(()),
+    }
+}
+
+fn print_board() -> Result<(), Box<dyn std::error::Error>>
 {
     let board = BitBoardState::start_of_game();
 
     println!("{}", convert_to_chess_pieces(&board.to_string()));
+
+    Ok(())
+}
+
+/// Prints the breakdown of the evaluation of a position, given as a FEN or
+/// defaulting to the starting position.
+///
+/// Usage: `bcld eval [fen]`
+fn print_eval(args: &[String]) -> Result<(), Box<dyn std::error::Error>>
+{
+    let board = match args.is_empty() {
+        true => BitBoardState::start_of_game(),
+        false => *BitBoardState::from_fen(&args.join(" "))?,
+    };
+
+    println!("{}", eval::eval_trace(&board));
+
+    Ok(())
+}
+
+/// Fits the win/draw/loss model to the finished games in some PGN files,
+/// printing its parameters.
+///
+/// Usage: `bcld wdl <pgn>...`
+fn fit_wdl(args: &[String]) -> Result<(), Box<dyn std::error::Error>>
+{
+    if args.is_empty() {
+        return Err("usage: bcld wdl <pgn>...".into());
+    }
+
+    let evaluator = eval::HandcraftedEvaluator::default();
+    let mut samples = Vec::new();
+    for path in args {
+        for game in pgn::parse_pgn(&std::fs::read_to_string(path)?)? {
+            samples.extend(wdl::samples(&game, &evaluator));
+        }
+    }
+
+    println!("fitting to {} positions", samples.len());
+    println!("{}", wdl::WdlModel::fit(&samples)?);
+
+    Ok(())
+}
+
+/// Tunes the handcrafted evaluation weights to a file of quiet positions
+/// labelled with their game results, writing the weights to a file every
+/// hundred epochs and at the end.
+///
+/// Usage: `bcld tune <positions> <output> [epochs]`
+fn tune(args: &[String]) -> Result<(), Box<dyn std::error::Error>>
+{
+    let usage = "usage: bcld tune <positions> <output> [epochs]";
+    let (input, output) = match args {
+        [input, output] | [input, output, _] => (input, output),
+        _ => return Err(usage.into()),
+    };
+    let epochs = match args.get(2) {
+        Some(epochs) => epochs.parse::<u32>()?,
+        None => 1000,
+    };
+    let threads =
+        std::thread::available_parallelism().map_or(1, |threads| threads.get());
+
+    let positions =
+        tune::parse_positions(&std::fs::read_to_string(input)?, threads)?;
+    let mut tuner =
+        tune::Tuner::new(positions, &eval::EvalParams::default(), threads);
+    if tuner.is_empty() {
+        return Err(format!("no positions in {}!", input).into());
+    }
+    println!("tuning to {} positions on {} threads", tuner.len(), threads);
+
+    let k = tuner.fit_k();
+    println!("k {:.4} error {:.6}", k, tuner.error());
+
+    for epoch in 1..=epochs {
+        let error = tuner.step();
+        if epoch % 100 == 0 || epoch == epochs {
+            println!("epoch {} error {:.6}", epoch, error);
+            std::fs::write(output, tuner.params().to_string())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Tunes the search parameters by SPSA over batches of self-play games,
+/// resuming from the checkpoint file if it exists and saving to it after
+/// every iteration.
+///
+/// Usage: `bcld spsa <checkpoint> [iterations] [games] [nodes]`
+fn tune_search(args: &[String]) -> Result<(), Box<dyn std::error::Error>>
+{
+    let path = args
+        .first()
+        .ok_or("usage: bcld spsa <checkpoint> [iterations] [games] [nodes]")?;
+    let number = |i: usize, default: u64| match args.get(i) {
+        Some(value) => value.parse::<u64>(),
+        None => Ok(default),
+    };
+    let (iterations, games, nodes) =
+        (number(1, 1000)?, number(2, 32)?, number(3, 5000)?);
+    if nodes < spsa::MIN_NODES {
+        let error = format!("spsa needs at least {} nodes!", spsa::MIN_NODES);
+        return Err(error.into());
+    }
+    let threads =
+        std::thread::available_parallelism().map_or(1, |threads| threads.get());
+
+    let mut spsa = match Path::new(path).exists() {
+        true => std::fs::read_to_string(path)?.parse()?,
+        false => spsa::Spsa::default(),
+    };
+
+    while (spsa.iteration as u64) < iterations {
+        let batch = spsa.step(games as usize, nodes, threads);
+        println!(
+            "iteration {} +{} -{} ={}",
+            spsa.iteration, batch.wins, batch.losses, batch.draws
+        );
+        std::fs::write(path, spsa.to_string())?;
+    }
+    print!("{}", spsa);
+
+    Ok(())
+}
+
+/// Plays a match between two engines, printing the results after every game.
+///
+/// Usage: `bcld match --engine [name=NAME] [cmd=COMMAND] [OPTION=VALUE]...
+/// --engine ... [--tc BASE+INC] [--openings FILE] [--rounds N]
+/// [--concurrency N] [--sprt ELO0 ELO1 [ALPHA BETA]] [--resign CP MOVES]
+/// [--draw CP MOVES [MOVENUMBER]] [--tb DIR] [--pgnout FILE]`
+fn play_match(args: &[String]) -> Result<(), Box<dyn std::error::Error>>
+{
+    let config = tournament::MatchConfig::parse(args)?;
+    let sprt = config.sprt;
+
+    // games an engine failed to start aren't in the stats, but are listed
+    let mut played = 0;
+    let stats = config.run(&mut |game, stats| {
+        played += 1;
+        println!(
+            "game {} {} - {} {} ({})",
+            played,
+            game.tag("White").unwrap_or("?"),
+            game.tag("Black").unwrap_or("?"),
+            game.result,
+            game.tag("Termination").unwrap_or("?")
+        );
+        println!("score {}", stats);
+        if let Some(sprt) = sprt {
+            let (lower, upper) = sprt.bounds();
+            println!(
+                "llr {:.2} ({:.2}, {:.2}) [{}, {}]",
+                stats.llr(&sprt),
+                lower,
+                upper,
+                sprt.elo0,
+                sprt.elo1
+            );
+        }
+    })?;
+
+    if let Some(sprt) = sprt {
+        let (lower, upper) = sprt.bounds();
+        match stats.llr(&sprt) {
+            llr if llr >= upper => println!("sprt: H1 accepted"),
+            llr if llr <= lower => println!("sprt: H0 accepted"),
+            _ => println!("sprt: inconclusive"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Annotates every game in a PGN file, searching each position to a depth or
+/// for a time, and writes them to another.
+///
+/// Usage: `bcld annotate <input> <output> [--depth N] [--movetime MS]
+/// [--threads N]`
+fn annotate_pgn(args: &[String]) -> Result<(), Box<dyn std::error::Error>>
+{
+    let usage = "usage: bcld annotate <input> <output> [--depth N] \
+                 [--movetime MS] [--threads N]";
+    let (input, output) = match args {
+        [input, output, ..] => (input, output),
+        _ => return Err(usage.into()),
+    };
+    let mut limits = search::SearchLimits::default();
+    let mut threads =
+        std::thread::available_parallelism().map_or(1, |threads| threads.get());
+
+    for flag in args[2..].chunks(2) {
+        match flag {
+            [name, value] if name == "--depth" =>
+                limits.depth = Some(value.parse()?),
+            [name, value] if name == "--movetime" =>
+                limits.movetime =
+                    Some(std::time::Duration::from_millis(value.parse()?)),
+            [name, value] if name == "--threads" => threads = value.parse()?,
+            _ => return Err(usage.into()),
+        }
+    }
+    if limits.depth.is_none() && limits.movetime.is_none() {
+        limits.depth = Some(12);
+    }
+    let overhead = time::DEFAULT_MOVE_OVERHEAD;
+    if limits.movetime.is_some_and(|movetime| movetime <= overhead) {
+        let error = format!(
+            "movetime must be more than the move overhead of {}ms!",
+            overhead.as_millis()
+        );
+        return Err(error.into());
+    }
+
+    let games = pgn::parse_pgn(&std::fs::read_to_string(input)?)?;
+    println!("annotating {} games on {} threads", games.len(), threads);
+    let annotated = annotate::annotate_games(&games, &limits, threads);
+
+    let text: Vec<String> =
+        annotated.iter().map(|game| game.to_string()).collect();
+    std::fs::write(output, text.join("\n"))?;
+    Ok(())
+}
+
+/// Generates tables for each given material signature, writing them along with
+/// their dependencies to a directory.
+///
+/// Usage: `bcld tablebase <dir> <material>...`
+fn generate_tablebases(
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error>>
+{
+    let (dir, materials) = args
+        .split_first()
+        .ok_or("usage: bcld tablebase <dir> <material>...")?;
+    let dir = Path::new(dir);
+
+    let mut tablebase = Tablebase::new();
+    if dir.is_dir() {
+        tablebase.load(dir)?;
+    }
+
+    for material in materials {
+        let material = Material::from_str(material)?;
+        tablebase.generate(&material);
+        println!("generated {}", material.canonical());
+    }
+
+    tablebase.save(dir)
 }
 
 #[cfg(test)]
@@ -145,7 +509,7 @@ mod tests
     #[test]
     fn test_castle_availability_from_string()
     {
-        let tests = vec!["KQkq", "Kkq", "kq", "q"];
+        let tests = vec!["KQkq", "Kkq", "kq", "q", "-"];
 
         for test in tests {
             assert_eq!(
@@ -168,6 +532,15 @@ mod tests
         assert_eq!(Square { file: 1, rank: 1 }.to_string(), "a1");
     }
 
+    #[test]
+    fn test_square_index()
+    {
+        assert_eq!(Square::from_str("a1").unwrap().index(), 0);
+        assert_eq!(Square::from_str("e4").unwrap().index(), 28);
+        assert_eq!(Square::from_str("h8").unwrap().index(), 63);
+        assert_eq!(Square::from_index(28).to_string(), "e4");
+    }
+
     #[test]
     fn test_square_from_string()
     {
@@ -178,6 +551,8 @@ mod tests
         }
 
         assert!(Square::from_str("invalid").is_err());
-        assert!(Square::from_str("").is_err())
+        assert!(Square::from_str("").is_err());
+        assert!(Square::from_str("i1").is_err());
+        assert!(Square::from_str("a9").is_err())
     }
 }
@@ -0,0 +1,380 @@
+use crate::attacks::*;
+use crate::board::BitBoardState;
+use crate::moves::Move;
+use crate::piece::Color::*;
+use crate::piece::Piece;
+use crate::piece::PieceType::{self, *};
+
+/// Which moves to generate.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MoveKind
+{
+    /// Captures and promotions.
+    Noisy,
+    /// Every other move, castling included.
+    Quiet,
+    All,
+}
+
+const PROMOTIONS: [PieceType; 4] = [Queen, Knight, Rook, Bishop];
+
+impl BitBoardState
+{
+    /// Appends the pseudo-legal moves of the given kind, which may still leave
+    /// the king in check.
+    pub fn generate_moves(&self, kind: MoveKind, moves: &mut Vec<Move>)
+    {
+        let us = self.turn();
+        let them = us.opposite();
+        let own = self.color_occupancy(us);
+        let enemy = self.color_occupancy(them);
+        let occupied = self.occupancy();
+
+        let targets = match kind {
+            MoveKind::Noisy => enemy,
+            MoveKind::Quiet => !occupied,
+            MoveKind::All => !own,
+        };
+
+        self.generate_pawn_moves(kind, moves);
+
+        for piece in [Knight, Bishop, Rook, Queen, King] {
+            let mut pieces = self.pieces(Piece { color: us, piece });
+            while pieces != 0 {
+                let from = pieces.trailing_zeros() as usize;
+                pieces &= pieces - 1;
+
+                let attacks = match piece {
+                    Knight => KNIGHT_ATTACKS[from],
+                    Bishop => bishop_attacks(from, occupied),
+                    Rook => rook_attacks(from, occupied),
+                    Queen => queen_attacks(from, occupied),
+                    _ => KING_ATTACKS[from],
+                };
+                push_moves(from, attacks & targets, moves);
+            }
+        }
+
+        if kind != MoveKind::Noisy {
+            self.generate_castling(moves);
+        }
+    }
+
+    fn generate_pawn_moves(&self, kind: MoveKind, moves: &mut Vec<Move>)
+    {
+        let us = self.turn();
+        let empty = !self.occupancy();
+        let enemy = self.color_occupancy(us.opposite());
+        let pawns = self.pieces(Piece { color: us, piece: Pawn });
+
+        let (forward, start_rank, last_rank): (i32, u64, u64) = match us {
+            White => (8, 0xff << 8, 0xff << 56),
+            Black => (-8, 0xff << 48, 0xff),
+        };
+        let shift =
+            |bits: u64, by: i32| if by > 0 { bits << by } else { bits >> -by };
+
+        let single = shift(pawns, forward) & empty;
+        let double =
+            shift(shift(pawns & start_rank, forward) & empty, forward) & empty;
+
+        let noisy = kind != MoveKind::Quiet;
+        let quiet = kind != MoveKind::Noisy;
+
+        let mut add =
+            |mut targets: u64, offset: i32, promotions_only_if_noisy: bool| {
+                while targets != 0 {
+                    let to = targets.trailing_zeros() as i32;
+                    targets &= targets - 1;
+                    let from = (to - offset) as u8;
+
+                    if last_rank & 1 << to != 0 {
+                        if noisy {
+                            for promotion in PROMOTIONS {
+                                moves.push(Move::with_promotion(
+                                    from, to as u8, promotion,
+                                ));
+                            }
+                        }
+                    }
+                    else if !promotions_only_if_noisy || quiet {
+                        moves.push(Move::new(from, to as u8));
+                    }
+                }
+            };
+
+        // pushes are quiet unless they promote
+        add(single, forward, true);
+        if quiet {
+            add(double, 2 * forward, true);
+        }
+
+        if noisy {
+            let mut targets = enemy;
+            if let Some(square) = self.en_passant_target() {
+                targets |= 1 << square.index();
+            }
+            let left = shift(pawns & !FILE_A, forward - 1) & targets;
+            let right = shift(pawns & !FILE_H, forward + 1) & targets;
+            add(left, forward - 1, false);
+            add(right, forward + 1, false);
+        }
+    }
+
+    fn generate_castling(&self, moves: &mut Vec<Move>)
+    {
+        let us = self.turn();
+        let castling = self.castle_availability();
+        let (rights, king, rank) = match us {
+            White => (castling.white, 4, 0),
+            Black => (castling.black, 60, 56),
+        };
+        let occupied = self.occupancy();
+        let rook = Piece { color: us, piece: Rook };
+
+        if self.piece_at(king) != Some(Piece { color: us, piece: King })
+            || self.in_check()
+        {
+            return;
+        }
+
+        // the king may not pass through an attacked square
+        let safe = |square: usize| {
+            self.attackers_to(square, occupied)
+                & self.color_occupancy(us.opposite())
+                == 0
+        };
+
+        if rights.0
+            && self.piece_at(rank + 7) == Some(rook)
+            && occupied & (0b0110_0000 << rank) == 0
+            && safe(rank + 5)
+        {
+            moves.push(Move::new(king as u8, rank as u8 + 6));
+        }
+        if rights.1
+            && self.piece_at(rank) == Some(rook)
+            && occupied & (0b0000_1110 << rank) == 0
+            && safe(rank + 3)
+        {
+            moves.push(Move::new(king as u8, rank as u8 + 2));
+        }
+    }
+
+    /// Returns true if a pseudo-legal move does not leave the mover's king in
+    /// check. The destination of castling moves is checked when playing them.
+    pub fn is_legal(&self, mv: Move) -> bool
+    {
+        let us = self.turn();
+        let from = mv.from as usize;
+        let to = mv.to as usize;
+        let piece = match self.piece_at(from) {
+            Some(piece) => piece,
+            None => return false,
+        };
+
+        let mut occupied = (self.occupancy() ^ 1 << from) | 1 << to;
+        let mut removed = 1 << to;
+        if piece.piece == Pawn
+            && from % 8 != to % 8
+            && self.piece_at(to).is_none()
+        {
+            let captured = if us == White { to - 8 } else { to + 8 };
+            occupied ^= 1 << captured;
+            removed |= 1 << captured;
+        }
+
+        let king = if piece.piece == King { to } else { self.king_square(us) };
+        let enemy = self.color_occupancy(us.opposite()) & !removed;
+
+        self.attackers_to(king, occupied) & enemy == 0
+    }
+
+    /// Returns true if a move, perhaps taken from another position, can be
+    /// played in this one.
+    pub fn is_pseudo_legal(&self, mv: Move) -> bool
+    {
+        let piece = match self.piece_at(mv.from as usize) {
+            Some(piece) if piece.color == self.turn() => piece,
+            _ => return false,
+        };
+        if self.piece_at(mv.to as usize).is_some_and(|p| p.color == piece.color)
+        {
+            return false;
+        }
+
+        let kind = match self.piece_at(mv.to as usize).is_some()
+            || mv.promotion.is_some()
+        {
+            true => MoveKind::Noisy,
+            false if piece.piece == Pawn && mv.from % 8 != mv.to % 8 =>
+                MoveKind::Noisy,
+            false => MoveKind::Quiet,
+        };
+
+        let mut moves = Vec::new();
+        self.generate_moves(kind, &mut moves);
+        moves.contains(&mv)
+    }
+
+    /// Returns every legal move in the position.
+    pub fn legal_moves(&self) -> Vec<Move>
+    {
+        let mut moves = Vec::with_capacity(64);
+        self.generate_moves(MoveKind::All, &mut moves);
+        moves.retain(|mv| self.is_legal(*mv));
+
+        moves
+    }
+
+    /// Returns true if a move captures a piece or promotes.
+    pub fn is_noisy(&self, mv: Move) -> bool
+    {
+        mv.promotion.is_some()
+            || self.piece_at(mv.to as usize).is_some()
+            || self.piece_at(mv.from as usize).is_some_and(|p| p.piece == Pawn)
+                && mv.from % 8 != mv.to % 8
+    }
+
+    /// Counts the leaf nodes of the legal move tree to the given depth.
+    pub fn perft(&mut self, depth: u32) -> u64
+    {
+        let moves = self.legal_moves();
+        if depth <= 1 {
+            return if depth == 1 { moves.len() as u64 } else { 1 };
+        }
+
+        moves
+            .into_iter()
+            .map(|mv| {
+                self.make_move(mv);
+                let nodes = self.perft(depth - 1);
+                self.unmake_move();
+                nodes
+            })
+            .sum()
+    }
+}
+
+fn push_moves(from: usize, mut targets: u64, moves: &mut Vec<Move>)
+{
+    while targets != 0 {
+        let to = targets.trailing_zeros() as u8;
+        targets &= targets - 1;
+        moves.push(Move::new(from as u8, to));
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::MoveKind;
+    use crate::board::{BitBoardState, GameState};
+
+    fn perft(fen: &str, depth: u32) -> u64
+    {
+        BitBoardState::from_fen(fen).unwrap().perft(depth)
+    }
+
+    #[test]
+    fn test_perft_start_position()
+    {
+        let mut board = BitBoardState::start_of_game();
+        let counts = [20, 400, 8902, 197281];
+
+        for (depth, count) in counts.iter().enumerate() {
+            assert_eq!(board.perft(depth as u32 + 1), *count);
+        }
+    }
+
+    #[test]
+    fn test_perft_tricky_positions()
+    {
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/\
+                        R3K2R w KQkq - 0 1";
+        assert_eq!(perft(kiwipete, 1), 48);
+        assert_eq!(perft(kiwipete, 2), 2039);
+        assert_eq!(perft(kiwipete, 3), 97862);
+
+        let endgame = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+        assert_eq!(perft(endgame, 4), 43238);
+        assert_eq!(perft(endgame, 5), 674624);
+
+        let promotions =
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+        assert_eq!(perft(promotions, 3), 9467);
+
+        let checks =
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+        assert_eq!(perft(checks, 3), 62379);
+    }
+
+    #[test]
+    fn test_move_kinds_partition_all_moves()
+    {
+        let board = BitBoardState::from_fen(
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 b kq - 0 1",
+        )
+        .unwrap();
+
+        let mut noisy = Vec::new();
+        let mut quiet = Vec::new();
+        let mut all = Vec::new();
+        board.generate_moves(MoveKind::Noisy, &mut noisy);
+        board.generate_moves(MoveKind::Quiet, &mut quiet);
+        board.generate_moves(MoveKind::All, &mut all);
+
+        assert_eq!(noisy.len() + quiet.len(), all.len());
+        assert!(noisy.iter().all(|mv| board.is_noisy(*mv)));
+        assert!(quiet.iter().all(|mv| !board.is_noisy(*mv)));
+        assert!(all.iter().all(|mv| board.is_pseudo_legal(*mv)));
+    }
+
+    #[test]
+    fn test_make_and_unmake_restore_position()
+    {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w \
+                   KQkq - 0 1";
+        let mut board = BitBoardState::from_fen(fen).unwrap();
+        let hash = board.hash();
+
+        for mv in board.legal_moves() {
+            board.make_move(mv);
+            let fresh = BitBoardState::from_fen(&board.as_fen()).unwrap();
+            assert_eq!(board.hash(), fresh.hash(), "{}", mv);
+            board.unmake_move();
+
+            assert_eq!(board.as_fen(), fen);
+            assert_eq!(board.hash(), hash);
+        }
+    }
+
+    #[test]
+    fn test_draws()
+    {
+        let mut board = BitBoardState::start_of_game();
+        assert!(!board.is_draw());
+
+        for mv in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            board.make_move(mv.parse().unwrap());
+        }
+        assert!(board.is_draw());
+
+        // the rules only call it a draw the third time round
+        assert!(!board.is_draw_by_rule());
+        for mv in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+            board.make_move(mv.parse().unwrap());
+        }
+        assert!(board.is_draw_by_rule());
+
+        assert!(BitBoardState::from_fen("8/8/8/4k3/8/8/8/2BK4 w - - 0 1")
+            .unwrap()
+            .is_draw());
+        assert!(!BitBoardState::from_fen("8/8/8/4k3/8/8/8/2RK4 w - - 0 1")
+            .unwrap()
+            .is_draw());
+        assert!(BitBoardState::from_fen("8/8/8/4k3/8/8/8/2RK4 w - - 100 80")
+            .unwrap()
+            .is_draw_by_rule());
+    }
+}
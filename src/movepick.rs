@@ -0,0 +1,455 @@
+//! Refer to https://www.chessprogramming.org/Move_Ordering
+
+use crate::board::BitBoardState;
+use crate::movegen::MoveKind;
+use crate::moves::Move;
+use crate::piece::{Color, Piece, PieceType};
+use crate::search::MAX_PLY;
+use crate::see::see_value;
+
+/// The most any history score can reach.
+const HISTORY_MAX: i32 = 16384;
+/// Piece kinds times squares.
+const PIECE_SQUARES: usize = 12 * 64;
+
+/// Returns an index for a piece arriving on a square, which the continuation
+/// histories are keyed by.
+pub fn piece_to(piece: Piece, to: u8) -> usize
+{
+    (piece.color as usize * 6 + piece.piece as usize) * 64 + to as usize
+}
+
+/// What the search has learned about which quiet moves tend to be good.
+pub struct History
+{
+    /// Quiet moves that caused a cutoff, by ply.
+    killers:      Vec<[Option<Move>; 2]>,
+    /// The quiet move that last refuted each move, by its piece and square.
+    countermoves: Vec<Option<Move>>,
+    /// Scores by color, origin and destination.
+    butterfly:    Vec<i16>,
+    /// Scores by the piece and square of a move one or two plies earlier, and
+    /// those of the move itself.
+    continuation: Vec<i16>,
+}
+
+impl Default for History
+{
+    fn default() -> Self
+    {
+        History {
+            killers:      vec![[None; 2]; MAX_PLY + 1],
+            countermoves: vec![None; PIECE_SQUARES],
+            butterfly:    vec![0; 2 * 64 * 64],
+            continuation: vec![0; PIECE_SQUARES * PIECE_SQUARES],
+        }
+    }
+}
+
+impl History
+{
+    pub fn clear(&mut self) { *self = History::default(); }
+
+    pub fn killers(&self, ply: usize) -> [Option<Move>; 2] { self.killers[ply] }
+
+    /// Returns the move that last refuted the previous one.
+    pub fn countermove(&self, previous: Option<usize>) -> Option<Move>
+    {
+        previous.and_then(|previous| self.countermoves[previous])
+    }
+
+    /// Scores a quiet move, given the continuations of the previous moves.
+    pub fn quiet_score(
+        &self, board: &BitBoardState, mv: Move,
+        continuations: [Option<usize>; 2],
+    ) -> i32
+    {
+        let piece = match board.piece_at(mv.from as usize) {
+            Some(piece) => piece,
+            None => return 0,
+        };
+        let current = piece_to(piece, mv.to);
+
+        let mut score = self.butterfly[butterfly_index(piece.color, mv)] as i32;
+        for previous in continuations.iter().flatten() {
+            score +=
+                self.continuation[previous * PIECE_SQUARES + current] as i32;
+        }
+
+        score
+    }
+
+    /// Rewards a quiet move that caused a cutoff and penalizes the quiet
+    /// moves tried before it.
+    pub fn update(
+        &mut self, board: &BitBoardState, ply: usize, best: Move,
+        tried: &[Move], depth: i32, continuations: [Option<usize>; 2],
+    )
+    {
+        if self.killers[ply][0] != Some(best) {
+            self.killers[ply] = [Some(best), self.killers[ply][0]];
+        }
+        if let Some(previous) = continuations[0] {
+            self.countermoves[previous] = Some(best);
+        }
+
+        let bonus = (depth * depth).min(1200);
+        self.reward(board, best, bonus, continuations);
+        for mv in tried {
+            self.reward(board, *mv, -bonus, continuations);
+        }
+    }
+
+    fn reward(
+        &mut self, board: &BitBoardState, mv: Move, bonus: i32,
+        continuations: [Option<usize>; 2],
+    )
+    {
+        let piece = match board.piece_at(mv.from as usize) {
+            Some(piece) => piece,
+            None => return,
+        };
+        let current = piece_to(piece, mv.to);
+
+        // scores move towards the bonus, more slowly the closer they are to
+        // the limit
+        let apply = |entry: &mut i16| {
+            let value = *entry as i32;
+            *entry = (value + bonus - value * bonus.abs() / HISTORY_MAX) as i16;
+        };
+
+        apply(&mut self.butterfly[butterfly_index(piece.color, mv)]);
+        for previous in continuations.iter().flatten() {
+            apply(&mut self.continuation[previous * PIECE_SQUARES + current]);
+        }
+    }
+}
+
+fn butterfly_index(color: Color, mv: Move) -> usize
+{
+    (color as usize * 64 + mv.from as usize) * 64 + mv.to as usize
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Stage
+{
+    TtMove,
+    GenerateNoisy,
+    GoodNoisy,
+    Killer(usize),
+    Countermove,
+    GenerateQuiets,
+    Quiets,
+    BadNoisy,
+    Done,
+}
+
+/// Hands out the moves of a position one at a time, best guesses first,
+/// generating each kind of move only once it is needed. The moves are
+/// pseudo-legal, and need checking before being played.
+///
+/// The order is the move from the transposition table, captures that don't
+/// lose material by most valuable victim and least valuable attacker, the
+/// killer moves, the countermove, the remaining quiet moves by history, and
+/// finally the captures that lose material.
+pub struct MovePicker
+{
+    stage:         Stage,
+    tt_move:       Option<Move>,
+    killers:       [Option<Move>; 2],
+    countermove:   Option<Move>,
+    continuations: [Option<usize>; 2],
+    moves:         Vec<(Move, i32)>,
+    bad_noisy:     Vec<(Move, i32)>,
+    skip_quiets:   bool,
+    quiescence:    bool,
+}
+
+impl MovePicker
+{
+    /// Creates a picker for the main search.
+    pub fn new(
+        board: &BitBoardState, tt_move: Option<Move>,
+        killers: [Option<Move>; 2], countermove: Option<Move>,
+        continuations: [Option<usize>; 2],
+    ) -> Self
+    {
+        MovePicker {
+            stage: Stage::TtMove,
+            tt_move: tt_move.filter(|mv| board.is_pseudo_legal(*mv)),
+            killers,
+            countermove,
+            continuations,
+            moves: Vec::new(),
+            bad_noisy: Vec::new(),
+            skip_quiets: false,
+            quiescence: false,
+        }
+    }
+
+    /// Creates a picker for the quiescence search, which only tries captures
+    /// that don't lose material unless the side to move is in check.
+    pub fn quiescence(
+        board: &BitBoardState, tt_move: Option<Move>, in_check: bool,
+    ) -> Self
+    {
+        let tt_move = tt_move.filter(|mv| {
+            (in_check || board.is_noisy(*mv)) && board.is_pseudo_legal(*mv)
+        });
+
+        MovePicker {
+            stage: Stage::TtMove,
+            tt_move,
+            killers: [None; 2],
+            countermove: None,
+            continuations: [None; 2],
+            moves: Vec::new(),
+            bad_noisy: Vec::new(),
+            skip_quiets: !in_check,
+            quiescence: !in_check,
+        }
+    }
+
+    /// Stops the picker from handing out any more quiet moves.
+    pub fn skip_quiets(&mut self) { self.skip_quiets = true; }
+
+    /// Returns the next move to try, or `None` once every move has been.
+    pub fn next(
+        &mut self, board: &BitBoardState, history: &History,
+    ) -> Option<Move>
+    {
+        loop {
+            match self.stage {
+                Stage::TtMove => {
+                    self.stage = Stage::GenerateNoisy;
+                    if self.tt_move.is_some() {
+                        return self.tt_move;
+                    }
+                },
+                Stage::GenerateNoisy => {
+                    let mut moves = Vec::new();
+                    board.generate_moves(MoveKind::Noisy, &mut moves);
+                    self.moves = moves
+                        .into_iter()
+                        .filter(|mv| Some(*mv) != self.tt_move)
+                        .map(|mv| (mv, mvv_lva(board, mv)))
+                        .collect();
+                    self.stage = Stage::GoodNoisy;
+                },
+                Stage::GoodNoisy => {
+                    while let Some((mv, score)) = pick_best(&mut self.moves) {
+                        let underpromotion =
+                            mv.promotion.is_some_and(|p| p != PieceType::Queen);
+                        if underpromotion || !board.see_ge(mv, 0) {
+                            self.bad_noisy.push((mv, score));
+                        }
+                        else {
+                            return Some(mv);
+                        }
+                    }
+                    self.stage = match self.quiescence {
+                        true => Stage::Done,
+                        false => Stage::Killer(0),
+                    };
+                },
+                Stage::Killer(i) => {
+                    self.stage = match i {
+                        0 => Stage::Killer(1),
+                        _ => Stage::Countermove,
+                    };
+                    let killer = self.killers[i];
+                    if !self.skip_quiets && self.is_refutation(board, killer) {
+                        return killer;
+                    }
+                },
+                Stage::Countermove => {
+                    self.stage = Stage::GenerateQuiets;
+                    let countermove = self.countermove;
+                    if !self.skip_quiets
+                        && !self.killers.contains(&countermove)
+                        && self.is_refutation(board, countermove)
+                    {
+                        return countermove;
+                    }
+                },
+                Stage::GenerateQuiets => {
+                    self.stage = Stage::Quiets;
+                    if self.skip_quiets {
+                        continue;
+                    }
+
+                    let mut moves = Vec::new();
+                    board.generate_moves(MoveKind::Quiet, &mut moves);
+                    self.moves = moves
+                        .into_iter()
+                        .filter(|mv| {
+                            Some(*mv) != self.tt_move
+                                && Some(*mv) != self.countermove
+                                && !self.killers.contains(&Some(*mv))
+                        })
+                        .map(|mv| {
+                            let score = history.quiet_score(
+                                board,
+                                mv,
+                                self.continuations,
+                            );
+                            (mv, score)
+                        })
+                        .collect();
+                },
+                Stage::Quiets => {
+                    if !self.skip_quiets {
+                        if let Some((mv, _)) = pick_best(&mut self.moves) {
+                            return Some(mv);
+                        }
+                    }
+                    self.stage = Stage::BadNoisy;
+                    self.bad_noisy.reverse();
+                },
+                Stage::BadNoisy => match self.bad_noisy.pop() {
+                    Some((mv, _)) => return Some(mv),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+
+    /// Returns true if a killer or countermove hasn't been tried yet and can
+    /// be played as a quiet move here.
+    fn is_refutation(&self, board: &BitBoardState, mv: Option<Move>) -> bool
+    {
+        match mv {
+            Some(mv) =>
+                Some(mv) != self.tt_move
+                    && !board.is_noisy(mv)
+                    && board.is_pseudo_legal(mv),
+            None => false,
+        }
+    }
+}
+
+/// Scores a capture by the value of its victim, breaking ties by the value of
+/// the attacker.
+fn mvv_lva(board: &BitBoardState, mv: Move) -> i32
+{
+    let victim = match board.piece_at(mv.to as usize) {
+        Some(piece) => see_value(piece.piece),
+        None if mv.promotion.is_none() => see_value(PieceType::Pawn),
+        None => 0,
+    };
+    let attacker =
+        board.piece_at(mv.from as usize).map_or(0, |p| p.piece as i32);
+    let promotion = mv.promotion.map_or(0, see_value);
+
+    (victim + promotion) * 8 - attacker
+}
+
+/// Removes and returns the best scoring move, without sorting the others.
+fn pick_best(moves: &mut Vec<(Move, i32)>) -> Option<(Move, i32)>
+{
+    let best = moves
+        .iter()
+        .enumerate()
+        .max_by_key(|(i, (_, score))| (*score, std::cmp::Reverse(*i)))?
+        .0;
+
+    Some(moves.swap_remove(best))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::board::GameState;
+
+    fn picked(picker: &mut MovePicker, board: &BitBoardState) -> Vec<Move>
+    {
+        let history = History::default();
+        std::iter::from_fn(|| picker.next(board, &history)).collect()
+    }
+
+    fn mv(s: &str) -> Move { Move::from_str(s).unwrap() }
+
+    #[test]
+    fn test_picks_every_move_once()
+    {
+        let board = BitBoardState::from_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - \
+             0 1",
+        )
+        .unwrap();
+        let mut picker = MovePicker::new(
+            &board,
+            Some(mv("e2a6")),
+            [Some(mv("a2a3")), Some(mv("d5e6"))],
+            Some(mv("g2h3")),
+            [None; 2],
+        );
+        let moves = picked(&mut picker, &board);
+
+        let mut expected = Vec::new();
+        board.generate_moves(MoveKind::All, &mut expected);
+        assert_eq!(moves.len(), expected.len());
+        assert!(expected.iter().all(|mv| moves.contains(mv)));
+
+        // the killer that captures is picked with the other captures
+        assert_eq!(moves[0], mv("e2a6"));
+        assert_eq!(moves.iter().filter(|m| **m == mv("d5e6")).count(), 1);
+    }
+
+    #[test]
+    fn test_stage_order()
+    {
+        let board =
+            BitBoardState::from_fen("4k3/2p5/3n4/6q1/8/3R1N2/8/4K3 w - - 0 1")
+                .unwrap();
+        let mut picker = MovePicker::new(
+            &board,
+            None,
+            [Some(mv("e1f2")), Some(mv("h7h8"))],
+            Some(mv("f3d4")),
+            [None; 2],
+        );
+        let moves = picked(&mut picker, &board);
+
+        assert_eq!(&moves[..3], &[mv("f3g5"), mv("e1f2"), mv("f3d4")]);
+        // the knight is defended by a pawn, so taking it loses material
+        assert_eq!(moves.last(), Some(&mv("d3d6")));
+    }
+
+    #[test]
+    fn test_quiescence_skips_quiets_and_bad_captures()
+    {
+        let board =
+            BitBoardState::from_fen("4k3/8/8/2p1q1p1/3P3p/5N2/8/6K1 w - - 0 1")
+                .unwrap();
+        let mut picker =
+            MovePicker::quiescence(&board, Some(mv("f3e5")), false);
+
+        assert_eq!(picked(&mut picker, &board), vec![
+            mv("f3e5"),
+            mv("d4e5"),
+            mv("d4c5")
+        ]);
+    }
+
+    #[test]
+    fn test_history_orders_quiets()
+    {
+        let board = BitBoardState::start_of_game();
+        let mut history = History::default();
+        history.update(&board, 3, mv("g1f3"), &[mv("a2a3")], 6, [None; 2]);
+
+        assert_eq!(history.killers(3), [Some(mv("g1f3")), None]);
+        assert!(history.quiet_score(&board, mv("g1f3"), [None; 2]) > 0);
+        assert!(history.quiet_score(&board, mv("a2a3"), [None; 2]) < 0);
+
+        let mut picker =
+            MovePicker::new(&board, None, [None; 2], None, [None; 2]);
+        let first = picker.next(&board, &history);
+        assert_eq!(first, Some(mv("g1f3")));
+    }
+}
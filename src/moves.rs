@@ -0,0 +1,101 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::piece::{Color, Piece, PieceType};
+use crate::Square;
+
+/// A single move, stored as its origin and destination squares (counting from
+/// a1 = 0) and an optional promotion.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Move
+{
+    pub from:      u8,
+    pub to:        u8,
+    pub promotion: Option<PieceType>,
+}
+
+impl Move
+{
+    pub fn new(from: u8, to: u8) -> Self { Move { from, to, promotion: None } }
+
+    pub fn with_promotion(from: u8, to: u8, promotion: PieceType) -> Self
+    {
+        Move { from, to, promotion: Some(promotion) }
+    }
+}
+
+impl FromStr for Move
+{
+    type Err = Box<dyn std::error::Error>;
+
+    /// Parses a move in long algebraic notation, as used by UCI (`e7e8q`).
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        if !(4..=5).contains(&s.len()) || !s.is_ascii() {
+            return Err(format!("invalid move {}!", s).into());
+        }
+
+        let from = Square::from_str(&s[0..2])?.index() as u8;
+        let to = Square::from_str(&s[2..4])?.index() as u8;
+        let promotion = match s.get(4..5) {
+            Some(p) => Some(Piece::from_str(&p.to_uppercase())?.piece),
+            None => None,
+        };
+
+        match promotion {
+            Some(PieceType::Pawn) | Some(PieceType::King) =>
+                Err(format!("invalid promotion in move {}!", s).into()),
+            _ => Ok(Move { from, to, promotion }),
+        }
+    }
+}
+
+impl fmt::Display for Move
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(
+            f,
+            "{}{}",
+            Square::from_index(self.from as usize),
+            Square::from_index(self.to as usize)
+        )?;
+
+        if let Some(piece) = self.promotion {
+            write!(f, "{}", Piece { color: Color::Black, piece })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::str::FromStr;
+
+    use super::Move;
+    use crate::piece::PieceType::*;
+
+    #[test]
+    fn test_move_from_string()
+    {
+        assert_eq!(Move::from_str("e2e4").unwrap(), Move::new(12, 28));
+        assert_eq!(
+            Move::from_str("e7e8q").unwrap(),
+            Move::with_promotion(52, 60, Queen)
+        );
+
+        assert!(Move::from_str("e2").is_err());
+        assert!(Move::from_str("e2e9").is_err());
+        assert!(Move::from_str("e7e8k").is_err());
+        assert!(Move::from_str("e7e8x").is_err());
+    }
+
+    #[test]
+    fn test_move_to_string()
+    {
+        assert_eq!(Move::new(12, 28).to_string(), "e2e4");
+        assert_eq!(Move::with_promotion(9, 1, Knight).to_string(), "b2b1n");
+    }
+}
@@ -0,0 +1,539 @@
+//! An efficiently updatable neural network evaluation.
+//!
+//! The network has 768 inputs for each side, one for every piece on every
+//! square, seen from that side: its own pieces first, with the board flipped
+//! for black and mirrored so that its king stands on the queen side. These
+//! feed a hidden layer of any width shared by both sides, whose clipped
+//! outputs for the side to move and then the other side feed a single output.
+//!
+//! Network files hold, in order and little endian:
+//!
+//! - the magic bytes `BCLDNN` and a version byte of 1,
+//! - the hidden layer's width as a u16,
+//! - the hidden layer's weights as i16s, each input's in turn,
+//! - the hidden layer's biases as i16s,
+//! - the output's weights as i16s, the side to move's first,
+//! - the output's bias as an i32.
+//!
+//! The hidden layer is quantized by 255, the output's weights by 64 and its
+//! bias by both, and the output is scaled by 400 to centipawns.
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::board::BitBoardState;
+use crate::eval::Evaluator;
+use crate::piece::{Color, Piece, PieceType};
+
+/// The number of inputs for each side.
+pub const INPUTS: usize = 768;
+/// The widest hidden layer a network file may have.
+pub const MAX_HIDDEN: usize = 4096;
+
+const FILE_MAGIC: &[u8; 6] = b"BCLDNN";
+const FILE_VERSION: u8 = 1;
+
+/// The quantization of the hidden layer, which is clipped to `0..=QA`.
+const QA: i32 = 255;
+/// The quantization of the output's weights.
+const QB: i32 = 64;
+/// Turns the network's output into centipawns.
+const SCALE: i32 = 400;
+/// The largest evaluation returned, well clear of mate scores.
+const MAX_EVAL: i32 = 20000;
+
+/// A network's weights, quantized to integers.
+pub struct Network
+{
+    hidden:          usize,
+    /// The hidden layer's weights, with each input's together.
+    feature_weights: Vec<i16>,
+    feature_biases:  Vec<i16>,
+    /// The output's weights, for the side to move and then the other side.
+    output_weights:  Vec<i16>,
+    output_bias:     i32,
+}
+
+/// The hidden layer's values for each ply of the moves made on a board, kept
+/// up to date as moves are made.
+#[derive(Clone)]
+pub struct Accumulators
+{
+    network: Arc<Network>,
+    /// The values for white and then black, for each ply in turn.
+    stack:   Vec<i16>,
+    /// The pieces added and removed by the move being made.
+    changes: [(Piece, usize, bool); 4],
+    changed: usize,
+}
+
+/// Evaluates positions with a network.
+pub struct NnueEvaluator
+{
+    network: Arc<Network>,
+}
+
+impl Network
+{
+    /// Returns a network with every weight zero, for filling in.
+    fn zeroed(hidden: usize) -> Self
+    {
+        Network {
+            hidden,
+            feature_weights: vec![0; INPUTS * hidden],
+            feature_biases: vec![0; hidden],
+            output_weights: vec![0; 2 * hidden],
+            output_bias: 0,
+        }
+    }
+
+    pub fn hidden(&self) -> usize { self.hidden }
+
+    /// Reads a network file.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>>
+    {
+        Network::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self, Box<dyn Error>>
+    {
+        let mut magic = [0; 6];
+        r.read_exact(&mut magic)?;
+        if &magic != FILE_MAGIC {
+            return Err("not a network file!".into());
+        }
+
+        let mut header = [0; 3];
+        r.read_exact(&mut header)?;
+        if header[0] != FILE_VERSION {
+            return Err(
+                format!("unsupported network version {}!", header[0]).into()
+            );
+        }
+        let hidden = u16::from_le_bytes([header[1], header[2]]) as usize;
+        if !(1..=MAX_HIDDEN).contains(&hidden) {
+            return Err(format!("invalid hidden layer size {}!", hidden).into());
+        }
+
+        let mut network = Network::zeroed(hidden);
+        read_i16s(r, &mut network.feature_weights)?;
+        read_i16s(r, &mut network.feature_biases)?;
+        read_i16s(r, &mut network.output_weights)?;
+        let mut bias = [0; 4];
+        r.read_exact(&mut bias)?;
+        network.output_bias = i32::from_le_bytes(bias);
+
+        if r.read(&mut [0])? != 0 {
+            return Err("network file is longer than its header says!".into());
+        }
+        Ok(network)
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<(), Box<dyn Error>>
+    {
+        w.write_all(FILE_MAGIC)?;
+        w.write_all(&[FILE_VERSION])?;
+        w.write_all(&(self.hidden as u16).to_le_bytes())?;
+        for values in
+            [&self.feature_weights, &self.feature_biases, &self.output_weights]
+        {
+            for value in values.iter() {
+                w.write_all(&value.to_le_bytes())?;
+            }
+        }
+        w.write_all(&self.output_bias.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Computes the hidden layer for one side from scratch.
+    fn refresh(&self, board: &BitBoardState, side: Color, out: &mut [i16])
+    {
+        out.copy_from_slice(&self.feature_biases);
+        let king = board.king_square(side);
+        for square in 0..64 {
+            if let Some(piece) = board.piece_at(square) {
+                let feature = feature(side, king, piece, square);
+                add(out, self.weights(feature));
+            }
+        }
+    }
+
+    fn weights(&self, feature: usize) -> &[i16]
+    {
+        &self.feature_weights[feature * self.hidden..][..self.hidden]
+    }
+
+    /// Computes the output from the hidden layer for each side, white first.
+    fn output(&self, values: &[i16], turn: Color) -> i32
+    {
+        let (white, black) = values.split_at(self.hidden);
+        let (us, them) = match turn {
+            Color::White => (white, black),
+            Color::Black => (black, white),
+        };
+        let (our_weights, their_weights) =
+            self.output_weights.split_at(self.hidden);
+
+        let sum = clipped_dot(us, our_weights) as i64
+            + clipped_dot(them, their_weights) as i64;
+        let output =
+            (sum + self.output_bias as i64) * SCALE as i64 / (QA * QB) as i64;
+        output.clamp(-MAX_EVAL as i64, MAX_EVAL as i64) as i32
+    }
+
+    /// Evaluates a position from scratch, without any accumulators.
+    pub fn evaluate(&self, board: &BitBoardState) -> i32
+    {
+        let mut values = vec![0; 2 * self.hidden];
+        let (white, black) = values.split_at_mut(self.hidden);
+        self.refresh(board, Color::White, white);
+        self.refresh(board, Color::Black, black);
+        self.output(&values, board.turn())
+    }
+}
+
+impl Accumulators
+{
+    /// Starts accumulating for a board, from scratch.
+    pub fn new(network: Arc<Network>, board: &BitBoardState) -> Self
+    {
+        let mut stack = vec![0; 2 * network.hidden];
+        let (white, black) = stack.split_at_mut(network.hidden);
+        network.refresh(board, Color::White, white);
+        network.refresh(board, Color::Black, black);
+
+        Accumulators {
+            network,
+            stack,
+            changes: [(
+                Piece { color: Color::White, piece: PieceType::Pawn },
+                0,
+                false,
+            ); 4],
+            changed: 0,
+        }
+    }
+
+    pub fn network(&self) -> &Arc<Network> { &self.network }
+
+    /// Returns the hidden layer for the current position, white first.
+    pub fn current(&self) -> &[i16]
+    {
+        &self.stack[self.stack.len() - 2 * self.network.hidden..]
+    }
+
+    /// Notes a piece added to or removed from a square by the move being
+    /// made.
+    pub fn record(&mut self, piece: Piece, square: usize, added: bool)
+    {
+        self.changes[self.changed] = (piece, square, added);
+        self.changed += 1;
+    }
+
+    /// Applies the changes noted for a move, now made on the board, as a new
+    /// ply. A side whose king moved is computed from scratch, since every
+    /// one of its inputs may have changed.
+    pub fn push(&mut self, board: &BitBoardState)
+    {
+        let hidden = self.network.hidden;
+        let start = self.stack.len() - 2 * hidden;
+        self.stack.extend_from_within(start..);
+        let changes = &self.changes[..std::mem::take(&mut self.changed)];
+
+        let top = self.stack.len() - 2 * hidden;
+        for (i, &side) in [Color::White, Color::Black].iter().enumerate() {
+            let values = &mut self.stack[top + i * hidden..][..hidden];
+            let king_moved = changes.iter().any(|(piece, ..)| {
+                piece.color == side && piece.piece == PieceType::King
+            });
+            if king_moved {
+                self.network.refresh(board, side, values);
+                continue;
+            }
+
+            let king = board.king_square(side);
+            for &(piece, square, added) in changes {
+                let weights =
+                    self.network.weights(feature(side, king, piece, square));
+                match added {
+                    true => add(values, weights),
+                    false => subtract(values, weights),
+                }
+            }
+        }
+    }
+
+    /// Goes back to the previous ply, now that a move has been taken back on
+    /// the board, forgetting the changes noted while doing so.
+    pub fn pop(&mut self, board: &BitBoardState)
+    {
+        self.changed = 0;
+        let hidden = self.network.hidden;
+        match self.stack.len() > 2 * hidden {
+            true => self.stack.truncate(self.stack.len() - 2 * hidden),
+            // the move was made before accumulating began
+            false => {
+                let (white, black) = self.stack.split_at_mut(hidden);
+                self.network.refresh(board, Color::White, white);
+                self.network.refresh(board, Color::Black, black);
+            },
+        }
+    }
+}
+
+impl NnueEvaluator
+{
+    pub fn new(network: Arc<Network>) -> Self { NnueEvaluator { network } }
+}
+
+impl Evaluator for NnueEvaluator
+{
+    fn evaluate(&self, board: &BitBoardState) -> i32
+    {
+        match board.accumulators() {
+            Some(acc) if Arc::ptr_eq(acc.network(), &self.network) =>
+                self.network.output(acc.current(), board.turn()),
+            _ => self.network.evaluate(board),
+        }
+    }
+
+    fn prepare(&self, board: &mut BitBoardState)
+    {
+        board.set_network(Some(self.network.clone()));
+    }
+}
+
+/// Returns the input for a piece on a square, as seen by one side with its
+/// king on the given square.
+fn feature(side: Color, king: usize, piece: Piece, square: usize) -> usize
+{
+    let mut square = match side {
+        Color::White => square,
+        Color::Black => square ^ 56,
+    };
+    if king % 8 >= 4 {
+        square ^= 7;
+    }
+    let theirs = (piece.color != side) as usize;
+
+    ((theirs * 6 + piece.piece as usize) * 64) + square
+}
+
+fn read_i16s<R: Read>(r: &mut R, out: &mut [i16])
+    -> Result<(), Box<dyn Error>>
+{
+    let mut bytes = vec![0; out.len() * 2];
+    r.read_exact(&mut bytes)
+        .map_err(|_| "network file is shorter than its header says!")?;
+    for (value, pair) in out.iter_mut().zip(bytes.chunks_exact(2)) {
+        *value = i16::from_le_bytes([pair[0], pair[1]]);
+    }
+    Ok(())
+}
+
+// the compiler vectorizes these loops by itself
+fn add(values: &mut [i16], weights: &[i16])
+{
+    for (value, weight) in values.iter_mut().zip(weights) {
+        *value = value.wrapping_add(*weight);
+    }
+}
+
+fn subtract(values: &mut [i16], weights: &[i16])
+{
+    for (value, weight) in values.iter_mut().zip(weights) {
+        *value = value.wrapping_sub(*weight);
+    }
+}
+
+/// Sums the values, clipped to `0..=QA`, times the weights.
+fn clipped_dot(values: &[i16], weights: &[i16]) -> i32
+{
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // safe, as the processor has just been checked for AVX2
+        return unsafe { clipped_dot_avx2(values, weights) };
+    }
+
+    clipped_dot_scalar(values, weights)
+}
+
+fn clipped_dot_scalar(values: &[i16], weights: &[i16]) -> i32
+{
+    values
+        .iter()
+        .zip(weights)
+        .map(|(&value, &weight)| (value as i32).clamp(0, QA) * weight as i32)
+        .sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn clipped_dot_avx2(values: &[i16], weights: &[i16]) -> i32
+{
+    use std::arch::x86_64::*;
+
+    let chunks = values.len() / 16;
+    let zero = _mm256_setzero_si256();
+    let max = _mm256_set1_epi16(QA as i16);
+    let mut sum = _mm256_setzero_si256();
+
+    for i in 0..chunks {
+        let v = _mm256_loadu_si256(values.as_ptr().add(i * 16) as *const _);
+        let w = _mm256_loadu_si256(weights.as_ptr().add(i * 16) as *const _);
+        let clipped = _mm256_min_epi16(_mm256_max_epi16(v, zero), max);
+        // each pair of products fits in 32 bits, as the values are below 256
+        sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clipped, w));
+    }
+
+    let mut lanes = [0i32; 8];
+    _mm256_storeu_si256(lanes.as_mut_ptr() as *mut _, sum);
+    lanes.iter().sum::<i32>()
+        + clipped_dot_scalar(&values[chunks * 16..], &weights[chunks * 16..])
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::board::GameState;
+    use crate::engine::Engine;
+    use crate::moves::Move;
+    use crate::options::Options;
+    use crate::search::SearchLimits;
+    use crate::strength::mix;
+    use crate::test_util::TempPath;
+
+    /// Returns a network with small random weights.
+    fn random_network(hidden: usize) -> Network
+    {
+        let mut network = Network::zeroed(hidden);
+        let mut seed = 0;
+        let mut random = |range: i64| {
+            seed += 1;
+            (mix(seed) % (2 * range as u64 + 1)) as i64 - range
+        };
+        for weight in network.feature_weights.iter_mut() {
+            *weight = random(40) as i16;
+        }
+        for bias in network.feature_biases.iter_mut() {
+            *bias = random(100) as i16;
+        }
+        for weight in network.output_weights.iter_mut() {
+            *weight = random(64) as i16;
+        }
+        network.output_bias = random(1000) as i32;
+        network
+    }
+
+    #[test]
+    fn test_file_round_trip()
+    {
+        let network = random_network(24);
+        let mut bytes = Vec::new();
+        network.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 6 + 3 + 2 * (INPUTS * 24 + 24 + 48) + 4);
+
+        let read = Network::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.hidden(), 24);
+        assert_eq!(read.feature_weights, network.feature_weights);
+        assert_eq!(read.output_bias, network.output_bias);
+
+        // files of the wrong size are refused
+        assert!(Network::read(&mut &bytes[..bytes.len() - 1]).is_err());
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(Network::read(&mut longer.as_slice()).is_err());
+        assert!(Network::read(&mut &b"BCLDTB\x01\x18\x00"[..]).is_err());
+    }
+
+    #[test]
+    fn test_incremental_updates()
+    {
+        let evaluator = NnueEvaluator::new(Arc::new(random_network(40)));
+        let mut board = *BitBoardState::from_fen(
+            "r3k2r/1P3ppp/8/3pP3/8/8/5PPP/R3K2R w KQkq d6 0 1",
+        )
+        .unwrap();
+        evaluator.prepare(&mut board);
+
+        // castling, en passant, promotion, captures and king moves
+        let moves = ["e1g1", "e8c8", "e5d6", "h7h6", "b7b8q", "c8b8", "g1h1"];
+        let mut evals = vec![evaluator.evaluate(&board)];
+        for mv in moves {
+            board.make_move(Move::from_str(mv).unwrap());
+            let eval = evaluator.evaluate(&board);
+            assert_eq!(eval, evaluator.network.evaluate(&board), "{}", mv);
+            evals.push(eval);
+        }
+
+        for _ in moves {
+            evals.pop();
+            board.unmake_move();
+            assert_eq!(evaluator.evaluate(&board), *evals.last().unwrap());
+        }
+
+        // a null move leaves the pieces alone
+        board.make_null_move();
+        assert_eq!(
+            evaluator.evaluate(&board),
+            evaluator.network.evaluate(&board)
+        );
+    }
+
+    #[test]
+    fn test_eval_file()
+    {
+        let path = TempPath::new("test_eval_file.nnue");
+        let mut file = File::create(&path).unwrap();
+        random_network(32).write(&mut file).unwrap();
+
+        let mut engine = Engine::default();
+        let mut options = Options::default();
+        let value = path.to_str();
+        options.set(&mut engine, "EvalFile", value).unwrap();
+
+        let limits = SearchLimits { depth: Some(4), ..Default::default() };
+        let board = BitBoardState::start_of_game();
+        engine.start(&board, limits, ());
+        let best_move = engine.wait().unwrap().best_move.unwrap();
+        assert!(board.legal_moves().contains(&best_move));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(options.set(&mut engine, "EvalFile", value).is_err());
+    }
+
+    #[test]
+    fn test_symmetry()
+    {
+        // a position and its mirror image score the same for the side to move
+        let network = random_network(16);
+        let white = BitBoardState::from_fen(
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+        )
+        .unwrap();
+        let black = BitBoardState::from_fen(
+            "rnbqkb1r/pppp1ppp/5n2/4p3/4P3/2N5/PPPP1PPP/R1BQKBNR b KQkq - 2 3",
+        )
+        .unwrap();
+        assert_eq!(network.evaluate(&white), network.evaluate(&black));
+    }
+
+    #[test]
+    fn test_clipped_dot()
+    {
+        let values: Vec<i16> =
+            (0..100).map(|i| (i * 7 % 400 - 100) as i16).collect();
+        let weights: Vec<i16> =
+            (0..100).map(|i| (i * 13 % 200 - 100) as i16).collect();
+        assert_eq!(
+            clipped_dot(&values, &weights),
+            clipped_dot_scalar(&values, &weights)
+        );
+    }
+}
@@ -0,0 +1,573 @@
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::book::Book;
+use crate::engine::Engine;
+use crate::eval::{EvalParams, Evaluator, HandcraftedEvaluator};
+use crate::nnue::{Network, NnueEvaluator};
+use crate::search::SearchOptions;
+use crate::strength::{MAX_ELO, MIN_ELO};
+use crate::tablebase::Tablebase;
+use crate::time::DEFAULT_MOVE_OVERHEAD;
+use crate::tt::DEFAULT_SIZE_MB;
+
+/// The largest transposition table that can be asked for, in megabytes.
+const MAX_HASH_MB: i64 = 65536;
+/// The most search threads that can be asked for.
+const MAX_THREADS: i64 = 1024;
+/// The most lines that can be searched for at once.
+const MAX_MULTI_PV: i64 = 256;
+/// The rating played at when limiting strength, until told otherwise.
+const DEFAULT_ELO: i64 = 1500;
+/// Separates the directories given for the tablebases, as in `PATH`.
+const PATH_SEPARATOR: char = if cfg!(windows) { ';' } else { ':' };
+
+/// Picks out one of the switches in the search options.
+type OptionField = fn(&mut SearchOptions) -> &mut bool;
+
+/// The check options switching parts of the search on and off, by name.
+const SEARCH_OPTIONS: [(&str, OptionField); 8] = [
+    ("NullMove", |o| &mut o.null_move),
+    ("LateMoveReductions", |o| &mut o.late_move_reductions),
+    ("ReverseFutility", |o| &mut o.reverse_futility),
+    ("Futility", |o| &mut o.futility),
+    ("Razoring", |o| &mut o.razoring),
+    ("LateMovePruning", |o| &mut o.late_move_pruning),
+    ("CheckExtensions", |o| &mut o.check_extensions),
+    ("SingularExtensions", |o| &mut o.singular_extensions),
+];
+
+/// Applies a change of option to the engine, given every option's value.
+pub type Hook = fn(&mut Engine, &Options) -> Result<(), Box<dyn Error>>;
+
+/// The type of an option, with its bounds and default.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum OptionKind
+{
+    Check
+    {
+        default: bool
+    },
+    Spin
+    {
+        default: i64, min: i64, max: i64
+    },
+    /// One of a fixed set of strings.
+    Combo
+    {
+        default: &'static str, values: &'static [&'static str]
+    },
+    String
+    {
+        default: &'static str
+    },
+    /// An action with no value.
+    Button,
+}
+
+/// A setting that can be changed from the GUI.
+#[derive(Clone)]
+pub struct EngineOption
+{
+    pub name: &'static str,
+    pub kind: OptionKind,
+    /// The current value, as it would be given to `setoption`.
+    value:    String,
+    /// Called after the value changes.
+    hook:     Option<Hook>,
+}
+
+/// Every option a protocol offers, in the order they're listed.
+#[derive(Clone)]
+pub struct Options
+{
+    options: Vec<EngineOption>,
+}
+
+impl OptionKind
+{
+    fn default_value(&self) -> String
+    {
+        match self {
+            OptionKind::Check { default } => default.to_string(),
+            OptionKind::Spin { default, .. } => default.to_string(),
+            OptionKind::Combo { default, .. } => default.to_string(),
+            OptionKind::String { default } => default.to_string(),
+            OptionKind::Button => String::new(),
+        }
+    }
+
+    /// Checks a value given for an option of this kind, returning it in
+    /// canonical form.
+    fn parse(
+        &self, name: &str, value: Option<&str>,
+    ) -> Result<String, Box<dyn Error>>
+    {
+        let value = match (self, value) {
+            (OptionKind::Button, _) => return Ok(String::new()),
+            (OptionKind::String { .. }, None | Some("<empty>")) =>
+                return Ok(String::new()),
+            (_, Some(value)) => value,
+            (_, None) =>
+                return Err(format!("no value given for {}!", name).into()),
+        };
+
+        match self {
+            OptionKind::Check { .. } => match value {
+                "true" | "1" => Ok("true".to_string()),
+                "false" | "0" => Ok("false".to_string()),
+                _ => Err(format!("{} must be true or false!", name).into()),
+            },
+            OptionKind::Spin { min, max, .. } => {
+                let number = i64::from_str(value)?;
+                if !(min..=max).contains(&&number) {
+                    return Err(format!(
+                        "{} must be from {} to {}!",
+                        name, min, max
+                    )
+                    .into());
+                }
+                Ok(number.to_string())
+            },
+            OptionKind::Combo { values, .. } => values
+                .iter()
+                .find(|v| v.eq_ignore_ascii_case(value))
+                .map(|v| v.to_string())
+                .ok_or_else(|| format!("{} can't be {}!", name, value).into()),
+            OptionKind::String { .. } => Ok(value.to_string()),
+            OptionKind::Button => unreachable!(),
+        }
+    }
+}
+
+/// Formats the option as UCI's `option` command lists it.
+impl fmt::Display for EngineOption
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "option name {} type ", self.name)?;
+        match &self.kind {
+            OptionKind::Check { default } =>
+                write!(f, "check default {}", default),
+            OptionKind::Spin { default, min, max } =>
+                write!(f, "spin default {} min {} max {}", default, min, max),
+            OptionKind::Combo { default, values } => {
+                write!(f, "combo default {}", default)?;
+                values.iter().try_for_each(|v| write!(f, " var {}", v))
+            },
+            OptionKind::String { default: "" } =>
+                write!(f, "string default <empty>"),
+            OptionKind::String { default } =>
+                write!(f, "string default {}", default),
+            OptionKind::Button => write!(f, "button"),
+        }
+    }
+}
+
+impl EngineOption
+{
+    /// Formats the option as XBoard's `feature option` command lists it.
+    pub fn xboard_feature(&self) -> String
+    {
+        let kind = match &self.kind {
+            OptionKind::Check { default } =>
+                format!("-check {}", *default as u8),
+            OptionKind::Spin { default, min, max } =>
+                format!("-spin {} {} {}", default, min, max),
+            // the default is marked with a star
+            OptionKind::Combo { default, values } => {
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|v| match v == default {
+                        true => format!("*{}", v),
+                        false => v.to_string(),
+                    })
+                    .collect();
+                format!("-combo {}", values.join(" /// "))
+            },
+            OptionKind::String { default } => format!("-string {}", default),
+            OptionKind::Button => "-button".to_string(),
+        };
+        format!("feature option=\"{} {}\"", self.name, kind)
+    }
+}
+
+impl Default for Options
+{
+    /// Returns the options every protocol offers, at their defaults.
+    fn default() -> Self
+    {
+        let mut options = Options { options: Vec::new() };
+        options.add(
+            "Hash",
+            OptionKind::Spin {
+                default: DEFAULT_SIZE_MB as i64,
+                min:     1,
+                max:     MAX_HASH_MB,
+            },
+            Some(|engine, options| {
+                let size = options.spin("Hash") as usize;
+                engine.searcher().set_hash_size(size);
+                Ok(())
+            }),
+        );
+        options.add(
+            "Clear Hash",
+            OptionKind::Button,
+            Some(|engine, _| {
+                engine.searcher().clear();
+                Ok(())
+            }),
+        );
+        options.add(
+            "Threads",
+            OptionKind::Spin { default: 1, min: 1, max: MAX_THREADS },
+            Some(|engine, options| {
+                engine.set_threads(options.spin("Threads") as usize);
+                Ok(())
+            }),
+        );
+        options.add(
+            "MultiPV",
+            OptionKind::Spin { default: 1, min: 1, max: MAX_MULTI_PV },
+            Some(|engine, options| {
+                engine.set_multi_pv(options.spin("MultiPV") as usize);
+                Ok(())
+            }),
+        );
+        options.add(
+            "Contempt",
+            OptionKind::Spin { default: 0, min: -100, max: 100 },
+            Some(|engine, options| {
+                let contempt = options.spin("Contempt") as i32;
+                engine.searcher().set_contempt(contempt);
+                Ok(())
+            }),
+        );
+        options.add(
+            "Move Overhead",
+            OptionKind::Spin {
+                default: DEFAULT_MOVE_OVERHEAD.as_millis() as i64,
+                min:     0,
+                max:     5000,
+            },
+            Some(|engine, options| {
+                let millis = options.spin("Move Overhead") as u64;
+                let overhead = Duration::from_millis(millis);
+                engine.searcher().set_move_overhead(overhead);
+                Ok(())
+            }),
+        );
+        options.add(
+            "OwnBook",
+            OptionKind::Check { default: false },
+            Some(load_book),
+        );
+        options.add(
+            "BookFile",
+            OptionKind::String { default: "" },
+            Some(load_book),
+        );
+        // named as GUIs expect, though it loads bcld's own distance to mate
+        // tables, as written by `bcld tablebase`, rather than Syzygy files
+        options.add(
+            "SyzygyPath",
+            OptionKind::String { default: "" },
+            Some(|engine, options| {
+                let tablebase = match options.string("SyzygyPath") {
+                    "" => None,
+                    paths => {
+                        let mut tablebase = Tablebase::new();
+                        for path in paths.split(PATH_SEPARATOR) {
+                            tablebase.load(Path::new(path))?;
+                        }
+                        Some(tablebase)
+                    },
+                };
+                engine.searcher().set_tablebase(tablebase);
+                Ok(())
+            }),
+        );
+        options.add(
+            "EvalFile",
+            OptionKind::String { default: "" },
+            Some(set_evaluator),
+        );
+        options.add(
+            "EvalParamsFile",
+            OptionKind::String { default: "" },
+            Some(set_evaluator),
+        );
+        options.add(
+            "UCI_LimitStrength",
+            OptionKind::Check { default: false },
+            Some(set_strength),
+        );
+        options.add(
+            "UCI_Elo",
+            OptionKind::Spin {
+                default: DEFAULT_ELO,
+                min:     MIN_ELO as i64,
+                max:     MAX_ELO as i64,
+            },
+            Some(set_strength),
+        );
+        for (name, _) in SEARCH_OPTIONS {
+            options.add(
+                name,
+                OptionKind::Check { default: true },
+                Some(set_search_options),
+            );
+        }
+
+        options
+    }
+}
+
+impl Options
+{
+    /// Adds an option at its default value, with a hook to call when it
+    /// changes.
+    pub fn add(
+        &mut self, name: &'static str, kind: OptionKind, hook: Option<Hook>,
+    )
+    {
+        let value = kind.default_value();
+        self.options.push(EngineOption { name, kind, value, hook });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &EngineOption>
+    {
+        self.options.iter()
+    }
+
+    /// Returns an option's current value, as given to `setoption`. Names
+    /// are matched ignoring case, as UCI asks.
+    pub fn get(&self, name: &str) -> Option<&str>
+    {
+        self.find(name).map(|i| self.options[i].value.as_str())
+    }
+
+    /// Returns the value of a check option, or false if there isn't one.
+    pub fn check(&self, name: &str) -> bool { self.get(name) == Some("true") }
+
+    /// Returns the value of a spin option, or 0 if there isn't one.
+    pub fn spin(&self, name: &str) -> i64
+    {
+        self.get(name).and_then(|v| v.parse().ok()).unwrap_or(0)
+    }
+
+    /// Returns the value of a string or combo option, or an empty string if
+    /// there isn't one.
+    pub fn string(&self, name: &str) -> &str { self.get(name).unwrap_or("") }
+
+    /// Changes an option, checking the value against its type and bounds,
+    /// then applies the change to the engine. The old value is kept if
+    /// either fails. A running search is stopped first, since the searcher
+    /// can't be changed until it finishes.
+    pub fn set(
+        &mut self, engine: &mut Engine, name: &str, value: Option<&str>,
+    ) -> Result<(), Box<dyn Error>>
+    {
+        let i = self
+            .find(name)
+            .ok_or_else(|| format!("unknown option {}!", name))?;
+        let option = &mut self.options[i];
+        let value = option.kind.parse(option.name, value)?;
+        let previous = std::mem::replace(&mut option.value, value);
+
+        let hook = match option.hook {
+            Some(hook) => hook,
+            None => return Ok(()),
+        };
+        engine.stop();
+        hook(engine, self).inspect_err(|_| {
+            self.options[i].value = previous;
+        })
+    }
+
+    fn find(&self, name: &str) -> Option<usize>
+    {
+        self.options
+            .iter()
+            .position(|option| option.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Loads the book file when using the engine's own book, and drops it
+/// otherwise.
+fn load_book(
+    engine: &mut Engine, options: &Options,
+) -> Result<(), Box<dyn Error>>
+{
+    let book = match (options.check("OwnBook"), options.string("BookFile")) {
+        (true, path) if !path.is_empty() => Some(Book::load(Path::new(path))?),
+        _ => None,
+    };
+    engine.searcher().set_book(book);
+    Ok(())
+}
+
+/// Uses the network in the eval file if there is one, and otherwise the
+/// handcrafted evaluation with the weights in the parameters file, or the
+/// built-in weights.
+fn set_evaluator(
+    engine: &mut Engine, options: &Options,
+) -> Result<(), Box<dyn Error>>
+{
+    let evaluator: Arc<dyn Evaluator + Send + Sync> =
+        match (options.string("EvalFile"), options.string("EvalParamsFile")) {
+            ("", "") => Arc::new(HandcraftedEvaluator::default()),
+            ("", path) => Arc::new(HandcraftedEvaluator::new(
+                EvalParams::load(Path::new(path))?,
+            )),
+            (path, _) => {
+                let network = Network::load(Path::new(path))?;
+                Arc::new(NnueEvaluator::new(Arc::new(network)))
+            },
+        };
+    engine.searcher().set_evaluator(evaluator);
+    Ok(())
+}
+
+fn set_strength(
+    engine: &mut Engine, options: &Options,
+) -> Result<(), Box<dyn Error>>
+{
+    let elo = options.spin("UCI_Elo") as u32;
+    engine.set_elo(options.check("UCI_LimitStrength").then_some(elo));
+    Ok(())
+}
+
+fn set_search_options(
+    engine: &mut Engine, options: &Options,
+) -> Result<(), Box<dyn Error>>
+{
+    let searcher = engine.searcher();
+    for (name, field) in SEARCH_OPTIONS {
+        *field(&mut searcher.options) = options.check(name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_listing()
+    {
+        let mut options = Options::default();
+        options.add(
+            "Style",
+            OptionKind::Combo {
+                default: "Normal",
+                values:  &["Solid", "Normal", "Risky"],
+            },
+            None,
+        );
+        let lines: Vec<String> =
+            options.iter().map(ToString::to_string).collect();
+
+        assert!(lines.contains(&format!(
+            "option name Hash type spin default {} min 1 max 65536",
+            DEFAULT_SIZE_MB
+        )));
+        assert!(lines.contains(&"option name Clear Hash type button".into()));
+        assert!(lines
+            .contains(&"option name OwnBook type check default false".into()));
+        assert!(lines.contains(
+            &"option name BookFile type string default <empty>".into()
+        ));
+        assert!(lines.contains(
+            &"option name Style type combo default Normal var Solid var \
+              Normal var Risky"
+                .into()
+        ));
+
+        let style = options.iter().find(|o| o.name == "Style").unwrap();
+        assert_eq!(
+            style.xboard_feature(),
+            "feature option=\"Style -combo Solid /// *Normal /// Risky\""
+        );
+    }
+
+    #[test]
+    fn test_set()
+    {
+        let mut engine = Engine::default();
+        let mut options = Options::default();
+
+        options.set(&mut engine, "threads", Some("2")).unwrap();
+        options.set(&mut engine, "MultiPV", Some("4")).unwrap();
+        options.set(&mut engine, "Futility", Some("false")).unwrap();
+        assert_eq!(options.spin("Threads"), 2);
+        assert_eq!(engine.searcher().threads(), 2);
+        assert_eq!(engine.searcher().multi_pv(), 4);
+        assert!(!engine.searcher().options.futility);
+        assert!(engine.searcher().options.razoring);
+
+        options.set(&mut engine, "UCI_LimitStrength", Some("true")).unwrap();
+        options.set(&mut engine, "UCI_Elo", Some("1100")).unwrap();
+        let strength = engine.searcher().strength().unwrap();
+        assert_eq!(strength.elo, 1100);
+
+        // bad values are turned away, leaving the old ones
+        for (name, value) in [
+            ("Hash", Some("0")),
+            ("Threads", Some("two")),
+            ("OwnBook", Some("yes")),
+            ("MultiPV", None),
+            ("Unknown", Some("1")),
+            ("SyzygyPath", Some("/nonexistent/tables")),
+        ] {
+            assert!(options.set(&mut engine, name, value).is_err());
+        }
+        assert_eq!(options.spin("Threads"), 2);
+
+        // as are values the engine can't use
+        options.set(&mut engine, "OwnBook", Some("true")).unwrap();
+        let missing = Some("/nonexistent/book.pgn");
+        assert!(options.set(&mut engine, "BookFile", missing).is_err());
+        assert_eq!(options.string("BookFile"), "");
+        options.set(&mut engine, "BookFile", Some("<empty>")).unwrap();
+    }
+
+    #[test]
+    fn test_params_file()
+    {
+        use crate::board::{BitBoardState, GameState};
+        use crate::search::{SearchLimits, SearchScore};
+        use crate::test_util::TempPath;
+
+        let path = TempPath::new("test_params_file.txt");
+        std::fs::write(&path, "bishop_pair 2000/2000\n").unwrap();
+
+        let mut engine = Engine::default();
+        let mut options = Options::default();
+        let value = path.to_str();
+        options.set(&mut engine, "EvalParamsFile", value).unwrap();
+
+        let limits = SearchLimits { depth: Some(1), ..Default::default() };
+        let board = BitBoardState::start_of_game();
+        engine.start(&board, limits.clone(), ());
+        let score = engine.wait().unwrap().score;
+        assert!(matches!(score, SearchScore::Centipawns(cp) if cp.abs() < 100));
+
+        // a file of the wrong shape keeps the weights loaded before
+        std::fs::write(&path, "bishop_pair 2000/2000 0/0\n").unwrap();
+        assert!(options.set(&mut engine, "EvalParamsFile", value).is_err());
+        assert_eq!(options.string("EvalParamsFile"), value.unwrap());
+
+        let board = BitBoardState::from_fen("4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1")
+            .unwrap();
+        engine.start(&board, limits, ());
+        let score = engine.wait().unwrap().score;
+        assert!(matches!(score, SearchScore::Centipawns(cp) if cp > 1500));
+    }
+}
@@ -0,0 +1,645 @@
+//! Refer to https://www.thechessdrum.net/PGN_Reference.txt
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::board::{BitBoardState, GameState};
+use crate::moves::Move;
+use crate::piece::{Color, PieceType};
+use crate::Square;
+
+/// How a game ended, if it has.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameResult
+{
+    WhiteWins,
+    BlackWins,
+    Draw,
+    Unfinished,
+}
+
+/// A game, as the moves played from a starting position.
+#[derive(Clone)]
+pub struct Game
+{
+    /// Tag pairs, in the order they are written.
+    pub tags:        Vec<(String, String)>,
+    pub start:       BitBoardState,
+    pub moves:       Vec<Move>,
+    pub result:      GameResult,
+    /// Notes on some of the moves, by their index in `moves`.
+    pub annotations: BTreeMap<usize, Annotation>,
+}
+
+/// Notes on a move, written after it in the movetext.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Annotation
+{
+    /// A judgement of the move, such as `?!` or `??`, written straight after
+    /// it.
+    pub glyph:             Option<String>,
+    pub comment:           Option<String>,
+    /// A line to play instead of the move, from the position before it.
+    pub variation:         Vec<Move>,
+    /// A comment on the first move of the variation.
+    pub variation_comment: Option<String>,
+}
+
+impl GameResult
+{
+    /// Returns the points scored by the given side, if the game is over.
+    pub fn score_for(&self, color: Color) -> Option<f64>
+    {
+        let white = match self {
+            GameResult::WhiteWins => 1.0,
+            GameResult::BlackWins => 0.0,
+            GameResult::Draw => 0.5,
+            GameResult::Unfinished => return None,
+        };
+
+        match color {
+            Color::White => Some(white),
+            Color::Black => Some(1.0 - white),
+        }
+    }
+}
+
+impl FromStr for GameResult
+{
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        match s {
+            "1-0" => Ok(GameResult::WhiteWins),
+            "0-1" => Ok(GameResult::BlackWins),
+            "1/2-1/2" => Ok(GameResult::Draw),
+            "*" => Ok(GameResult::Unfinished),
+            _ => Err(format!("invalid result {}!", s).into()),
+        }
+    }
+}
+
+impl fmt::Display for GameResult
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}", match self {
+            GameResult::WhiteWins => "1-0",
+            GameResult::BlackWins => "0-1",
+            GameResult::Draw => "1/2-1/2",
+            GameResult::Unfinished => "*",
+        })
+    }
+}
+
+impl Default for Game
+{
+    fn default() -> Self { Game::new(BitBoardState::start_of_game()) }
+}
+
+impl Game
+{
+    /// Creates a game with no moves yet from the given position.
+    pub fn new(start: BitBoardState) -> Self
+    {
+        Game {
+            tags: Vec::new(),
+            start,
+            moves: Vec::new(),
+            result: GameResult::Unfinished,
+            annotations: BTreeMap::new(),
+        }
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str>
+    {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Sets a tag, replacing any with the same name.
+    pub fn set_tag(&mut self, name: &str, value: &str)
+    {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Returns the position after every move has been played.
+    pub fn board(&self) -> BitBoardState
+    {
+        let mut board = self.start.clone();
+        for mv in &self.moves {
+            board.make_move(*mv);
+        }
+        board
+    }
+
+    /// Plays a move, checking that it is legal.
+    pub fn push(&mut self, mv: Move) -> Result<(), Box<dyn Error>>
+    {
+        if !self.board().legal_moves().contains(&mv) {
+            return Err(format!("illegal move {}!", mv).into());
+        }
+
+        self.moves.push(mv);
+        Ok(())
+    }
+
+    /// Writes the movetext with move numbers and annotations, wrapped to fit
+    /// in 80 columns.
+    fn movetext(&self) -> String
+    {
+        let mut board = self.start.clone();
+        let mut tokens = Vec::new();
+        // black's move needs its number again after a comment or variation
+        let mut interrupted = true;
+
+        for (i, mv) in self.moves.iter().enumerate() {
+            tokens.push(board.to_san(*mv));
+            if let Some(number) = move_number(&board, interrupted) {
+                tokens.insert(tokens.len() - 1, number);
+            }
+
+            let annotation = self.annotations.get(&i);
+            interrupted = false;
+            if let Some(annotation) = annotation {
+                if let Some(glyph) = &annotation.glyph {
+                    tokens.last_mut().unwrap().push_str(glyph);
+                }
+                if let Some(comment) = &annotation.comment {
+                    tokens.push(format!("{{{}}}", comment));
+                    interrupted = true;
+                }
+                if !annotation.variation.is_empty() {
+                    tokens.extend(variation(&board, annotation));
+                    interrupted = true;
+                }
+            }
+            board.make_move(*mv);
+        }
+        tokens.push(self.result.to_string());
+
+        let mut text = String::new();
+        let mut line = 0;
+        for token in tokens {
+            if line > 0 && line + token.len() + 1 > 80 {
+                text.push('\n');
+                line = 0;
+            }
+            else if line > 0 {
+                text.push(' ');
+                line += 1;
+            }
+            text.push_str(&token);
+            line += token.len();
+        }
+
+        text
+    }
+}
+
+/// Returns the number to write before the next move, if it needs one.
+fn move_number(board: &BitBoardState, interrupted: bool) -> Option<String>
+{
+    match board.turn() {
+        Color::White => Some(format!("{}.", board.move_number())),
+        Color::Black if interrupted =>
+            Some(format!("{}...", board.move_number())),
+        Color::Black => None,
+    }
+}
+
+/// Writes the variation of an annotation in brackets.
+fn variation(board: &BitBoardState, annotation: &Annotation) -> Vec<String>
+{
+    let mut board = board.clone();
+    let mut tokens = Vec::new();
+
+    for (i, mv) in annotation.variation.iter().enumerate() {
+        let commented = i == 1 && annotation.variation_comment.is_some();
+        tokens.extend(move_number(&board, i == 0 || commented));
+        tokens.push(board.to_san(*mv));
+        if let (0, Some(comment)) = (i, &annotation.variation_comment) {
+            tokens.push(format!("{{{}}}", comment));
+        }
+        board.make_move(*mv);
+    }
+    tokens[0].insert(0, '(');
+    tokens.last_mut().unwrap().push(')');
+
+    tokens
+}
+
+impl fmt::Display for Game
+{
+    /// Writes the game as PGN, adding the tags needed to set up a position
+    /// other than the usual start.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let mut tags = self.tags.clone();
+        let fen = self.start.as_fen();
+        if fen != BitBoardState::start_of_game().as_fen()
+            && self.tag("FEN").is_none()
+        {
+            tags.push(("SetUp".to_string(), "1".to_string()));
+            tags.push(("FEN".to_string(), fen));
+        }
+        if self.tag("Result").is_none() {
+            tags.push(("Result".to_string(), self.result.to_string()));
+        }
+
+        for (name, value) in tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{} \"{}\"]", name, value)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{}", self.movetext())
+    }
+}
+
+/// A piece of PGN text.
+#[derive(PartialEq, Debug)]
+enum Token
+{
+    Tag(String, String),
+    Move(String),
+    Result(GameResult),
+}
+
+/// Splits PGN text into tags, moves and results, leaving out move numbers,
+/// comments, annotations and variations.
+fn tokenize(text: &str) -> Result<Vec<Token>, Box<dyn Error>>
+{
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let mut depth = 0;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                chars.by_ref().find(|&c| c == '}');
+            },
+            ';' => {
+                chars.by_ref().find(|&c| c == '\n');
+            },
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '[' if depth == 0 => {
+                let tag: String =
+                    chars.by_ref().take_while(|&c| c != ']').collect();
+                let (name, value) =
+                    tag.trim().split_once(' ').ok_or("invalid tag!")?;
+                let value = value.trim().trim_matches('"');
+                let value = value.replace("\\\"", "\"").replace("\\\\", "\\");
+                tokens.push(Token::Tag(name.to_string(), value));
+            },
+            c if c.is_whitespace() => (),
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "{}();[".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+
+                if depth > 0 || word.starts_with('$') {
+                    continue;
+                }
+                if let Ok(result) = GameResult::from_str(&word) {
+                    tokens.push(Token::Result(result));
+                    continue;
+                }
+
+                // move numbers may run into the move, as in `1.e4`
+                let word = match word.rfind('.') {
+                    Some(i) => &word[i + 1..],
+                    None => &word,
+                };
+                if !word.is_empty() {
+                    tokens.push(Token::Move(word.to_string()));
+                }
+            },
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Reads every game in some PGN text.
+pub fn parse_pgn(text: &str) -> Result<Vec<Game>, Box<dyn Error>>
+{
+    let mut games = Vec::new();
+    let mut game: Option<(Game, BitBoardState)> = None;
+
+    for token in tokenize(text)? {
+        // a tag after the moves starts the next game, even without a result
+        if let (Token::Tag(..), Some((current, _))) = (&token, &game) {
+            if !current.moves.is_empty() {
+                games.push(game.take().unwrap().0);
+            }
+        }
+        let (current, board) = game.get_or_insert_with(|| {
+            (Game::default(), BitBoardState::start_of_game())
+        });
+
+        match token {
+            Token::Tag(name, value) => {
+                if name == "FEN" {
+                    current.start = *BitBoardState::from_fen(&value)?;
+                    *board = current.start.clone();
+                }
+                if name == "Result" {
+                    current.result = GameResult::from_str(&value)?;
+                }
+                current.set_tag(&name, &value);
+            },
+            Token::Move(san) => {
+                let mv = board.parse_san(&san)?;
+                board.make_move(mv);
+                current.moves.push(mv);
+            },
+            Token::Result(result) => {
+                current.result = result;
+                current.set_tag("Result", &result.to_string());
+                games.push(game.take().unwrap().0);
+            },
+        }
+    }
+
+    if let Some((current, _)) = game {
+        if !current.moves.is_empty() || !current.tags.is_empty() {
+            games.push(current);
+        }
+    }
+
+    Ok(games)
+}
+
+impl BitBoardState
+{
+    /// Writes a legal move in standard algebraic notation, as used by PGN
+    /// (`Nbd7`, `exd6`, `O-O`, `e8=Q+`).
+    pub fn to_san(&self, mv: Move) -> String
+    {
+        let mut san = self.san_without_check(mv, &self.legal_moves());
+
+        let mut board = self.clone();
+        board.make_move(mv);
+        if board.in_check() {
+            san.push(match board.legal_moves().is_empty() {
+                true => '#',
+                false => '+',
+            });
+        }
+
+        san
+    }
+
+    /// Reads a move in standard algebraic notation, or in the long algebraic
+    /// notation used by UCI, checking that it is legal.
+    pub fn parse_san(&self, san: &str) -> Result<Move, Box<dyn Error>>
+    {
+        let legal = self.legal_moves();
+        let wanted =
+            san.trim_end_matches(['+', '#', '!', '?']).replace('0', "O");
+
+        legal
+            .iter()
+            .copied()
+            .find(|&mv| self.san_without_check(mv, &legal) == wanted)
+            .or_else(|| {
+                Move::from_str(san).ok().filter(|mv| legal.contains(mv))
+            })
+            .ok_or_else(|| format!("illegal move {}!", san).into())
+    }
+
+    fn san_without_check(&self, mv: Move, legal: &[Move]) -> String
+    {
+        let piece = match self.piece_at(mv.from as usize) {
+            Some(piece) => piece.piece,
+            None => return mv.to_string(),
+        };
+        let from = Square::from_index(mv.from as usize).to_string();
+        let to = Square::from_index(mv.to as usize).to_string();
+
+        if piece == PieceType::King
+            && (mv.from as i32 - mv.to as i32).abs() == 2
+        {
+            return match mv.to > mv.from {
+                true => "O-O".to_string(),
+                false => "O-O-O".to_string(),
+            };
+        }
+
+        let capture = self.piece_at(mv.to as usize).is_some()
+            || (piece == PieceType::Pawn && mv.from % 8 != mv.to % 8);
+        let mut san = String::new();
+
+        if piece == PieceType::Pawn {
+            if capture {
+                san.push_str(&from[..1]);
+            }
+        }
+        else {
+            san.push_str(&piece.to_string());
+
+            // name as little of the origin as tells the move apart
+            let rivals: Vec<&Move> = legal
+                .iter()
+                .filter(|other| {
+                    other.to == mv.to
+                        && other.from != mv.from
+                        && self.piece_at(other.from as usize).map(|p| p.piece)
+                            == Some(piece)
+                })
+                .collect();
+            let same_file = rivals.iter().any(|m| m.from % 8 == mv.from % 8);
+            let same_rank = rivals.iter().any(|m| m.from / 8 == mv.from / 8);
+
+            if !rivals.is_empty() && !same_file {
+                san.push_str(&from[..1]);
+            }
+            else if !rivals.is_empty() && !same_rank {
+                san.push_str(&from[1..]);
+            }
+            else if !rivals.is_empty() {
+                san.push_str(&from);
+            }
+        }
+
+        if capture {
+            san.push('x');
+        }
+        san.push_str(&to);
+        if let Some(promotion) = mv.promotion {
+            san.push('=');
+            san.push_str(&promotion.to_string());
+        }
+
+        san
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn san(fen: &str, mv: &str) -> String
+    {
+        let board = BitBoardState::from_fen(fen).unwrap();
+        board.to_san(Move::from_str(mv).unwrap())
+    }
+
+    #[test]
+    fn test_to_san()
+    {
+        let start = BitBoardState::start_of_game().as_fen();
+        assert_eq!(san(&start, "e2e4"), "e4");
+        assert_eq!(san(&start, "g1f3"), "Nf3");
+
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/\
+                        R3K2R w KQkq - 0 1";
+        assert_eq!(san(kiwipete, "e1g1"), "O-O");
+        assert_eq!(san(kiwipete, "e1c1"), "O-O-O");
+        assert_eq!(san(kiwipete, "d5e6"), "dxe6");
+        assert_eq!(san(kiwipete, "e5f7"), "Nxf7");
+        assert_eq!(san(kiwipete, "e2a6"), "Bxa6");
+        assert_eq!(san(kiwipete, "d2c1"), "Bc1");
+
+        let rooks = "7k/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(rooks, "a1a3"), "R1a3");
+        assert_eq!(san(rooks, "a5a8"), "Ra8+");
+
+        assert_eq!(san("k7/2P5/1K6/8/8/8/8/8 w - - 0 1", "c7c8q"), "c8=Q#");
+        assert_eq!(san("7k/8/6K1/8/8/8/8/R7 w - - 0 1", "a1a8"), "Ra8#");
+    }
+
+    #[test]
+    fn test_parse_san()
+    {
+        let board = BitBoardState::start_of_game();
+        assert_eq!(
+            board.parse_san("Nf3").unwrap(),
+            Move::from_str("g1f3").unwrap()
+        );
+        assert_eq!(
+            board.parse_san("e4!?").unwrap(),
+            Move::from_str("e2e4").unwrap()
+        );
+        assert_eq!(
+            board.parse_san("d2d4").unwrap(),
+            Move::from_str("d2d4").unwrap()
+        );
+        assert!(board.parse_san("Nd2").is_err());
+        assert!(board.parse_san("e5").is_err());
+
+        let castle =
+            BitBoardState::from_fen("4k3/8/8/8/8/8/8/4K2R w K - 0 1").unwrap();
+        assert_eq!(
+            castle.parse_san("0-0").unwrap(),
+            Move::from_str("e1g1").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_pgn()
+    {
+        let pgn = r#"
+[Event "Test"]
+[White "A \"quoted\" name"]
+[Result "1-0"]
+
+1. e4 {best by test} e5 2.Nf3 (2. f4 exf4) Nc6 $1 3. Bb5 a6; the Morphy
+4. Ba4 1-0
+
+[FEN "7k/8/6K1/8/8/8/8/R7 b - - 0 1"]
+
+1... Kg8 2. Ra8# *
+"#;
+        let games = parse_pgn(pgn).unwrap();
+        assert_eq!(games.len(), 2);
+
+        assert_eq!(games[0].tag("White"), Some("A \"quoted\" name"));
+        assert_eq!(games[0].result, GameResult::WhiteWins);
+        assert_eq!(games[0].moves.len(), 7);
+        assert_eq!(games[0].moves[6], Move::from_str("b5a4").unwrap());
+
+        assert_eq!(games[1].result, GameResult::Unfinished);
+        assert_eq!(games[1].moves.len(), 2);
+        assert!(games[1].board().legal_moves().is_empty());
+
+        assert!(parse_pgn("1. e4 e4").is_err());
+    }
+
+    #[test]
+    fn test_write_pgn()
+    {
+        let mut game = Game::default();
+        game.set_tag("Event", "Test");
+        for mv in ["e2e4", "e7e5", "g1f3"] {
+            game.push(Move::from_str(mv).unwrap()).unwrap();
+        }
+        assert!(game.push(Move::from_str("e1e2").unwrap()).is_err());
+        game.result = GameResult::Draw;
+
+        let pgn = game.to_string();
+        assert_eq!(
+            pgn,
+            "[Event \"Test\"]\n[Result \"1/2-1/2\"]\n\n1. e4 e5 2. Nf3 \
+             1/2-1/2\n"
+        );
+
+        let read = &parse_pgn(&pgn).unwrap()[0];
+        assert_eq!(read.moves, game.moves);
+        assert_eq!(read.result, GameResult::Draw);
+
+        let board =
+            BitBoardState::from_fen("7k/8/6K1/8/8/8/8/R7 b - - 0 1").unwrap();
+        let mut game = Game::new(*board);
+        game.push(Move::from_str("h8g8").unwrap()).unwrap();
+        let pgn = game.to_string();
+        assert!(pgn.contains("[FEN \"7k/8/6K1/8/8/8/8/R7 b - - 0 1\"]"));
+        assert!(pgn.ends_with("1... Kg8 *\n"));
+        assert_eq!(parse_pgn(&pgn).unwrap()[0].moves, game.moves);
+    }
+
+    #[test]
+    fn test_write_annotations()
+    {
+        let mut game = Game::default();
+        for mv in ["e2e4", "e7e5", "d1h5", "g8f6", "h5f7"] {
+            game.push(Move::from_str(mv).unwrap()).unwrap();
+        }
+        game.result = GameResult::WhiteWins;
+        game.annotations.insert(0, Annotation {
+            comment: Some("[%eval 0.30]".to_string()),
+            ..Default::default()
+        });
+        game.annotations.insert(3, Annotation {
+            glyph:             Some("??".to_string()),
+            comment:           Some("Blunder.".to_string()),
+            variation:         vec![
+                Move::from_str("g7g6").unwrap(),
+                Move::from_str("h5e5").unwrap(),
+            ],
+            variation_comment: Some("[%eval 0.50]".to_string()),
+        });
+
+        let pgn = game.to_string();
+        assert!(pgn.ends_with(
+            "1. e4 {[%eval 0.30]} 1... e5 2. Qh5 Nf6?? {Blunder.} (2... g6 \
+             {[%eval 0.50]} 3.\nQxe5+) 3. Qxf7+ 1-0\n"
+        ));
+        assert_eq!(parse_pgn(&pgn).unwrap()[0].moves, game.moves);
+    }
+}
@@ -3,14 +3,14 @@ use std::str::FromStr;
 
 use crate::bitboard::BitBoardType;
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Color
 {
     White,
     Black,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum PieceType
 {
     Pawn,
@@ -21,13 +21,25 @@ pub enum PieceType
     King,
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Piece
 {
     pub color: Color,
     pub piece: PieceType,
 }
 
+impl Color
+{
+    /// Returns the other color.
+    pub fn opposite(self) -> Self
+    {
+        match self {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        }
+    }
+}
+
 impl FromStr for Piece
 {
     type Err = Box<dyn std::error::Error>;
@@ -158,7 +170,7 @@ impl fmt::Debug for Piece
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
     {
-        write!(f, "{}", self.to_string())
+        write!(f, "{}", self)
     }
 }
 
@@ -0,0 +1,1824 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fmt, thread};
+
+use crate::board::BitBoardState;
+use crate::book::Book;
+use crate::eval::{Evaluator, HandcraftedEvaluator};
+use crate::movepick::{piece_to, History, MovePicker};
+use crate::moves::Move;
+use crate::piece::PieceType;
+use crate::strength::{self, Strength};
+use crate::tablebase::{Dtm, Tablebase};
+use crate::time::{Clock, SystemClock, TimeManager, DEFAULT_MOVE_OVERHEAD};
+use crate::tt::{Bound, TranspositionTable, TtEntry};
+
+/// The score of delivering mate on the spot. Mates further away score one less
+/// per ply.
+pub const MATE: i32 = 32000;
+/// The deepest the search will ever go, counting from the root.
+pub const MAX_PLY: usize = 128;
+
+const INFINITY: i32 = MATE + 1;
+/// Scores beyond this are mates.
+const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+/// How often, in nodes, to check whether the search should stop.
+const STOP_CHECK_INTERVAL: u64 = 1024;
+
+/// A search score, as reported to the user.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SearchScore
+{
+    Centipawns(i32),
+    /// Mate in the given number of moves. Negative if the side to move is the
+    /// one getting mated.
+    Mate(i32),
+}
+
+impl SearchScore
+{
+    /// Converts a score from the search into centipawns or moves to mate.
+    pub fn from_value(value: i32) -> Self
+    {
+        if value >= MATE_BOUND {
+            SearchScore::Mate((MATE - value + 1) / 2)
+        }
+        else if value <= -MATE_BOUND {
+            SearchScore::Mate(-(MATE + value + 1) / 2)
+        }
+        else {
+            SearchScore::Centipawns(value)
+        }
+    }
+}
+
+impl fmt::Display for SearchScore
+{
+    /// Formats the score as UCI does (`cp 35`, `mate -3`).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self {
+            SearchScore::Centipawns(cp) => write!(f, "cp {}", cp),
+            SearchScore::Mate(moves) => write!(f, "mate {}", moves),
+        }
+    }
+}
+
+/// When to stop searching, as given to UCI's `go` command. The search runs
+/// until told to stop if no limits are given.
+#[derive(Clone, Default)]
+pub struct SearchLimits
+{
+    /// Time left on each side's clock.
+    pub wtime:     Option<Duration>,
+    pub btime:     Option<Duration>,
+    /// Time added to each side's clock after every move.
+    pub winc:      Option<Duration>,
+    pub binc:      Option<Duration>,
+    /// Moves left until the next time control.
+    pub movestogo: Option<u32>,
+    /// Exactly how long to search for.
+    pub movetime:  Option<Duration>,
+    pub depth:     Option<u32>,
+    pub nodes:     Option<u64>,
+    /// Stop once a mate in this many moves or fewer has been found.
+    pub mate:      Option<u32>,
+    /// Ignore the clock and search until stopped.
+    pub infinite:  bool,
+    /// Search without regard to the clock until `ponderhit` is set.
+    pub ponder:    bool,
+    /// Set from another thread to stop the search early.
+    pub stop:      Arc<AtomicBool>,
+    /// Set from another thread once the move pondered on has been played.
+    pub ponderhit: Arc<AtomicBool>,
+}
+
+/// The outcome of a search.
+#[derive(Clone, Debug)]
+pub struct SearchResult
+{
+    /// The move to play, or `None` if there are no legal moves.
+    pub best_move:   Option<Move>,
+    pub score:       SearchScore,
+    /// The last depth searched to completion.
+    pub depth:       u32,
+    /// The deepest ply reached, quiescence search included.
+    pub seldepth:    usize,
+    pub nodes:       u64,
+    /// How long the search took.
+    pub time:        Duration,
+    /// The reply expected to the best move, to think about on the opponent's
+    /// time.
+    pub ponder_move: Option<Move>,
+    /// The principal variation, starting with the best move.
+    pub pv:          Vec<Move>,
+    /// The best lines found, best first, as many as asked for.
+    pub lines:       Vec<PvLine>,
+}
+
+impl SearchResult
+{
+    /// Returns the result of playing a move that's known without searching.
+    fn known(mv: Move, score: SearchScore) -> Self
+    {
+        SearchResult {
+            best_move: Some(mv),
+            score,
+            depth: 0,
+            seldepth: 0,
+            nodes: 0,
+            time: Duration::ZERO,
+            ponder_move: None,
+            pv: vec![mv],
+            lines: vec![PvLine { best_move: mv, score, pv: vec![mv] }],
+        }
+    }
+}
+
+/// One of the best lines from the root.
+#[derive(Clone, Debug)]
+pub struct PvLine
+{
+    pub best_move: Move,
+    pub score:     SearchScore,
+    pub pv:        Vec<Move>,
+}
+
+/// Progress reported after each iteration of a search.
+#[derive(Clone, Debug)]
+pub struct SearchInfo
+{
+    pub depth:    u32,
+    pub seldepth: usize,
+    /// Which line this is, counting from 1 for the best.
+    pub multipv:  usize,
+    pub score:    SearchScore,
+    pub nodes:    u64,
+    pub time:     Duration,
+    /// How full the transposition table is, in permille.
+    pub hashfull: u32,
+    pub pv:       Vec<Move>,
+}
+
+impl SearchInfo
+{
+    /// Returns the nodes searched per second.
+    pub fn nps(&self) -> u64
+    {
+        match self.time.as_millis() {
+            0 => 0,
+            millis => self.nodes * 1000 / millis as u64,
+        }
+    }
+}
+
+/// Switches for each of the ways the search skips or extends moves, so that
+/// their effect on strength can be measured.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SearchOptions
+{
+    pub null_move:            bool,
+    pub late_move_reductions: bool,
+    pub reverse_futility:     bool,
+    pub futility:             bool,
+    pub razoring:             bool,
+    pub late_move_pruning:    bool,
+    pub check_extensions:     bool,
+    pub singular_extensions:  bool,
+}
+
+impl Default for SearchOptions
+{
+    fn default() -> Self
+    {
+        SearchOptions {
+            null_move:            true,
+            late_move_reductions: true,
+            reverse_futility:     true,
+            futility:             true,
+            razoring:             true,
+            late_move_pruning:    true,
+            check_extensions:     true,
+            singular_extensions:  true,
+        }
+    }
+}
+
+impl SearchOptions
+{
+    /// Returns options with every kind of pruning, reduction and extension
+    /// turned off, for a plain alpha-beta search.
+    pub fn none() -> Self
+    {
+        SearchOptions {
+            null_move:            false,
+            late_move_reductions: false,
+            reverse_futility:     false,
+            futility:             false,
+            razoring:             false,
+            late_move_pruning:    false,
+            check_extensions:     false,
+            singular_extensions:  false,
+        }
+    }
+}
+
+/// The margins and reductions the search prunes by, so that they can be
+/// tuned.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SearchParams
+{
+    /// Late moves are reduced by `lmr_base + ln(depth) * ln(moves) /
+    /// lmr_divisor` plies.
+    pub lmr_base:                f64,
+    pub lmr_divisor:             f64,
+    /// How far above beta the static evaluation must be, per ply, to prune.
+    pub reverse_futility_margin: i32,
+    /// How far below alpha the static evaluation must be to skip quiet
+    /// moves, as a base plus an amount per ply.
+    pub futility_base:           i32,
+    pub futility_margin:         i32,
+    /// The null move search is reduced by `null_move_base + depth /
+    /// null_move_divisor` plies.
+    pub null_move_base:          i32,
+    pub null_move_divisor:       i32,
+    /// The half width of the first aspiration window, in centipawns.
+    pub aspiration_window:       i32,
+}
+
+impl Default for SearchParams
+{
+    fn default() -> Self
+    {
+        SearchParams {
+            lmr_base:                0.75,
+            lmr_divisor:             2.25,
+            reverse_futility_margin: 80,
+            futility_base:           100,
+            futility_margin:         100,
+            null_move_base:          3,
+            null_move_divisor:       4,
+            aspiration_window:       25,
+        }
+    }
+}
+
+impl SearchParams
+{
+    /// Returns the late move reductions, by depth and number of moves
+    /// searched.
+    fn reductions(&self) -> Vec<[i32; 64]>
+    {
+        (0..64)
+            .map(|depth| {
+                let mut row = [0; 64];
+                for (moves, reduction) in row.iter_mut().enumerate().skip(1) {
+                    let depth = (depth as f64).max(1.0).ln();
+                    *reduction = (self.lmr_base
+                        + depth * (moves as f64).ln() / self.lmr_divisor)
+                        as i32;
+                }
+                row
+            })
+            .collect()
+    }
+}
+
+/// Searches positions for the best move.
+pub struct Searcher
+{
+    pub options: SearchOptions,
+    params:      SearchParams,
+    evaluator:   Arc<dyn Evaluator + Send + Sync>,
+    tt:          Arc<TranspositionTable>,
+    /// Move ordering history for each thread, with the main thread first.
+    histories:   Vec<History>,
+    /// How many of the best lines to search for.
+    multi_pv:    usize,
+    /// The settings to play weaker with, if any.
+    strength:    Option<Strength>,
+    /// How much worse than even a draw is for the side to move at the root,
+    /// in centipawns.
+    contempt:    i32,
+    /// Time kept back on every move for communication delays.
+    overhead:    Duration,
+    /// Moves to play without searching early in the game.
+    book:        Option<Book>,
+    /// Moves to play without searching once few pieces are left.
+    tablebase:   Option<Tablebase>,
+    /// Late move reductions, by depth and number of moves searched.
+    reductions:  Vec<[i32; 64]>,
+    clock:       Arc<dyn Clock>,
+}
+
+impl Default for Searcher
+{
+    fn default() -> Self
+    {
+        Searcher::new(Arc::new(HandcraftedEvaluator::default()))
+    }
+}
+
+impl Searcher
+{
+    pub fn new(evaluator: Arc<dyn Evaluator + Send + Sync>) -> Self
+    {
+        let params = SearchParams::default();
+
+        Searcher {
+            options: SearchOptions::default(),
+            params,
+            evaluator,
+            tt: Arc::new(TranspositionTable::default()),
+            histories: vec![History::default()],
+            multi_pv: 1,
+            strength: None,
+            contempt: 0,
+            overhead: DEFAULT_MOVE_OVERHEAD,
+            book: None,
+            tablebase: None,
+            reductions: params.reductions(),
+            clock: Arc::new(SystemClock::default()),
+        }
+    }
+
+    pub fn params(&self) -> SearchParams { self.params }
+
+    /// Changes the margins and reductions the search prunes by.
+    pub fn set_params(&mut self, params: SearchParams)
+    {
+        self.params = params;
+        self.reductions = params.reductions();
+    }
+
+    /// Replaces the evaluation, as when loading a network.
+    pub fn set_evaluator(&mut self, evaluator: Arc<dyn Evaluator + Send + Sync>)
+    {
+        self.evaluator = evaluator;
+    }
+
+    /// Returns the transposition table, for reporting how full it is.
+    pub fn tt(&self) -> &TranspositionTable { &self.tt }
+
+    /// Replaces the transposition table with an empty one of the given size.
+    pub fn set_hash_size(&mut self, size_mb: usize)
+    {
+        self.tt = Arc::new(TranspositionTable::new(size_mb));
+    }
+
+    /// Replaces the clock used to time searches.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) { self.clock = clock; }
+
+    /// Forgets everything learned from previous searches, as when starting a
+    /// new game.
+    pub fn clear(&mut self)
+    {
+        self.tt.clear();
+        for history in self.histories.iter_mut() {
+            history.clear();
+        }
+    }
+
+    pub fn threads(&self) -> usize { self.histories.len() }
+
+    pub fn multi_pv(&self) -> usize { self.multi_pv }
+
+    /// Sets how many of the best lines to search for, rather than just the
+    /// best move.
+    pub fn set_multi_pv(&mut self, lines: usize)
+    {
+        self.multi_pv = lines.max(1);
+    }
+
+    pub fn strength(&self) -> Option<Strength> { self.strength }
+
+    /// Plays weaker, or at full strength given `None`.
+    ///
+    /// The search is cut short, the evaluation made noisy, and the move to
+    /// play picked at random from the best few, favouring the better ones and
+    /// leaving out any that give material away.
+    pub fn set_strength(&mut self, strength: Option<Strength>)
+    {
+        self.strength = strength;
+    }
+
+    /// Makes the search avoid draws when positive, and seek them when
+    /// negative.
+    pub fn set_contempt(&mut self, contempt: i32) { self.contempt = contempt; }
+
+    /// Sets the time kept back on every move for communication delays.
+    pub fn set_move_overhead(&mut self, overhead: Duration)
+    {
+        self.overhead = overhead;
+    }
+
+    /// Plays moves from a book, when it has them, unless searching without
+    /// limits.
+    pub fn set_book(&mut self, book: Option<Book>) { self.book = book; }
+
+    /// Plays perfectly in positions covered by the tablebase.
+    pub fn set_tablebase(&mut self, tablebase: Option<Tablebase>)
+    {
+        self.tablebase = tablebase;
+    }
+
+    /// Sets how many threads to search with, counting the main thread.
+    pub fn set_threads(&mut self, threads: usize)
+    {
+        self.histories.resize_with(threads.max(1), History::default);
+    }
+
+    /// Searches a position by iterative deepening until one of the limits is
+    /// reached, returning the result of the last completed iteration.
+    pub fn search(
+        &mut self, board: &BitBoardState, limits: &SearchLimits,
+    ) -> SearchResult
+    {
+        self.search_with_info(board, limits, &mut |_| ())
+    }
+
+    /// Searches like `search`, reporting progress after every iteration.
+    ///
+    /// With more than one thread, helpers search the same position alongside
+    /// the main thread, sharing what they find through the transposition
+    /// table. Only the main thread watches the clock and reports progress.
+    pub fn search_with_info(
+        &mut self, board: &BitBoardState, limits: &SearchLimits,
+        on_info: &mut dyn FnMut(&SearchInfo),
+    ) -> SearchResult
+    {
+        let weakened;
+        let limits = match self.strength {
+            Some(strength) => {
+                weakened = strength.limit(limits);
+                &weakened
+            },
+            None => limits,
+        };
+        let seed = strength::random_seed();
+        let noise = self.strength.map_or(0, |strength| strength.noise);
+        let candidates =
+            self.strength.map_or(1, |strength| strength.candidates);
+
+        if let Some(result) = self.look_up(board, limits, on_info) {
+            return result;
+        }
+
+        let time = TimeManager::new(
+            limits,
+            board.turn(),
+            self.clock.clone(),
+            self.overhead,
+        );
+        let done = AtomicBool::new(false);
+        let nodes: Vec<AtomicU64> =
+            self.histories.iter().map(|_| AtomicU64::new(0)).collect();
+        self.tt.new_search();
+
+        let max_depth = limits
+            .depth
+            .unwrap_or(MAX_PLY as u32 - 1)
+            .clamp(1, MAX_PLY as u32 - 1);
+
+        let shared = Shared {
+            time: &time,
+            evaluator: self.evaluator.as_ref(),
+            tt: &self.tt,
+            limits,
+            done: &done,
+            nodes: &nodes,
+            multi_pv: self.multi_pv.max(candidates),
+            noise,
+            seed,
+            contempt: self.contempt,
+            options: self.options,
+            params: self.params,
+            reductions: &self.reductions,
+        };
+        let (main, helpers) = self.histories.split_first_mut().unwrap();
+        // lines searched only as candidates for a weaker move aren't reported
+        let shown = self.multi_pv;
+
+        let mut results = thread::scope(|scope| {
+            let helpers: Vec<_> = helpers
+                .iter_mut()
+                .enumerate()
+                .map(|(i, history)| {
+                    let shared = &shared;
+                    scope.spawn(move || {
+                        Search::new(shared, history, i + 1).iterate(
+                            board,
+                            max_depth,
+                            &mut |_| (),
+                        )
+                    })
+                })
+                .collect();
+
+            let main = Search::new(&shared, main, 0).iterate(
+                board,
+                max_depth,
+                &mut |info| {
+                    if info.multipv <= shown {
+                        on_info(info)
+                    }
+                },
+            );
+            done.store(true, Ordering::Relaxed);
+
+            let mut results = vec![main];
+            for helper in helpers {
+                results.push(helper.join().expect("helper thread panicked!"));
+            }
+            results
+        });
+
+        let best = vote(&results);
+        let (mut result, _) = results.swap_remove(best);
+        result.nodes = total_nodes(&nodes);
+        result.time = time.elapsed();
+        if let Some(strength) = self.strength {
+            // a weaker move may miss a win of material, but doesn't give any
+            // away to a plain exchange, unless every move does
+            let safe: Vec<PvLine> = result
+                .lines
+                .iter()
+                .filter(|line| match line.score {
+                    SearchScore::Mate(moves) => moves > 0,
+                    _ => board.see_ge(line.best_move, 0),
+                })
+                .cloned()
+                .collect();
+            let lines = match safe.is_empty() {
+                true => &result.lines,
+                false => &safe,
+            };
+            let line = lines.get(strength.choose(lines, seed));
+            if let Some(line) = line.cloned() {
+                result.best_move = Some(line.best_move);
+                result.score = line.score;
+                result.ponder_move = line.pv.get(1).copied();
+                result.pv = line.pv;
+            }
+            result.lines.truncate(self.multi_pv);
+        }
+        if result.best_move.is_none() {
+            result.best_move = self.fallback_move(board);
+            result.pv = result.best_move.into_iter().collect();
+        }
+        if result.ponder_move.is_none() {
+            result.ponder_move = self.expected_reply(board, result.best_move);
+        }
+        result
+    }
+
+    /// Returns the move from the book or the tablebase, if either has one for
+    /// the position, so that there's no need to search.
+    fn look_up(
+        &self, board: &BitBoardState, limits: &SearchLimits,
+        on_info: &mut dyn FnMut(&SearchInfo),
+    ) -> Option<SearchResult>
+    {
+        // analysis should show what the engine thinks, not what's in a book
+        let book_move = match (&self.book, limits.infinite) {
+            (Some(book), false) => book.probe(board, strength::random_seed()),
+            _ => None,
+        };
+        if let Some(mv) = book_move {
+            return Some(SearchResult::known(mv, SearchScore::Centipawns(0)));
+        }
+
+        let (mv, dtm) = self.tablebase.as_ref()?.best_move(board)?;
+        let score = match dtm {
+            Dtm::Win(plies) => SearchScore::Mate((plies as i32 + 1) / 2),
+            Dtm::Loss(plies) => SearchScore::Mate(-(plies as i32) / 2),
+            Dtm::Draw => SearchScore::Centipawns(0),
+        };
+        let result = SearchResult::known(mv, score);
+        on_info(&SearchInfo {
+            depth: 1,
+            seldepth: 1,
+            multipv: 1,
+            score,
+            nodes: 0,
+            time: Duration::ZERO,
+            hashfull: self.tt.hashfull(),
+            pv: result.pv.clone(),
+        });
+        Some(result)
+    }
+
+    /// Returns the move from the transposition table, or else the first legal
+    /// move, for when the search found none. There's none only when the game
+    /// is over.
+    fn fallback_move(&self, board: &BitBoardState) -> Option<Move>
+    {
+        let legal_moves = board.legal_moves();
+        let table_move =
+            self.tt.probe(board.hash(), 0).and_then(|e| e.best_move);
+        match table_move {
+            Some(mv) if legal_moves.contains(&mv) => Some(mv),
+            _ => legal_moves.first().copied(),
+        }
+    }
+
+    /// Looks up the reply to a move in the transposition table, for when the
+    /// search was stopped before it had one.
+    fn expected_reply(
+        &self, board: &BitBoardState, mv: Option<Move>,
+    ) -> Option<Move>
+    {
+        let mut board = board.clone();
+        board.make_move(mv?);
+
+        let reply = self.tt.probe(board.hash(), 0)?.best_move?;
+        board.legal_moves().contains(&reply).then_some(reply)
+    }
+}
+
+/// What every thread of a search shares.
+struct Shared<'a>
+{
+    time:       &'a TimeManager,
+    evaluator:  &'a (dyn Evaluator + Send + Sync),
+    tt:         &'a TranspositionTable,
+    limits:     &'a SearchLimits,
+    /// Set once the main thread has finished, to stop the helpers.
+    done:       &'a AtomicBool,
+    /// The nodes searched by each thread so far.
+    nodes:      &'a [AtomicU64],
+    multi_pv:   usize,
+    /// The most each evaluation is randomly off by, and the seed for it.
+    noise:      i32,
+    seed:       u64,
+    contempt:   i32,
+    options:    SearchOptions,
+    params:     SearchParams,
+    reductions: &'a [[i32; 64]],
+}
+
+/// The state of a single search thread.
+struct Search<'a>
+{
+    time:          &'a TimeManager,
+    evaluator:     &'a (dyn Evaluator + Send + Sync),
+    tt:            &'a TranspositionTable,
+    limits:        &'a SearchLimits,
+    done:          &'a AtomicBool,
+    node_counts:   &'a [AtomicU64],
+    /// The index of this thread, with the main thread first.
+    id:            usize,
+    nodes:         u64,
+    seldepth:      usize,
+    stopped:       bool,
+    /// The principal variation found from each ply.
+    pv:            Vec<Vec<Move>>,
+    /// The principal variation of the previous iteration, searched first.
+    previous_pv:   Vec<Move>,
+    follow_pv:     bool,
+    history:       &'a mut History,
+    /// The continuation of the move played at each ply.
+    stack:         Vec<Option<usize>>,
+    /// The static evaluation at each ply.
+    evals:         Vec<i32>,
+    /// The move left out at each ply while testing for a singular move.
+    excluded:      Vec<Option<Move>>,
+    /// The root moves of the lines already found in this iteration.
+    root_excluded: Vec<Move>,
+    multi_pv:      usize,
+    noise:         i32,
+    seed:          u64,
+    contempt:      i32,
+    options:       SearchOptions,
+    params:        SearchParams,
+    reductions:    &'a [[i32; 64]],
+    root_depth:    i32,
+}
+
+impl<'a> Search<'a>
+{
+    fn new(shared: &Shared<'a>, history: &'a mut History, id: usize) -> Self
+    {
+        Search {
+            time: shared.time,
+            evaluator: shared.evaluator,
+            tt: shared.tt,
+            limits: shared.limits,
+            done: shared.done,
+            node_counts: shared.nodes,
+            id,
+            nodes: 0,
+            seldepth: 0,
+            stopped: false,
+            pv: vec![Vec::new(); MAX_PLY + 1],
+            previous_pv: Vec::new(),
+            follow_pv: false,
+            history,
+            stack: vec![None; MAX_PLY + 1],
+            evals: vec![0; MAX_PLY + 1],
+            excluded: vec![None; MAX_PLY + 1],
+            root_excluded: Vec::new(),
+            multi_pv: shared.multi_pv,
+            noise: shared.noise,
+            seed: shared.seed,
+            contempt: shared.contempt,
+            options: shared.options,
+            params: shared.params,
+            reductions: shared.reductions,
+            root_depth: 0,
+        }
+    }
+
+    /// Searches by iterative deepening until stopped, returning the result of
+    /// the last completed iteration along with its score.
+    fn iterate(
+        &mut self, board: &BitBoardState, max_depth: u32,
+        on_info: &mut dyn FnMut(&SearchInfo),
+    ) -> (SearchResult, i32)
+    {
+        let mut board = board.clone();
+        self.evaluator.prepare(&mut board);
+        let main = self.id == 0;
+
+        let mut result = SearchResult {
+            best_move:   None,
+            score:       SearchScore::Centipawns(0),
+            depth:       0,
+            seldepth:    0,
+            nodes:       0,
+            time:        Duration::ZERO,
+            ponder_move: None,
+            pv:          Vec::new(),
+            lines:       Vec::new(),
+        };
+        let mut score = 0;
+        let mut best_move_changes = 0.0;
+
+        // the score and principal variation of each line, best first
+        let mut lines: Vec<(i32, Vec<Move>)> = Vec::new();
+        let root_moves = board.legal_moves().len();
+        let line_count = self.multi_pv.clamp(1, root_moves.max(1));
+
+        // there's no point thinking about a forced move when short of time
+        let forced = self.time.is_timed() && root_moves == 1;
+
+        // every other helper searches a ply deeper, to spread the threads out
+        let offset = self.id as u32 % 2;
+
+        for depth in 1..=max_depth {
+            let depth = (depth + offset).min(max_depth);
+            if depth <= result.depth {
+                break;
+            }
+            let previous_score = score;
+            let previous_best = result.best_move;
+
+            self.seldepth = 0;
+            self.root_depth = depth as i32;
+            self.root_excluded.clear();
+
+            // each line is searched without the moves of the lines before it
+            let mut current = Vec::new();
+            for line in 0..line_count {
+                let (previous, pv) =
+                    lines.get(line).cloned().unwrap_or((score, Vec::new()));
+                self.pv[0] = pv;
+                let line_score =
+                    self.aspiration(&mut board, depth as i32, previous);
+
+                // a partial line can't be trusted, unless there's nothing else
+                if self.stopped && (result.depth > 0 || !current.is_empty()) {
+                    break;
+                }
+
+                let pv = self.pv[0].clone();
+                let best_move = pv.first().copied();
+                current.push((line_score, pv));
+                match best_move {
+                    Some(mv) if !self.stopped => self.root_excluded.push(mv),
+                    _ => break,
+                }
+            }
+
+            if self.stopped && result.depth > 0 {
+                break;
+            }
+
+            current.sort_by_key(|(score, _)| -score);
+            lines = current;
+            score = lines[0].0;
+
+            result.pv = lines[0].1.clone();
+            result.best_move = result.pv.first().copied();
+            result.ponder_move = result.pv.get(1).copied();
+            result.score = SearchScore::from_value(score);
+            result.depth = depth;
+            result.seldepth = self.seldepth;
+            result.lines = lines
+                .iter()
+                .filter(|(_, pv)| !pv.is_empty())
+                .map(|(score, pv)| PvLine {
+                    best_move: pv[0],
+                    score:     SearchScore::from_value(*score),
+                    pv:        pv.clone(),
+                })
+                .collect();
+
+            if main {
+                self.node_counts[0].store(self.nodes, Ordering::Relaxed);
+                let nodes = total_nodes(self.node_counts);
+                let time = self.time.elapsed();
+                let hashfull = self.tt.hashfull();
+
+                for (i, (score, pv)) in lines.iter().enumerate() {
+                    on_info(&SearchInfo {
+                        depth: result.depth,
+                        seldepth: result.seldepth,
+                        multipv: i + 1,
+                        score: SearchScore::from_value(*score),
+                        nodes,
+                        time,
+                        hashfull,
+                        pv: pv.clone(),
+                    });
+                }
+            }
+
+            if self.stopped || result.best_move.is_none() {
+                break;
+            }
+            if !main {
+                continue;
+            }
+
+            if forced {
+                break;
+            }
+
+            let mate_found = match (self.limits.mate, result.score) {
+                (Some(limit), SearchScore::Mate(moves)) =>
+                    moves > 0 && moves as u32 <= limit,
+                _ => false,
+            };
+            if mate_found {
+                break;
+            }
+
+            best_move_changes /= 2.0;
+            if depth > 1 && result.best_move != previous_best {
+                best_move_changes += 1.0;
+            }
+            if self.time.should_stop(best_move_changes, previous_score - score)
+            {
+                break;
+            }
+        }
+
+        self.node_counts[self.id].store(self.nodes, Ordering::Relaxed);
+        (result, score)
+    }
+}
+
+impl Search<'_>
+{
+    /// Evaluates a position statically, with noise when playing weaker.
+    fn evaluate(&self, board: &BitBoardState) -> i32
+    {
+        let eval = self.evaluator.evaluate(board);
+        eval + strength::noise(board.hash(), self.seed, self.noise)
+    }
+
+    /// Returns the score of a draw, which the side to move at the root thinks
+    /// less of with contempt.
+    fn draw_score(&self, ply: usize) -> i32
+    {
+        match ply % 2 {
+            0 => -self.contempt,
+            _ => self.contempt,
+        }
+    }
+
+    /// Searches to the given depth, starting with a narrow window around the
+    /// previous score and widening it on failure.
+    fn aspiration(
+        &mut self, board: &mut BitBoardState, depth: i32, previous: i32,
+    ) -> i32
+    {
+        let mut delta = self.params.aspiration_window;
+        let (mut alpha, mut beta) = match depth {
+            1..=3 => (-INFINITY, INFINITY),
+            _ => (
+                (previous - delta).max(-INFINITY),
+                (previous + delta).min(INFINITY),
+            ),
+        };
+
+        loop {
+            self.previous_pv = self.pv[0].clone();
+            self.follow_pv = true;
+
+            let score = self.negamax(board, depth, alpha, beta, 0);
+            if self.stopped {
+                return score;
+            }
+
+            if score <= alpha {
+                alpha = (score - delta).max(-INFINITY);
+                self.pv[0] = self.previous_pv.clone();
+            }
+            else if score >= beta {
+                beta = (score + delta).min(INFINITY);
+            }
+            else {
+                return score;
+            }
+
+            delta *= 2;
+        }
+    }
+
+    fn negamax(
+        &mut self, board: &mut BitBoardState, depth: i32, mut alpha: i32,
+        mut beta: i32, ply: usize,
+    ) -> i32
+    {
+        if depth <= 0 {
+            return self.quiescence(board, alpha, beta, ply);
+        }
+
+        self.pv[ply].clear();
+        if self.should_stop() {
+            return 0;
+        }
+        self.nodes += 1;
+
+        if ply > 0 {
+            if board.is_draw() {
+                return self.draw_score(ply);
+            }
+            if ply >= MAX_PLY {
+                return self.evaluate(board);
+            }
+
+            // no line from here can beat a mate that's already been found
+            alpha = alpha.max(-MATE + ply as i32);
+            beta = beta.min(MATE - ply as i32 - 1);
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+
+        // while testing whether a move is singular, the position is searched
+        // again without it, so the table's result for it is no use
+        let excluded = self.excluded[ply];
+        let pv_node = beta - alpha > 1;
+        let entry = match excluded {
+            Some(_) => None,
+            None => self.tt.probe(board.hash(), ply),
+        };
+        if let Some(entry) = entry {
+            if !pv_node && entry.depth >= depth && cuts_off(&entry, alpha, beta)
+            {
+                return entry.score;
+            }
+        }
+
+        let (options, params) = (self.options, self.params);
+        let in_check = board.in_check();
+        let eval = match in_check {
+            true => -INFINITY,
+            false => self.evaluate(board),
+        };
+        self.evals[ply] = eval;
+        let improving = !in_check && ply >= 2 && eval > self.evals[ply - 2];
+
+        if !pv_node && !in_check && excluded.is_none() {
+            // far enough above beta that no move is likely to bring it back
+            if options.reverse_futility
+                && depth <= 8
+                && eval.abs() < MATE_BOUND
+                && eval
+                    - params.reverse_futility_margin
+                        * (depth - improving as i32)
+                    >= beta
+            {
+                return eval;
+            }
+
+            // so far below alpha that only captures could help
+            if options.razoring
+                && depth <= 3
+                && eval + 300 + 250 * depth < alpha
+            {
+                let score = self.quiescence(board, alpha, alpha + 1, ply);
+                if score <= alpha {
+                    return score;
+                }
+            }
+
+            // if passing still fails high then a real move surely would, as
+            // long as the side to move isn't in zugzwang, which is unlikely
+            // with pieces on the board
+            if options.null_move
+                && depth >= 3
+                && eval >= beta
+                && board.last_move().is_some()
+                && has_non_pawn_material(board)
+            {
+                let reduction = params.null_move_base
+                    + depth / params.null_move_divisor.max(1);
+                self.stack[ply] = None;
+                board.make_null_move();
+                let score = -self.negamax(
+                    board,
+                    depth - 1 - reduction,
+                    -beta,
+                    -beta + 1,
+                    ply + 1,
+                );
+                board.unmake_null_move();
+
+                if self.stopped {
+                    return 0;
+                }
+                if score >= beta {
+                    return if score >= MATE_BOUND { beta } else { score };
+                }
+            }
+        }
+
+        let pv_move = match self.follow_pv {
+            true => self.previous_pv.get(ply).copied(),
+            false => None,
+        };
+        let hash_move = entry.and_then(|entry| entry.best_move);
+        let continuations = self.continuations(ply);
+        let killers = self.history.killers(ply);
+        let mut picker = MovePicker::new(
+            board,
+            pv_move.or(hash_move),
+            killers,
+            self.history.countermove(continuations[0]),
+            continuations,
+        );
+
+        // a move from the table that fails high is singular if every other
+        // move falls well short of it
+        let singular = entry.filter(|entry| {
+            options.singular_extensions
+                && ply > 0
+                && depth >= 8
+                && excluded.is_none()
+                && entry.best_move.is_some()
+                && entry.bound != Bound::Upper
+                && entry.depth >= depth - 3
+                && entry.score.abs() < MATE_BOUND
+        });
+
+        let original_alpha = alpha;
+        let mut best = -INFINITY;
+        let mut best_move = None;
+        let mut quiets_tried = Vec::new();
+        let mut legal_moves = 0;
+        let mut searched = 0;
+
+        while let Some(mv) = picker.next(board, self.history) {
+            if Some(mv) == excluded
+                || (ply == 0 && self.root_excluded.contains(&mv))
+                || !board.is_legal(mv)
+            {
+                continue;
+            }
+            legal_moves += 1;
+            let quiet = !board.is_noisy(mv);
+
+            if !pv_node && quiet && !in_check && best > -MATE_BOUND {
+                let late = options.late_move_pruning
+                    && depth <= 8
+                    && quiets_tried.len() as i32
+                        >= (3 + depth * depth) / (2 - improving as i32);
+                let futile = options.futility
+                    && depth <= 6
+                    && eval
+                        + params.futility_base
+                        + params.futility_margin * depth
+                        <= alpha;
+
+                if late || futile {
+                    picker.skip_quiets();
+                    continue;
+                }
+            }
+
+            let mut extension = 0;
+            if let Some(entry) = singular.filter(|e| e.best_move == Some(mv)) {
+                let singular_beta = entry.score - 2 * depth;
+                let follow_pv = self.follow_pv;
+
+                self.excluded[ply] = Some(mv);
+                let score = self.negamax(
+                    board,
+                    (depth - 1) / 2,
+                    singular_beta - 1,
+                    singular_beta,
+                    ply,
+                );
+                self.excluded[ply] = None;
+                self.follow_pv = follow_pv;
+
+                if self.stopped {
+                    return 0;
+                }
+                if score < singular_beta {
+                    extension = 1;
+                }
+            }
+
+            self.follow_pv = self.follow_pv && Some(mv) == pv_move;
+            self.play(board, mv, ply);
+            searched += 1;
+
+            let gives_check = board.in_check();
+            if options.check_extensions
+                && gives_check
+                && (ply as i32) < 2 * self.root_depth
+            {
+                extension = 1;
+            }
+            let new_depth = depth - 1 + extension;
+
+            let score = if searched == 1 {
+                -self.negamax(board, new_depth, -beta, -alpha, ply + 1)
+            }
+            else {
+                // quiet moves late in the ordering are searched less deeply
+                let mut reduction = 0;
+                if options.late_move_reductions
+                    && depth >= 3
+                    && quiet
+                    && !in_check
+                    && !gives_check
+                {
+                    reduction = self.reductions[depth.min(63) as usize]
+                        [searched.min(63)];
+                    reduction -= pv_node as i32;
+                    reduction += !improving as i32;
+                    reduction -= killers.contains(&Some(mv)) as i32;
+                    reduction = reduction.clamp(0, new_depth - 1);
+                }
+
+                // prove the move is worse with a null window, then search it
+                // properly if that fails
+                let mut score = -self.negamax(
+                    board,
+                    new_depth - reduction,
+                    -alpha - 1,
+                    -alpha,
+                    ply + 1,
+                );
+                if score > alpha && reduction > 0 {
+                    score = -self.negamax(
+                        board,
+                        new_depth,
+                        -alpha - 1,
+                        -alpha,
+                        ply + 1,
+                    );
+                }
+                if score > alpha && score < beta {
+                    score =
+                        -self.negamax(board, new_depth, -beta, -alpha, ply + 1);
+                }
+                score
+            };
+            board.unmake_move();
+
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    best_move = Some(mv);
+                    self.update_pv(ply, mv);
+                    if alpha >= beta {
+                        if quiet {
+                            self.history.update(
+                                board,
+                                ply,
+                                mv,
+                                &quiets_tried,
+                                depth,
+                                continuations,
+                            );
+                        }
+                        break;
+                    }
+                }
+            }
+            if quiet {
+                quiets_tried.push(mv);
+            }
+        }
+
+        if legal_moves == 0 {
+            return match (excluded, in_check) {
+                (Some(_), _) => alpha,
+                (None, true) => -MATE + ply as i32,
+                (None, false) => self.draw_score(ply),
+            };
+        }
+
+        // nor can it use a root score that leaves out the best moves
+        if excluded.is_none() && (ply > 0 || self.root_excluded.is_empty()) {
+            let bound = bound(best, original_alpha, beta);
+            self.tt.store(board.hash(), ply, depth, bound, best, best_move);
+        }
+
+        best
+    }
+
+    /// Searches captures and promotions until the position is quiet, so that
+    /// the evaluation isn't taken in the middle of an exchange.
+    fn quiescence(
+        &mut self, board: &mut BitBoardState, mut alpha: i32, beta: i32,
+        ply: usize,
+    ) -> i32
+    {
+        self.pv[ply].clear();
+        if self.should_stop() {
+            return 0;
+        }
+        self.nodes += 1;
+        self.seldepth = self.seldepth.max(ply);
+
+        if board.is_draw() {
+            return self.draw_score(ply);
+        }
+        if ply >= MAX_PLY {
+            return self.evaluate(board);
+        }
+
+        let pv_node = beta - alpha > 1;
+        let entry = self.tt.probe(board.hash(), ply);
+        if let Some(entry) = entry {
+            if !pv_node && cuts_off(&entry, alpha, beta) {
+                return entry.score;
+            }
+        }
+
+        // every move is tried when in check, as standing pat isn't an option
+        let in_check = board.in_check();
+        let mut best = -INFINITY;
+        if !in_check {
+            best = self.evaluate(board);
+            if best >= beta {
+                return best;
+            }
+            alpha = alpha.max(best);
+        }
+
+        let hash_move = entry.and_then(|entry| entry.best_move);
+        let mut picker = MovePicker::quiescence(board, hash_move, in_check);
+
+        let original_alpha = alpha;
+        let mut best_move = None;
+        let mut legal_moves = 0;
+
+        while let Some(mv) = picker.next(board, self.history) {
+            if !board.is_legal(mv) {
+                continue;
+            }
+            legal_moves += 1;
+
+            self.play(board, mv, ply);
+            let score = -self.quiescence(board, -beta, -alpha, ply + 1);
+            board.unmake_move();
+
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    best_move = Some(mv);
+                    self.update_pv(ply, mv);
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if in_check && legal_moves == 0 {
+            return -MATE + ply as i32;
+        }
+
+        let bound = bound(best, original_alpha, beta);
+        self.tt.store(board.hash(), ply, 0, bound, best, best_move);
+
+        best
+    }
+
+    /// Plays a move, remembering which piece made it for the continuation
+    /// histories.
+    fn play(&mut self, board: &mut BitBoardState, mv: Move, ply: usize)
+    {
+        self.stack[ply] = board
+            .piece_at(mv.from as usize)
+            .map(|piece| piece_to(piece, mv.to));
+        board.make_move(mv);
+    }
+
+    /// Returns the continuations of the moves one and two plies before this
+    /// one.
+    fn continuations(&self, ply: usize) -> [Option<usize>; 2]
+    {
+        let previous = |back: usize| match ply.checked_sub(back) {
+            Some(ply) => self.stack[ply],
+            None => None,
+        };
+
+        [previous(1), previous(2)]
+    }
+
+    /// Makes a move followed by the principal variation of its reply the
+    /// principal variation at this ply.
+    fn update_pv(&mut self, ply: usize, mv: Move)
+    {
+        let (head, tail) = self.pv.split_at_mut(ply + 1);
+        head[ply].clear();
+        head[ply].push(mv);
+        head[ply].extend_from_slice(&tail[0]);
+    }
+
+    fn should_stop(&mut self) -> bool
+    {
+        let main = self.id == 0;
+        // the first iteration is always finished, so that there's a move to
+        // play however little time or how few nodes there are
+        if main && self.root_depth <= 1 {
+            return false;
+        }
+        if self.nodes.is_multiple_of(STOP_CHECK_INTERVAL) {
+            self.node_counts[self.id].store(self.nodes, Ordering::Relaxed);
+            self.stopped |= self.limits.stop.load(Ordering::Relaxed)
+                || self.done.load(Ordering::Relaxed)
+                || (main && self.time.out_of_time());
+        }
+        // helpers stop along with the main thread, which keeps to the limit
+        if main && self.limits.nodes.is_some_and(|nodes| self.nodes >= nodes) {
+            self.stopped = true;
+        }
+
+        self.stopped
+    }
+}
+
+/// Returns the nodes searched so far by all threads.
+fn total_nodes(nodes: &[AtomicU64]) -> u64
+{
+    nodes.iter().map(|nodes| nodes.load(Ordering::Relaxed)).sum()
+}
+
+/// Picks the thread whose move to play, given each thread's result and score.
+///
+/// Each thread votes for its move, with a weight growing with the depth it
+/// reached and how well it thinks the move scores. Among threads playing the
+/// same move the deepest is trusted most, and a proven mate beats any vote.
+fn vote(results: &[(SearchResult, i32)]) -> usize
+{
+    let min_score = results.iter().map(|(_, score)| *score).min().unwrap();
+    let mut votes: HashMap<Move, i64> = HashMap::new();
+    for (result, score) in results {
+        if let Some(mv) = result.best_move {
+            *votes.entry(mv).or_default() +=
+                (score - min_score + 20) as i64 * result.depth as i64;
+        }
+    }
+
+    let mut best = 0;
+    for (i, (result, score)) in results.iter().enumerate().skip(1) {
+        let (best_result, best_score) = &results[best];
+        let mv = match result.best_move {
+            Some(mv) if result.depth > 0 => mv,
+            _ => continue,
+        };
+
+        let better = match (*best_score >= MATE_BOUND, *score >= MATE_BOUND) {
+            (true, _) => score > best_score,
+            (false, true) => true,
+            (false, false) => {
+                let best_votes =
+                    best_result.best_move.map_or(0, |mv| votes[&mv]);
+                votes[&mv] > best_votes
+                    || (votes[&mv] == best_votes
+                        && result.depth > best_result.depth)
+            },
+        };
+        if better {
+            best = i;
+        }
+    }
+
+    best
+}
+
+/// Returns true if the side to move has anything besides pawns and its king.
+fn has_non_pawn_material(board: &BitBoardState) -> bool
+{
+    let pawns_and_kings = board.pieces_of_type(PieceType::Pawn)
+        | board.pieces_of_type(PieceType::King);
+
+    board.color_occupancy(board.turn()) & !pawns_and_kings != 0
+}
+
+/// Returns true if a stored score settles the search of a window.
+fn cuts_off(entry: &TtEntry, alpha: i32, beta: i32) -> bool
+{
+    match entry.bound {
+        Bound::Exact => true,
+        Bound::Lower => entry.score >= beta,
+        Bound::Upper => entry.score <= alpha,
+    }
+}
+
+/// Returns how a score relates to the true one, given the window it was
+/// searched with.
+fn bound(score: i32, alpha: i32, beta: i32) -> Bound
+{
+    if score >= beta {
+        Bound::Lower
+    }
+    else if score > alpha {
+        Bound::Exact
+    }
+    else {
+        Bound::Upper
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::board::GameState;
+    use crate::time::MockClock;
+
+    fn search(fen: &str, depth: u32) -> SearchResult
+    {
+        let board = BitBoardState::from_fen(fen).unwrap();
+        let limits = SearchLimits { depth: Some(depth), ..Default::default() };
+
+        Searcher::default().search(&board, &limits)
+    }
+
+    #[test]
+    fn test_score_from_value()
+    {
+        assert_eq!(SearchScore::from_value(35), SearchScore::Centipawns(35));
+        assert_eq!(SearchScore::from_value(MATE - 1), SearchScore::Mate(1));
+        assert_eq!(SearchScore::from_value(MATE - 3), SearchScore::Mate(2));
+        assert_eq!(SearchScore::from_value(-MATE + 2), SearchScore::Mate(-1));
+        assert_eq!(SearchScore::Mate(-1).to_string(), "mate -1");
+    }
+
+    #[test]
+    fn test_finds_mates()
+    {
+        let result = search("k7/8/8/8/8/8/1R6/2R3K1 w - - 0 1", 3);
+        assert_eq!(result.best_move, Some(Move::from_str("c1a1").unwrap()));
+        assert_eq!(result.score, SearchScore::Mate(1));
+
+        let result = search("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", 5);
+        assert_eq!(result.score, SearchScore::Mate(2));
+        assert_eq!(result.pv.len(), 3);
+
+        let result = search("7k/R7/8/8/8/8/8/1R4K1 b - - 0 1", 4);
+        assert_eq!(result.score, SearchScore::Mate(-1));
+    }
+
+    #[test]
+    fn test_takes_hanging_material()
+    {
+        let result = search("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1", 4);
+        assert_eq!(result.best_move, Some(Move::from_str("d2d5").unwrap()));
+
+        // the bishop is defended, so taking it loses the queen
+        let result = search("4k3/8/2p5/3b4/8/8/8/3QK3 w - - 0 1", 4);
+        assert_ne!(result.best_move, Some(Move::from_str("d1d5").unwrap()));
+    }
+
+    #[test]
+    fn test_no_legal_moves()
+    {
+        let result = search("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1", 3);
+        assert_eq!(result.best_move, None);
+        assert_eq!(result.score, SearchScore::Centipawns(0));
+    }
+
+    #[test]
+    fn test_reuses_transposition_table()
+    {
+        let board = BitBoardState::start_of_game();
+        let limits = SearchLimits { depth: Some(5), ..Default::default() };
+        let mut searcher = Searcher::default();
+        searcher.set_hash_size(1);
+
+        let first = searcher.search(&board, &limits);
+        assert!(searcher.tt().hashfull() > 0);
+        let second = searcher.search(&board, &limits);
+        assert!(second.nodes < first.nodes);
+
+        searcher.clear();
+        assert_eq!(searcher.tt().hashfull(), 0);
+    }
+
+    #[test]
+    fn test_selectivity_options()
+    {
+        let board = BitBoardState::from_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - \
+             0 1",
+        )
+        .unwrap();
+        let limits = SearchLimits { depth: Some(5), ..Default::default() };
+
+        let mut searcher = Searcher::default();
+        let selective = searcher.search(&board, &limits);
+        searcher.clear();
+        searcher.options = SearchOptions::none();
+        let full = searcher.search(&board, &limits);
+        assert!(selective.nodes < full.nodes);
+
+        // each option can be turned off on its own without breaking the search
+        let toggles: [fn(&mut SearchOptions); 8] = [
+            |o| o.null_move = false,
+            |o| o.late_move_reductions = false,
+            |o| o.reverse_futility = false,
+            |o| o.futility = false,
+            |o| o.razoring = false,
+            |o| o.late_move_pruning = false,
+            |o| o.check_extensions = false,
+            |o| o.singular_extensions = false,
+        ];
+        for toggle in toggles.iter() {
+            let mut searcher = Searcher::default();
+            toggle(&mut searcher.options);
+
+            let board =
+                BitBoardState::from_fen("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1")
+                    .unwrap();
+            let result = searcher.search(&board, &limits);
+            assert_eq!(result.score, SearchScore::Mate(2));
+        }
+    }
+
+    #[test]
+    fn test_null_move_zugzwang_guard()
+    {
+        let pawns = BitBoardState::from_fen("8/8/4k3/8/8/4K3/4P3/8 w - - 0 1");
+        let knight =
+            BitBoardState::from_fen("8/8/4k3/8/8/4K3/4P3/6N1 w - - 0 1");
+
+        assert!(!has_non_pawn_material(&pawns.unwrap()));
+        assert!(has_non_pawn_material(&knight.unwrap()));
+    }
+
+    #[test]
+    fn test_time_limits()
+    {
+        let board = BitBoardState::start_of_game();
+        let mut searcher = Searcher::default();
+        searcher.set_clock(Arc::new(MockClock::with_tick(
+            Duration::from_millis(1),
+        )));
+
+        let limits = SearchLimits {
+            movetime: Some(Duration::from_millis(60)),
+            ..Default::default()
+        };
+        let result = searcher.search(&board, &limits);
+        assert!(result.best_move.is_some());
+        assert!(result.time >= Duration::from_millis(50));
+        assert!(result.time < Duration::from_millis(60));
+
+        // a forced move is played straight away
+        let check =
+            BitBoardState::from_fen("k7/8/8/8/8/8/1q6/K7 w - - 0 1").unwrap();
+        let limits = SearchLimits {
+            wtime: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let result = searcher.search(&check, &limits);
+        assert_eq!(result.best_move, Some(Move::from_str("a1b2").unwrap()));
+        assert_eq!(result.depth, 1);
+    }
+
+    #[test]
+    fn test_no_time_left()
+    {
+        // less time than the overhead, or a single node, still gives a move
+        let board = BitBoardState::start_of_game();
+        let ms = Duration::from_millis;
+        let mut searcher = Searcher::default();
+        searcher.set_clock(Arc::new(MockClock::with_tick(ms(1))));
+        for limits in [
+            SearchLimits { movetime: Some(ms(5)), ..Default::default() },
+            SearchLimits {
+                wtime: Some(ms(8)),
+                btime: Some(ms(8)),
+                ..Default::default()
+            },
+            SearchLimits { nodes: Some(1), ..Default::default() },
+        ] {
+            let result = searcher.search(&board, &limits);
+            let best_move = result.best_move.unwrap();
+            assert!(board.legal_moves().contains(&best_move));
+            assert_eq!(result.pv.first(), Some(&best_move));
+            assert_eq!(result.depth, 1);
+        }
+    }
+
+    #[test]
+    fn test_threads()
+    {
+        let mut searcher = Searcher::default();
+        searcher.set_threads(4);
+        assert_eq!(searcher.threads(), 4);
+
+        let board =
+            BitBoardState::from_fen("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1").unwrap();
+        let limits = SearchLimits { depth: Some(6), ..Default::default() };
+        let mut infos = Vec::new();
+        let result = searcher.search_with_info(&board, &limits, &mut |info| {
+            infos.push(info.clone())
+        });
+        assert_eq!(result.score, SearchScore::Mate(2));
+        assert!(result.nodes >= infos.last().unwrap().nodes);
+
+        let board = BitBoardState::start_of_game();
+        let limits = SearchLimits { nodes: Some(20_000), ..Default::default() };
+        assert!(searcher.search(&board, &limits).best_move.is_some());
+
+        searcher.set_threads(0);
+        assert_eq!(searcher.threads(), 1);
+    }
+
+    #[test]
+    fn test_vote()
+    {
+        let result = |mv: &str, depth| SearchResult {
+            best_move: Some(Move::from_str(mv).unwrap()),
+            score: SearchScore::Centipawns(0),
+            depth,
+            seldepth: 0,
+            nodes: 0,
+            time: Duration::ZERO,
+            ponder_move: None,
+            pv: Vec::new(),
+            lines: Vec::new(),
+        };
+
+        // two helpers agreeing outvote the main thread
+        let results = [
+            (result("e2e4", 10), 30),
+            (result("d2d4", 10), 25),
+            (result("d2d4", 11), 20),
+        ];
+        assert_eq!(vote(&results), 2);
+
+        // but not a mate
+        let results = [(result("e2e4", 10), MATE - 5), (result("d2d4", 12), 0)];
+        assert_eq!(vote(&results), 0);
+
+        let results = [(result("e2e4", 10), 10), (result("d2d4", 0), 50)];
+        assert_eq!(vote(&results), 0);
+    }
+
+    #[test]
+    fn test_multi_pv()
+    {
+        // taking the rook is best by far
+        let board =
+            BitBoardState::from_fen("4k3/8/8/3r4/8/8/3Q4/4K3 w - - 0 1")
+                .unwrap();
+        let limits = SearchLimits { depth: Some(5), ..Default::default() };
+        let mut searcher = Searcher::default();
+        searcher.set_multi_pv(3);
+
+        let mut infos = Vec::new();
+        let result = searcher.search_with_info(&board, &limits, &mut |info| {
+            infos.push(info.clone())
+        });
+
+        assert_eq!(result.lines.len(), 3);
+        assert_eq!(result.lines[0].best_move, Move::from_str("d2d5").unwrap());
+        assert_eq!(result.best_move, Some(result.lines[0].best_move));
+        let centipawns = |line: &PvLine| match line.score {
+            SearchScore::Centipawns(cp) => cp,
+            SearchScore::Mate(_) => panic!("unexpected mate!"),
+        };
+        assert!(result.lines.windows(2).all(|pair| {
+            pair[0].best_move != pair[1].best_move
+                && centipawns(&pair[0]) >= centipawns(&pair[1])
+        }));
+
+        let last: Vec<usize> =
+            infos.iter().rev().take(3).map(|info| info.multipv).collect();
+        assert_eq!(last, vec![3, 2, 1]);
+
+        // there can't be more lines than legal moves
+        let board =
+            BitBoardState::from_fen("k7/8/8/8/8/8/1q6/K7 w - - 0 1").unwrap();
+        assert_eq!(searcher.search(&board, &limits).lines.len(), 1);
+    }
+
+    #[test]
+    fn test_book()
+    {
+        let games = crate::pgn::parse_pgn("1. b3 e5 *").unwrap();
+        let mut searcher = Searcher::default();
+        searcher.set_book(Some(Book::from_games(&games)));
+
+        let board = BitBoardState::start_of_game();
+        let limits = SearchLimits { depth: Some(3), ..Default::default() };
+        let result = searcher.search(&board, &limits);
+        assert_eq!(result.best_move, Some(Move::from_str("b2b3").unwrap()));
+        assert_eq!(result.nodes, 0);
+
+        // analysis ignores the book
+        let limits = SearchLimits { infinite: true, ..limits };
+        assert!(searcher.search(&board, &limits).nodes > 0);
+    }
+
+    #[test]
+    fn test_strength()
+    {
+        let mut searcher = Searcher::default();
+        searcher.set_strength(Some(Strength::from_elo(strength::MIN_ELO)));
+        let limits = SearchLimits::default();
+
+        // even the weakest setting takes a free rook and mates in one
+        let free_rook =
+            BitBoardState::from_fen("4k3/8/8/3r4/8/8/3Q4/4K3 w - - 0 1")
+                .unwrap();
+        let mate =
+            BitBoardState::from_fen("7k/8/6K1/8/8/8/8/R7 w - - 0 1").unwrap();
+        let mut infos = 0;
+        for _ in 0..20 {
+            let result = searcher.search(&free_rook, &limits);
+            assert_eq!(result.best_move, Some(Move::from_str("d2d5").unwrap()));
+            assert_eq!(result.lines.len(), 1);
+
+            let result = searcher.search_with_info(&mate, &limits, &mut |_| {
+                infos += 1;
+            });
+            assert_eq!(result.best_move, Some(Move::from_str("a1a8").unwrap()));
+            assert_eq!(result.score, SearchScore::Mate(1));
+        }
+
+        // only the lines asked for are reported, and the depth is capped
+        let depth = Strength::from_elo(strength::MIN_ELO).depth;
+        assert_eq!(infos, 20 * depth);
+
+        // but it doesn't always play the same opening move
+        let board = BitBoardState::start_of_game();
+        let moves: std::collections::HashSet<Move> = (0..20)
+            .filter_map(|_| searcher.search(&board, &limits).best_move)
+            .collect();
+        assert!(moves.len() > 1);
+        assert!(moves.iter().all(|mv| board.legal_moves().contains(mv)));
+    }
+
+    #[test]
+    fn test_mate_limit()
+    {
+        let board =
+            BitBoardState::from_fen("7k/8/8/8/8/8/R7/1R4K1 w - - 0 1").unwrap();
+        let limits = SearchLimits { mate: Some(2), ..Default::default() };
+        let result = Searcher::default().search(&board, &limits);
+
+        assert_eq!(result.score, SearchScore::Mate(2));
+        assert!(result.depth < 10);
+    }
+
+    #[test]
+    fn test_node_limit()
+    {
+        let board = BitBoardState::start_of_game();
+        let limits = SearchLimits { nodes: Some(5000), ..Default::default() };
+        let result = Searcher::default().search(&board, &limits);
+
+        assert!(result.best_move.is_some());
+        assert!(result.nodes <= 5000);
+    }
+}
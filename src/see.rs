@@ -0,0 +1,266 @@
+use crate::attacks::*;
+use crate::board::BitBoardState;
+use crate::moves::Move;
+use crate::piece::Color::{self, *};
+use crate::piece::Piece;
+use crate::piece::PieceType::{self, *};
+
+/// Returns the value of a piece when trading off captures.
+pub fn see_value(piece: PieceType) -> i32
+{
+    match piece {
+        Pawn => 100,
+        Knight => 320,
+        Bishop => 330,
+        Rook => 500,
+        Queen => 900,
+        King => 20000,
+    }
+}
+
+impl BitBoardState
+{
+    /// Returns every piece of either color attacking a square, given the
+    /// occupancy to look through.
+    pub fn attackers_to(&self, square: usize, occupied: u64) -> u64
+    {
+        let diagonal = self.pieces_of_type(Bishop) | self.pieces_of_type(Queen);
+        let straight = self.pieces_of_type(Rook) | self.pieces_of_type(Queen);
+
+        pawn_attacks(White, square)
+            & self.pieces(Piece { color: Black, piece: Pawn })
+            | pawn_attacks(Black, square)
+                & self.pieces(Piece { color: White, piece: Pawn })
+            | KNIGHT_ATTACKS[square] & self.pieces_of_type(Knight)
+            | KING_ATTACKS[square] & self.pieces_of_type(King)
+            | bishop_attacks(square, occupied) & diagonal
+            | rook_attacks(square, occupied) & straight
+    }
+
+    /// Returns each piece of the given color pinned to its king, along with
+    /// the square of the slider pinning it.
+    fn pins(&self, color: Color) -> Vec<(usize, usize)>
+    {
+        let king = self.king_square(color);
+        let enemy =
+            |piece| self.pieces(Piece { color: color.opposite(), piece });
+
+        let mut snipers = rook_attacks(king, 0) & (enemy(Rook) | enemy(Queen))
+            | bishop_attacks(king, 0) & (enemy(Bishop) | enemy(Queen));
+        let mut pins = Vec::new();
+
+        while snipers != 0 {
+            let sniper = snipers.trailing_zeros() as usize;
+            snipers &= snipers - 1;
+
+            let blockers = between(king, sniper) & self.occupancy();
+            if blockers.count_ones() == 1
+                && blockers & self.color_occupancy(color) != 0
+            {
+                pins.push((blockers.trailing_zeros() as usize, sniper));
+            }
+        }
+
+        pins
+    }
+
+    /// Returns the material balance after the best sequence of captures on the
+    /// destination square of a move, from the moving side's perspective.
+    ///
+    /// Either side may stop capturing at any point. Sliders behind other
+    /// attackers join in as the squares in front of them clear, pawns
+    /// recapturing on the last rank promote to queens, and pieces pinned to
+    /// their king may only recapture along the pin.
+    pub fn see(&self, mv: Move) -> i32
+    {
+        let from = mv.from as usize;
+        let to = mv.to as usize;
+        let mover = match self.piece_at(from) {
+            Some(piece) => piece,
+            None => return 0,
+        };
+
+        // castling never wins or loses material
+        if mover.piece == King && (from as i32 - to as i32).abs() == 2 {
+            return 0;
+        }
+
+        let mut occupied = self.occupancy() ^ 1 << from;
+        let mut gain = [0; 32];
+
+        gain[0] = match self.piece_at(to) {
+            Some(piece) => see_value(piece.piece),
+            None if mover.piece == Pawn && from % 8 != to % 8 => {
+                // en passant removes the pawn behind the target square
+                let captured =
+                    if mover.color == White { to - 8 } else { to + 8 };
+                occupied ^= 1 << captured;
+                see_value(Pawn)
+            },
+            None => 0,
+        };
+
+        let mut on_square = mover.piece;
+        if let Some(promotion) = mv.promotion {
+            gain[0] += see_value(promotion) - see_value(Pawn);
+            on_square = promotion;
+        }
+
+        let pins = [self.pins(White), self.pins(Black)];
+        let diagonal = self.pieces_of_type(Bishop) | self.pieces_of_type(Queen);
+        let straight = self.pieces_of_type(Rook) | self.pieces_of_type(Queen);
+
+        let mut attackers = self.attackers_to(to, occupied);
+        let mut side = mover.color.opposite();
+        let mut depth = 0;
+
+        loop {
+            attackers &= occupied;
+
+            let king = self.king_square(side);
+            let pinned = pins[side as usize]
+                .iter()
+                .filter(|(_, pinner)| occupied & 1 << pinner != 0)
+                .filter(|(pinned, _)| line(king, *pinned) & 1 << to == 0)
+                .fold(0, |acc, (pinned, _)| acc | 1 << pinned);
+
+            let candidates = attackers & self.color_occupancy(side) & !pinned;
+            let attacker = [Pawn, Knight, Bishop, Rook, Queen, King]
+                .iter()
+                .map(|piece| {
+                    (
+                        *piece,
+                        candidates
+                            & self.pieces(Piece { color: side, piece: *piece }),
+                    )
+                })
+                .find(|(_, bits)| *bits != 0);
+
+            let (piece, bits) = match attacker {
+                Some(attacker) => attacker,
+                None => break,
+            };
+
+            // the king may only capture if nothing can take it back
+            if piece == King
+                && attackers & self.color_occupancy(side.opposite()) != 0
+            {
+                break;
+            }
+
+            let promotes = piece == Pawn && !(8..56).contains(&to);
+
+            depth += 1;
+            gain[depth] = see_value(on_square) - gain[depth - 1];
+            if promotes {
+                gain[depth] += see_value(Queen) - see_value(Pawn);
+            }
+            on_square = if promotes { Queen } else { piece };
+
+            occupied ^= 1 << bits.trailing_zeros();
+            attackers |= bishop_attacks(to, occupied) & diagonal
+                | rook_attacks(to, occupied) & straight;
+            side = side.opposite();
+        }
+
+        while depth > 0 {
+            gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+            depth -= 1;
+        }
+
+        gain[0]
+    }
+
+    /// Returns true if a move wins at least `threshold` material once all
+    /// exchanges on its destination square are played out.
+    pub fn see_ge(&self, mv: Move, threshold: i32) -> bool
+    {
+        let en_passant = mv.from % 8 != mv.to % 8
+            && self.piece_at(mv.from as usize).is_some_and(|p| p.piece == Pawn);
+        let captured = match self.piece_at(mv.to as usize) {
+            Some(piece) => see_value(piece.piece),
+            None if en_passant => see_value(Pawn),
+            None => 0,
+        };
+        let promotion =
+            mv.promotion.map_or(0, |p| see_value(p) - see_value(Pawn));
+
+        // the opponent's replies can only take away from the first capture
+        if captured + promotion < threshold {
+            return false;
+        }
+
+        self.see(mv) >= threshold
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::str::FromStr;
+
+    use crate::board::{BitBoardState, GameState};
+    use crate::moves::Move;
+
+    fn see(fen: &str, mv: &str) -> i32
+    {
+        BitBoardState::from_fen(fen).unwrap().see(Move::from_str(mv).unwrap())
+    }
+
+    #[test]
+    fn test_see_simple_captures()
+    {
+        assert_eq!(
+            see("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"),
+            100
+        );
+        assert_eq!(see("3rk3/8/8/3p4/8/8/3R4/6K1 w - - 0 1", "d2d5"), -400);
+        assert_eq!(see("4k3/8/8/3p4/4P3/8/8/6K1 w - - 0 1", "e4d5"), 100);
+
+        // moving a queen onto a square guarded by a pawn
+        assert_eq!(see("4k3/8/4p3/8/8/8/8/3QK3 w - - 0 1", "d1d5"), -900);
+        assert_eq!(see("4k3/8/8/8/8/8/8/3QK3 w - - 0 1", "d1d5"), 0);
+    }
+
+    #[test]
+    fn test_see_x_rays()
+    {
+        // the second rook joins in once the first has captured
+        assert_eq!(see("3rk3/8/8/3p4/8/8/3R4/3R2K1 w - - 0 1", "d2d5"), 100);
+
+        // a bishop behind a pawn stops the rook from recapturing
+        assert_eq!(see("3rk3/8/8/3n4/4P3/5B2/8/4K3 w - - 0 1", "e4d5"), 320);
+        assert_eq!(see("3rk3/8/8/3n4/4P3/8/8/4K3 w - - 0 1", "e4d5"), 220);
+    }
+
+    #[test]
+    fn test_see_promotions()
+    {
+        assert_eq!(see("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7b8q"), 1300);
+        assert_eq!(see("1r2k3/P2n4/8/8/8/8/8/4K3 w - - 0 1", "a7b8q"), 400);
+
+        // a pawn recaptures on the last rank and promotes
+        assert_eq!(see("2Nrk3/1P6/8/8/8/8/8/4K3 b - - 0 1", "d8c8"), -980);
+    }
+
+    #[test]
+    fn test_see_pinned_pieces()
+    {
+        assert_eq!(see("8/k7/1n6/3p4/8/4B3/8/3QK3 w - - 0 1", "d1d5"), 100);
+        assert_eq!(see("8/k7/1n6/3p4/8/8/8/3QK3 w - - 0 1", "d1d5"), -800);
+    }
+
+    #[test]
+    fn test_see_ge()
+    {
+        let board =
+            BitBoardState::from_fen("3rk3/8/8/3p4/8/8/3R4/3R2K1 w - - 0 1")
+                .unwrap();
+        let mv = Move::from_str("d2d5").unwrap();
+
+        assert!(board.see_ge(mv, 0));
+        assert!(board.see_ge(mv, 100));
+        assert!(!board.see_ge(mv, 101));
+        assert!(!board.see_ge(Move::from_str("d1d4").unwrap(), 1));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::board::{BitBoardState, GameState};
+use crate::pgn::{Game, GameResult};
+use crate::piece::Color;
+use crate::search::{SearchLimits, Searcher};
+use crate::strength::mix;
+
+/// Games still going after this many plies are scored as draws.
+pub const MAX_GAME_PLIES: usize = 400;
+
+/// Plays a game between two searchers from a starting position, each
+/// searching every move with the same limits. The game is left unfinished if
+/// a searcher comes up with no move.
+pub fn play_game(
+    white: &mut Searcher, black: &mut Searcher, start: &BitBoardState,
+    limits: &SearchLimits,
+) -> Game
+{
+    let mut game = Game::new(start.clone());
+    let mut board = start.clone();
+    white.clear();
+    black.clear();
+
+    game.result = loop {
+        if board.legal_moves().is_empty() {
+            break match (board.in_check(), board.turn()) {
+                (false, _) => GameResult::Draw,
+                (true, Color::White) => GameResult::BlackWins,
+                (true, Color::Black) => GameResult::WhiteWins,
+            };
+        }
+        if board.is_draw_by_rule() || game.moves.len() >= MAX_GAME_PLIES {
+            break GameResult::Draw;
+        }
+
+        let searcher = match board.turn() {
+            Color::White => &mut *white,
+            Color::Black => &mut *black,
+        };
+        let mv = match searcher.search(&board, limits).best_move {
+            Some(mv) => mv,
+            None => break GameResult::Unfinished,
+        };
+        board.make_move(mv);
+        game.moves.push(mv);
+    };
+
+    game
+}
+
+/// Plays random legal moves from the starting position, to vary the games
+/// between two searchers. The same seed always gives the same opening.
+pub fn random_opening(seed: u64, plies: usize) -> BitBoardState
+{
+    let mut board = BitBoardState::start_of_game();
+    for ply in 0..plies {
+        let moves = board.legal_moves();
+        if moves.is_empty() {
+            break;
+        }
+        let random = mix(seed ^ mix(ply as u64));
+        board.make_move(moves[(random % moves.len() as u64) as usize]);
+    }
+
+    board
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_play_game()
+    {
+        // white mates straight away with the rook
+        let start =
+            BitBoardState::from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1")
+                .unwrap();
+        let limits = SearchLimits { depth: Some(2), ..Default::default() };
+        let (mut white, mut black) = (Searcher::default(), Searcher::default());
+
+        let game = play_game(&mut white, &mut black, &start, &limits);
+        assert_eq!(game.result, GameResult::WhiteWins);
+        assert_eq!(game.moves.len(), 1);
+
+        let limits = SearchLimits { nodes: Some(200), ..Default::default() };
+        let opening = random_opening(1, 8);
+        let game = play_game(&mut black, &mut white, &opening, &limits);
+        assert_ne!(game.result, GameResult::Unfinished);
+        assert_eq!(random_opening(7, 8).hash(), random_opening(7, 8).hash());
+        assert_ne!(random_opening(7, 8).hash(), random_opening(8, 8).hash());
+    }
+}
@@ -0,0 +1,316 @@
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::{fmt, thread};
+
+use crate::pgn::GameResult;
+use crate::search::{SearchLimits, SearchParams, Searcher};
+use crate::selfplay::{play_game, random_opening};
+use crate::strength::mix;
+
+/// How quickly the steps and perturbations shrink over the iterations.
+const ALPHA: f64 = 0.602;
+const GAMMA: f64 = 0.101;
+/// Delays the shrinking of the steps, to keep the first ones from being too
+/// large.
+const STABILITY: f64 = 100.0;
+/// The step taken per game won by the better side, as a fraction of each
+/// parameter's perturbation.
+const LEARNING_RATE: f64 = 0.05;
+/// How many random plies start each pair of games.
+const OPENING_PLIES: usize = 8;
+/// The hash size of each searcher playing games, in megabytes.
+const SELFPLAY_HASH_MB: usize = 4;
+/// The fewest nodes a move the games may be played with, since with fewer
+/// most middlegame searches don't get past the first ply.
+pub const MIN_NODES: u64 = 1000;
+
+/// A search parameter to tune, with its perturbation and allowed range.
+struct Tunable
+{
+    name:  &'static str,
+    get:   fn(&SearchParams) -> f64,
+    set:   fn(&mut SearchParams, f64),
+    /// How far the parameter is nudged either way at first.
+    delta: f64,
+    min:   f64,
+    max:   f64,
+}
+
+#[rustfmt::skip]
+const TUNABLES: [Tunable; 8] = [
+    Tunable {
+        name: "lmr_base", delta: 0.1, min: 0.0, max: 2.0,
+        get: |p| p.lmr_base, set: |p, v| p.lmr_base = v,
+    },
+    Tunable {
+        name: "lmr_divisor", delta: 0.2, min: 1.0, max: 4.0,
+        get: |p| p.lmr_divisor, set: |p, v| p.lmr_divisor = v,
+    },
+    Tunable {
+        name: "reverse_futility_margin", delta: 10.0, min: 20.0, max: 200.0,
+        get: |p| p.reverse_futility_margin as f64,
+        set: |p, v| p.reverse_futility_margin = v.round() as i32,
+    },
+    Tunable {
+        name: "futility_base", delta: 15.0, min: 0.0, max: 300.0,
+        get: |p| p.futility_base as f64,
+        set: |p, v| p.futility_base = v.round() as i32,
+    },
+    Tunable {
+        name: "futility_margin", delta: 15.0, min: 20.0, max: 300.0,
+        get: |p| p.futility_margin as f64,
+        set: |p, v| p.futility_margin = v.round() as i32,
+    },
+    Tunable {
+        name: "null_move_base", delta: 0.5, min: 1.0, max: 6.0,
+        get: |p| p.null_move_base as f64,
+        set: |p, v| p.null_move_base = v.round() as i32,
+    },
+    Tunable {
+        name: "null_move_divisor", delta: 0.5, min: 2.0, max: 8.0,
+        get: |p| p.null_move_divisor as f64,
+        set: |p, v| p.null_move_divisor = v.round() as i32,
+    },
+    Tunable {
+        name: "aspiration_window", delta: 4.0, min: 5.0, max: 100.0,
+        get: |p| p.aspiration_window as f64,
+        set: |p, v| p.aspiration_window = v.round() as i32,
+    },
+];
+
+/// Tunes the search parameters by simultaneous perturbation stochastic
+/// approximation: each iteration nudges every parameter at random either way,
+/// plays the two resulting searchers against each other, and moves the
+/// parameters towards the side that scored better.
+///
+/// The state is kept as text, with the iteration on the first line and then
+/// each parameter's name and value, so that tuning can be stopped and
+/// resumed.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Spsa
+{
+    /// The iterations completed so far.
+    pub iteration: u32,
+    /// The current value of each tunable, kept unrounded between steps.
+    pub values:    Vec<f64>,
+}
+
+/// The outcome of one iteration.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Batch
+{
+    /// Games won, lost and drawn by the searcher nudged upwards. Games left
+    /// unfinished aren't counted.
+    pub wins:   u32,
+    pub losses: u32,
+    pub draws:  u32,
+}
+
+impl Default for Spsa
+{
+    fn default() -> Self
+    {
+        let params = SearchParams::default();
+        Spsa {
+            iteration: 0,
+            values:    TUNABLES.iter().map(|t| (t.get)(&params)).collect(),
+        }
+    }
+}
+
+impl Spsa
+{
+    /// Returns the search parameters for the current values.
+    pub fn params(&self) -> SearchParams { self.params_with(&self.values) }
+
+    fn params_with(&self, values: &[f64]) -> SearchParams
+    {
+        let mut params = SearchParams::default();
+        for (tunable, value) in TUNABLES.iter().zip(values) {
+            (tunable.set)(&mut params, *value);
+        }
+        params
+    }
+
+    /// Plays a batch of games between two searchers nudged either way from
+    /// the current values, each opening once with either color, on several
+    /// threads, and steps the values towards the winner.
+    pub fn step(&mut self, games: usize, nodes: u64, threads: usize) -> Batch
+    {
+        let k = self.iteration as f64 + 1.0;
+        let seed = mix(self.iteration as u64);
+        let signs: Vec<f64> = (0..TUNABLES.len())
+            .map(|i| match mix(seed ^ i as u64) & 1 {
+                0 => 1.0,
+                _ => -1.0,
+            })
+            .collect();
+        let perturbations: Vec<f64> = TUNABLES
+            .iter()
+            .zip(&signs)
+            .map(|(tunable, sign)| tunable.delta / k.powf(GAMMA) * sign)
+            .collect();
+
+        let nudged = |direction: f64| {
+            let values: Vec<f64> = self
+                .values
+                .iter()
+                .zip(&perturbations)
+                .map(|(value, delta)| value + direction * delta)
+                .collect();
+            self.params_with(&values)
+        };
+        let players = [nudged(1.0), nudged(-1.0)];
+        let batch = play_batch(players, games, nodes, threads, seed);
+
+        // the decays are scaled so that the first step uses the full rate
+        let gain = LEARNING_RATE
+            * ((STABILITY + 1.0) / (STABILITY + k)).powf(ALPHA)
+            * k.powf(GAMMA);
+        let result = batch.wins as f64 - batch.losses as f64;
+        for ((value, tunable), sign) in
+            self.values.iter_mut().zip(TUNABLES.iter()).zip(&signs)
+        {
+            *value = (*value + gain * tunable.delta * result * sign)
+                .clamp(tunable.min, tunable.max);
+        }
+
+        self.iteration += 1;
+        batch
+    }
+}
+
+/// Plays pairs of games between two sets of parameters, from random
+/// openings, returning the results for the first.
+fn play_batch(
+    players: [SearchParams; 2], games: usize, nodes: u64, threads: usize,
+    seed: u64,
+) -> Batch
+{
+    let pairs = games.div_ceil(2);
+    let next = AtomicUsize::new(0);
+    let batch = Mutex::new(Batch::default());
+    let limits = SearchLimits { nodes: Some(nodes), ..Default::default() };
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1).min(pairs) {
+            scope.spawn(|| {
+                let mut searchers = players.map(|params| {
+                    let mut searcher = Searcher::default();
+                    searcher.set_hash_size(SELFPLAY_HASH_MB);
+                    searcher.set_params(params);
+                    searcher
+                });
+
+                loop {
+                    let pair = next.fetch_add(1, Ordering::Relaxed);
+                    if pair >= pairs {
+                        break;
+                    }
+
+                    let opening =
+                        random_opening(mix(seed ^ pair as u64), OPENING_PLIES);
+                    let [first, second] = &mut searchers;
+                    let results = [
+                        play_game(first, second, &opening, &limits).result,
+                        match play_game(second, first, &opening, &limits).result
+                        {
+                            GameResult::WhiteWins => GameResult::BlackWins,
+                            GameResult::BlackWins => GameResult::WhiteWins,
+                            result => result,
+                        },
+                    ];
+
+                    let mut batch = batch.lock().unwrap();
+                    for result in results {
+                        match result {
+                            GameResult::WhiteWins => batch.wins += 1,
+                            GameResult::BlackWins => batch.losses += 1,
+                            GameResult::Draw => batch.draws += 1,
+                            GameResult::Unfinished => (),
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    batch.into_inner().unwrap()
+}
+
+impl fmt::Display for Spsa
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        writeln!(f, "iteration {}", self.iteration)?;
+        for (tunable, value) in TUNABLES.iter().zip(&self.values) {
+            writeln!(f, "{} {:.4}", tunable.name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Spsa
+{
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let mut spsa = Spsa::default();
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let (name, value) = line
+                .split_once(' ')
+                .ok_or_else(|| format!("invalid checkpoint line {}!", line))?;
+            let value = value.trim();
+
+            if name == "iteration" {
+                spsa.iteration = value.parse()?;
+                continue;
+            }
+            let index = TUNABLES
+                .iter()
+                .position(|tunable| tunable.name == name)
+                .ok_or_else(|| format!("unknown search parameter {}!", name))?;
+            spsa.values[index] = value.parse()?;
+        }
+
+        Ok(spsa)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn test_checkpoint()
+    {
+        let mut spsa = Spsa::default();
+        assert_eq!(spsa.params(), SearchParams::default());
+
+        spsa.iteration = 12;
+        spsa.values[0] = 0.8125;
+        let parsed: Spsa = spsa.to_string().parse().unwrap();
+        assert_eq!(parsed, spsa);
+        assert_eq!(parsed.params().lmr_base, 0.8125);
+
+        assert!("lmr_bass 1.0".parse::<Spsa>().is_err());
+        assert!("lmr_base one".parse::<Spsa>().is_err());
+    }
+
+    #[test]
+    fn test_step()
+    {
+        let mut spsa = Spsa::default();
+        let batch = spsa.step(4, 100, 2);
+        assert_eq!(batch.wins + batch.losses + batch.draws, 4);
+        assert_eq!(spsa.iteration, 1);
+
+        for (tunable, value) in TUNABLES.iter().zip(&spsa.values) {
+            assert!((tunable.min..=tunable.max).contains(value));
+        }
+    }
+}
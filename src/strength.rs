@@ -0,0 +1,336 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::search::{PvLine, SearchLimits, SearchScore, MATE};
+
+/// Settings that weaken the engine to play at around a given rating.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Strength
+{
+    /// The approximate rating these settings play at.
+    pub elo:         u32,
+    /// The deepest a search may go.
+    pub depth:       u32,
+    /// The most nodes a search may visit.
+    pub nodes:       u64,
+    /// The most each position's evaluation is randomly off by, in
+    /// centipawns.
+    pub noise:       i32,
+    /// How many of the best moves to choose between.
+    pub candidates:  usize,
+    /// The most a chosen move may score below the best, in centipawns.
+    pub max_loss:    i32,
+    /// How readily worse moves are chosen. A move this many centipawns
+    /// worse than the best is chosen `e` times less often.
+    pub temperature: i32,
+}
+
+/// The settings for each rating, weakest first. Ratings in between are
+/// interpolated. The ratings are rough guides for human play rather than
+/// measurements on any rating list.
+///
+/// Twice the noise plus the largest loss stays below a minor piece, so that
+/// weaker play comes from searching less rather than from hanging pieces.
+const CALIBRATION: [Strength; 7] = [
+    Strength {
+        elo:         800,
+        depth:       1,
+        nodes:       200,
+        noise:       60,
+        candidates:  6,
+        max_loss:    150,
+        temperature: 150,
+    },
+    Strength {
+        elo:         1100,
+        depth:       3,
+        nodes:       2_000,
+        noise:       50,
+        candidates:  5,
+        max_loss:    130,
+        temperature: 100,
+    },
+    Strength {
+        elo:         1400,
+        depth:       4,
+        nodes:       8_000,
+        noise:       40,
+        candidates:  4,
+        max_loss:    110,
+        temperature: 60,
+    },
+    Strength {
+        elo:         1700,
+        depth:       6,
+        nodes:       30_000,
+        noise:       30,
+        candidates:  3,
+        max_loss:    80,
+        temperature: 30,
+    },
+    Strength {
+        elo:         2000,
+        depth:       8,
+        nodes:       120_000,
+        noise:       15,
+        candidates:  2,
+        max_loss:    40,
+        temperature: 15,
+    },
+    Strength {
+        elo:         2300,
+        depth:       11,
+        nodes:       500_000,
+        noise:       5,
+        candidates:  2,
+        max_loss:    15,
+        temperature: 5,
+    },
+    Strength {
+        elo:         2600,
+        depth:       16,
+        nodes:       2_000_000,
+        noise:       0,
+        candidates:  1,
+        max_loss:    0,
+        temperature: 0,
+    },
+];
+
+/// The weakest and strongest ratings that can be asked for.
+pub const MIN_ELO: u32 = CALIBRATION[0].elo;
+pub const MAX_ELO: u32 = CALIBRATION[CALIBRATION.len() - 1].elo;
+
+impl Strength
+{
+    /// Returns the settings for a rating, clamped to the calibrated range.
+    pub fn from_elo(elo: u32) -> Self
+    {
+        let elo = elo.clamp(MIN_ELO, MAX_ELO);
+        let upper = CALIBRATION.iter().position(|s| s.elo >= elo).unwrap();
+        if CALIBRATION[upper].elo == elo {
+            return CALIBRATION[upper];
+        }
+
+        let (low, high) = (CALIBRATION[upper - 1], CALIBRATION[upper]);
+        let t = (elo - low.elo) as f64 / (high.elo - low.elo) as f64;
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+
+        Strength {
+            elo,
+            depth: lerp(low.depth as f64, high.depth as f64).round() as u32,
+            // nodes grow geometrically with strength
+            nodes: lerp((low.nodes as f64).ln(), (high.nodes as f64).ln())
+                .exp()
+                .round() as u64,
+            noise: lerp(low.noise as f64, high.noise as f64).round() as i32,
+            candidates: lerp(low.candidates as f64, high.candidates as f64)
+                .round() as usize,
+            max_loss: lerp(low.max_loss as f64, high.max_loss as f64).round()
+                as i32,
+            temperature: lerp(low.temperature as f64, high.temperature as f64)
+                .round() as i32,
+        }
+    }
+
+    /// Returns the limits with the depth and nodes capped.
+    pub fn limit(&self, limits: &SearchLimits) -> SearchLimits
+    {
+        let mut limits = limits.clone();
+        limits.depth =
+            Some(limits.depth.map_or(self.depth, |d| d.min(self.depth)));
+        limits.nodes =
+            Some(limits.nodes.map_or(self.nodes, |n| n.min(self.nodes)));
+        limits
+    }
+
+    /// Picks one of the lines, best first, to play, given a random number.
+    ///
+    /// Only lines within `max_loss` of the best are considered, so a weak
+    /// setting still never throws away a piece for nothing or walks into a
+    /// mate, and a mate is always taken when found.
+    pub fn choose(&self, lines: &[PvLine], random: u64) -> usize
+    {
+        let best = match lines.first() {
+            Some(line) if !matches!(line.score, SearchScore::Mate(_)) =>
+                centipawns(line.score),
+            _ => return 0,
+        };
+
+        let weights: Vec<f64> = lines
+            .iter()
+            .take(self.candidates)
+            .map(|line| centipawns(line.score) - best)
+            .take_while(|&loss| loss >= -self.max_loss)
+            .map(|loss| match self.temperature {
+                0 if loss < 0 => 0.0,
+                0 => 1.0,
+                temperature => (loss as f64 / temperature as f64).exp(),
+            })
+            .collect();
+
+        let total: f64 = weights.iter().sum();
+        let mut roll = (random >> 11) as f64 / (1u64 << 53) as f64 * total;
+        for (i, weight) in weights.iter().enumerate() {
+            if roll < *weight {
+                return i;
+            }
+            roll -= weight;
+        }
+        0
+    }
+}
+
+/// Returns a score in centipawns, counting mates as beyond any material.
+fn centipawns(score: SearchScore) -> i32
+{
+    match score {
+        SearchScore::Centipawns(cp) => cp,
+        SearchScore::Mate(moves) if moves > 0 => MATE - moves,
+        SearchScore::Mate(moves) => -MATE - moves,
+    }
+}
+
+/// Mixes the bits of a number, for cheap repeatable randomness.
+pub fn mix(x: u64) -> u64
+{
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Returns a random offset of at most `amount` either way for a position,
+/// the same every time the position is seen with the same seed so that the
+/// search stays consistent.
+pub fn noise(hash: u64, seed: u64, amount: i32) -> i32
+{
+    match amount {
+        0 => 0,
+        _ => (mix(hash ^ seed) % (2 * amount as u64 + 1)) as i32 - amount,
+    }
+}
+
+/// Returns a seed that differs from one call to the next.
+pub fn random_seed() -> u64
+{
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos() as u64);
+    mix(nanos)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::board::{BitBoardState, GameState};
+    use crate::moves::Move;
+    use crate::search::Searcher;
+
+    fn line(mv: &str, score: SearchScore) -> PvLine
+    {
+        let mv: Move = mv.parse().unwrap();
+        PvLine { best_move: mv, score, pv: vec![mv] }
+    }
+
+    #[test]
+    fn test_from_elo()
+    {
+        assert_eq!(Strength::from_elo(0), CALIBRATION[0]);
+        assert_eq!(Strength::from_elo(1700), CALIBRATION[3]);
+        assert_eq!(Strength::from_elo(9999).candidates, 1);
+
+        // stronger settings never search less or guess more
+        let mut previous = Strength::from_elo(MIN_ELO);
+        for elo in (MIN_ELO..=MAX_ELO).step_by(50) {
+            let strength = Strength::from_elo(elo);
+            assert!(strength.depth >= previous.depth);
+            assert!(strength.nodes >= previous.nodes);
+            assert!(strength.noise <= previous.noise);
+            assert!(strength.max_loss <= previous.max_loss);
+            previous = strength;
+        }
+    }
+
+    #[test]
+    fn test_choose()
+    {
+        let weak = Strength::from_elo(MIN_ELO);
+        let lines = [
+            line("e2e4", SearchScore::Centipawns(30)),
+            line("d2d4", SearchScore::Centipawns(20)),
+            line("g1f3", SearchScore::Centipawns(-100)),
+            line("f2f3", SearchScore::Centipawns(-290)),
+            line("g2g4", SearchScore::Mate(-2)),
+        ];
+
+        let mut chosen = [0; 5];
+        for i in 0..1000 {
+            chosen[weak.choose(&lines, mix(i))] += 1;
+        }
+        assert!(chosen[0] > chosen[1] && chosen[1] > chosen[2]);
+        assert!(chosen[2] > 0);
+        assert_eq!(chosen[3] + chosen[4], 0);
+
+        // full strength always plays the best move, and a mate is never missed
+        let strong = Strength::from_elo(MAX_ELO);
+        let mate = [
+            line("a1a8", SearchScore::Mate(1)),
+            line("a1a7", SearchScore::Centipawns(900)),
+        ];
+        for i in 0..100 {
+            assert_eq!(strong.choose(&lines, mix(i)), 0);
+            assert_eq!(weak.choose(&mate, mix(i)), 0);
+        }
+    }
+
+    #[test]
+    fn test_weakest_keeps_pieces()
+    {
+        // Nb5 and Nd5 give the knight away to a pawn
+        let board =
+            BitBoardState::from_fen("4k3/8/2p1p3/8/8/2N5/8/4K3 w - - 0 1")
+                .unwrap();
+        let blunders: [Move; 2] =
+            ["c3b5".parse().unwrap(), "c3d5".parse().unwrap()];
+
+        let mut searcher = Searcher::default();
+        searcher.set_strength(Some(Strength::from_elo(MIN_ELO)));
+        for _ in 0..50 {
+            let result = searcher.search(&board, &SearchLimits::default());
+            assert!(!blunders.contains(&result.best_move.unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_weakest_in_the_middlegame()
+    {
+        // every root move is searched, and none is picked that loses material
+        let board = BitBoardState::from_fen(
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - \
+             0 1",
+        )
+        .unwrap();
+
+        let weakest = Strength::from_elo(MIN_ELO);
+        let mut searcher = Searcher::default();
+        searcher.set_strength(Some(weakest));
+        searcher.set_multi_pv(weakest.candidates);
+        for _ in 0..50 {
+            let result = searcher.search(&board, &SearchLimits::default());
+            assert_eq!(result.lines.len(), weakest.candidates);
+            assert!(board.see_ge(result.best_move.unwrap(), 0));
+        }
+    }
+
+    #[test]
+    fn test_noise()
+    {
+        assert_eq!(noise(123, 456, 0), 0);
+        for hash in 0..1000 {
+            let offset = noise(hash, 7, 50);
+            assert!((-50..=50).contains(&offset));
+            assert_eq!(offset, noise(hash, 7, 50));
+        }
+    }
+}
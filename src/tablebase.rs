@@ -0,0 +1,1089 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::attacks::*;
+use crate::board::{BitBoardState, GameState};
+use crate::moves::Move;
+use crate::piece::Color::{self, *};
+use crate::piece::Piece;
+use crate::piece::PieceType::{self, *};
+
+/// The largest number of pieces (kings included) a table may contain.
+pub const MAX_PIECES: usize = 5;
+
+const FILE_MAGIC: &[u8; 6] = b"BCLDTB";
+const FILE_VERSION: u8 = 1;
+const FILE_EXTENSION: &str = "bctb";
+
+/// Stored values are the distance to mate in plies plus one, so that an even
+/// distance (the side to move is getting mated) can be told from an odd one.
+const UNKNOWN: u8 = 0;
+const BROKEN: u8 = 255;
+
+/// A removed (captured) piece is parked on this square.
+const NONE: u8 = 64;
+
+/// The canonical squares of the white king in pawnless tables: a1-d1-d4.
+const TRIANGLE: [u8; 10] = [0, 1, 2, 3, 9, 10, 11, 18, 19, 27];
+
+/// The exact result of a position, from the side to move's perspective.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dtm
+{
+    /// The side to move mates in this many plies.
+    Win(u8),
+    /// The side to move is mated in this many plies.
+    Loss(u8),
+    Draw,
+}
+
+/// A material signature such as `KRKP`, listing the white pieces first.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Material
+{
+    white: Vec<PieceType>,
+    black: Vec<PieceType>,
+}
+
+/// A single distance-to-mate table for one material signature.
+pub struct Table
+{
+    material: Material,
+    pieces:   Vec<Piece>,
+    values:   [Vec<u8>; 2],
+}
+
+/// A set of tables, keyed by their canonical material signature.
+#[derive(Default)]
+pub struct Tablebase
+{
+    tables: HashMap<Material, Table>,
+}
+
+struct TableMove
+{
+    slot:      usize,
+    to:        u8,
+    capture:   Option<usize>,
+    promotion: Option<PieceType>,
+}
+
+impl Dtm
+{
+    fn decode(value: u8) -> Option<Self>
+    {
+        match value {
+            UNKNOWN => Some(Dtm::Draw),
+            BROKEN => None,
+            v if (v - 1) % 2 == 0 => Some(Dtm::Loss(v - 1)),
+            v => Some(Dtm::Win(v - 1)),
+        }
+    }
+}
+
+/// Orders pieces from the strongest to the weakest.
+fn piece_order(piece: PieceType) -> u8
+{
+    match piece {
+        Queen => 0,
+        Rook => 1,
+        Bishop => 2,
+        Knight => 3,
+        Pawn => 4,
+        King => 5,
+    }
+}
+
+fn side_index(color: Color) -> usize
+{
+    match color {
+        White => 0,
+        Black => 1,
+    }
+}
+
+impl Material
+{
+    fn new(mut white: Vec<PieceType>, mut black: Vec<PieceType>) -> Self
+    {
+        white.sort_by_key(|p| piece_order(*p));
+        black.sort_by_key(|p| piece_order(*p));
+
+        Material { white, black }
+    }
+
+    /// Returns the signature with the stronger side as white.
+    pub fn canonical(&self) -> Self
+    {
+        let strength = |pieces: &[PieceType]| {
+            let values: Vec<u8> =
+                pieces.iter().map(|p| 5 - piece_order(*p)).collect();
+            (pieces.len(), values)
+        };
+
+        if strength(&self.black) > strength(&self.white) {
+            self.flipped()
+        }
+        else {
+            self.clone()
+        }
+    }
+
+    /// Returns the signature with the colors swapped.
+    pub fn flipped(&self) -> Self
+    {
+        Material { white: self.black.clone(), black: self.white.clone() }
+    }
+
+    pub fn piece_count(&self) -> usize
+    {
+        2 + self.white.len() + self.black.len()
+    }
+
+    pub fn has_pawns(&self) -> bool
+    {
+        self.white.iter().chain(self.black.iter()).any(|p| *p == Pawn)
+    }
+
+    /// Returns the pieces in table slot order: both kings, then the remaining
+    /// white and black pieces.
+    fn slots(&self) -> Vec<Piece>
+    {
+        let mut pieces = vec![Piece { color: White, piece: King }, Piece {
+            color: Black,
+            piece: King,
+        }];
+        pieces.extend(
+            self.white.iter().map(|p| Piece { color: White, piece: *p }),
+        );
+        pieces.extend(
+            self.black.iter().map(|p| Piece { color: Black, piece: *p }),
+        );
+
+        pieces
+    }
+
+    /// Returns every signature reachable by a single capture or promotion.
+    fn successors(&self) -> Vec<Self>
+    {
+        let mut out = Vec::new();
+
+        for (i, piece) in self.white.iter().enumerate() {
+            let mut white = self.white.clone();
+            white.remove(i);
+            out.push(Material::new(white, self.black.clone()));
+
+            if *piece == Pawn {
+                for promotion in [Queen, Rook, Bishop, Knight] {
+                    let mut white = self.white.clone();
+                    white[i] = promotion;
+                    out.push(Material::new(white, self.black.clone()));
+                }
+            }
+        }
+        for (i, piece) in self.black.iter().enumerate() {
+            let mut black = self.black.clone();
+            black.remove(i);
+            out.push(Material::new(self.white.clone(), black));
+
+            if *piece == Pawn {
+                for promotion in [Queen, Rook, Bishop, Knight] {
+                    let mut black = self.black.clone();
+                    black[i] = promotion;
+                    out.push(Material::new(self.white.clone(), black));
+                }
+            }
+        }
+
+        out
+    }
+
+    fn from_pieces(pieces: &[(Piece, u8)]) -> Self
+    {
+        let side = |color| {
+            pieces
+                .iter()
+                .filter(|(p, _)| p.color == color && p.piece != King)
+                .map(|(p, _)| p.piece)
+                .collect()
+        };
+
+        Material::new(side(White), side(Black))
+    }
+}
+
+impl FromStr for Material
+{
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let s = s.to_uppercase();
+        let split = s.rfind('K').ok_or(format!("invalid material {}!", s))?;
+
+        if !s.starts_with('K') || split == 0 {
+            return Err(
+                format!("material {} needs exactly two kings!", s).into()
+            );
+        }
+
+        let parse = |pieces: &str| -> Result<Vec<PieceType>, Self::Err> {
+            pieces
+                .chars()
+                .map(|c| match Piece::from_str(&c.to_string())?.piece {
+                    King =>
+                        Err(format!("material {} has too many kings!", s)
+                            .into()),
+                    piece => Ok(piece),
+                })
+                .collect()
+        };
+
+        let material =
+            Material::new(parse(&s[1..split])?, parse(&s[split + 1..])?);
+        if material.piece_count() > MAX_PIECES {
+            return Err(format!(
+                "material {} has more than {} pieces!",
+                s, MAX_PIECES
+            )
+            .into());
+        }
+
+        Ok(material)
+    }
+}
+
+impl fmt::Display for Material
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let side = |pieces: &[PieceType]| -> String {
+            pieces.iter().map(|p| p.to_string()).collect()
+        };
+
+        write!(f, "K{}K{}", side(&self.white), side(&self.black))
+    }
+}
+
+impl Table
+{
+    fn new(material: Material) -> Self
+    {
+        let pieces = material.slots();
+        let mut table =
+            Table { material, pieces, values: [Vec::new(), Vec::new()] };
+
+        let size = table.size();
+        table.values = [vec![UNKNOWN; size], vec![UNKNOWN; size]];
+
+        table
+    }
+
+    pub fn material(&self) -> &Material { &self.material }
+
+    /// Returns the number of positions stored for each side to move.
+    pub fn size(&self) -> usize
+    {
+        self.king_squares() * 64usize.pow(self.pieces.len() as u32 - 1)
+    }
+
+    fn king_squares(&self) -> usize
+    {
+        match self.material.has_pawns() {
+            true => 32,
+            false => TRIANGLE.len(),
+        }
+    }
+
+    fn encode(&self, squares: &[u8]) -> usize
+    {
+        let king = squares[0] as usize;
+        let mut index = match self.material.has_pawns() {
+            true => (king / 8) * 4 + king % 8,
+            false =>
+                TRIANGLE.iter().position(|sq| *sq as usize == king).unwrap(),
+        };
+
+        for square in &squares[1..self.pieces.len()] {
+            index = index * 64 + *square as usize;
+        }
+
+        index
+    }
+
+    fn decode(&self, mut index: usize) -> [u8; MAX_PIECES]
+    {
+        let mut squares = [NONE; MAX_PIECES];
+
+        for i in (1..self.pieces.len()).rev() {
+            squares[i] = (index % 64) as u8;
+            index /= 64;
+        }
+        squares[0] = match self.material.has_pawns() {
+            true => ((index / 4) * 8 + index % 4) as u8,
+            false => TRIANGLE[index],
+        };
+
+        squares
+    }
+
+    /// Mirrors the position so that the white king lands on a canonical
+    /// square. Positions with the king on the a1-h8 diagonal are told apart by
+    /// the first piece off the diagonal.
+    fn canonicalize(&self, squares: &mut [u8])
+    {
+        let n = self.pieces.len();
+        let transform = |squares: &mut [u8], f: &dyn Fn(u8) -> u8| {
+            squares[..n]
+                .iter_mut()
+                .filter(|sq| **sq != NONE)
+                .for_each(|sq| *sq = f(*sq))
+        };
+
+        if squares[0] % 8 > 3 {
+            transform(squares, &|sq| sq ^ 7);
+        }
+        if self.material.has_pawns() {
+            return;
+        }
+        if squares[0] / 8 > 3 {
+            transform(squares, &|sq| sq ^ 56);
+        }
+
+        let transpose = |sq: u8| (sq % 8) * 8 + sq / 8;
+        let off_diagonal = squares[..n]
+            .iter()
+            .find(|sq| **sq != NONE && *sq / 8 != *sq % 8)
+            .is_some_and(|sq| sq / 8 > sq % 8);
+        if off_diagonal {
+            transform(squares, &transpose);
+        }
+    }
+
+    fn occupancy(&self, squares: &[u8], color: Option<Color>) -> u64
+    {
+        self.pieces
+            .iter()
+            .zip(squares.iter())
+            .filter(|(p, sq)| {
+                **sq != NONE && color.is_none_or(|c| p.color == c)
+            })
+            .fold(0, |acc, (_, sq)| acc | 1 << sq)
+    }
+
+    fn attacks_from(&self, slot: usize, squares: &[u8], occupied: u64) -> u64
+    {
+        let piece = self.pieces[slot];
+        let square = squares[slot] as usize;
+
+        match piece.piece {
+            Pawn => pawn_attacks(piece.color, square),
+            Knight => KNIGHT_ATTACKS[square],
+            Bishop => bishop_attacks(square, occupied),
+            Rook => rook_attacks(square, occupied),
+            Queen => queen_attacks(square, occupied),
+            King => KING_ATTACKS[square],
+        }
+    }
+
+    fn is_attacked(&self, squares: &[u8], target: u8, by: Color) -> bool
+    {
+        let occupied = self.occupancy(squares, None);
+
+        (0..self.pieces.len()).any(|slot| {
+            self.pieces[slot].color == by
+                && squares[slot] != NONE
+                && self.attacks_from(slot, squares, occupied) & 1 << target != 0
+        })
+    }
+
+    fn king_slot(color: Color) -> usize { side_index(color) }
+
+    fn is_legal(&self, squares: &[u8], turn: Color) -> bool
+    {
+        let n = self.pieces.len();
+        let occupied = self.occupancy(squares, None);
+
+        if occupied.count_ones() as usize != n {
+            return false;
+        }
+        let pawn_on_back_rank = (0..n).any(|slot| {
+            self.pieces[slot].piece == Pawn
+                && (squares[slot] < 8 || squares[slot] >= 56)
+        });
+        if pawn_on_back_rank {
+            return false;
+        }
+
+        let other_king = squares[Self::king_slot(turn.opposite())];
+        !self.is_attacked(squares, other_king, turn)
+    }
+
+    fn apply(&self, squares: &[u8], mv: &TableMove) -> [u8; MAX_PIECES]
+    {
+        let mut out = [NONE; MAX_PIECES];
+        out[..self.pieces.len()].copy_from_slice(&squares[..self.pieces.len()]);
+
+        if let Some(captured) = mv.capture {
+            out[captured] = NONE;
+        }
+        out[mv.slot] = mv.to;
+
+        out
+    }
+
+    fn generate_moves(&self, squares: &[u8], turn: Color) -> Vec<TableMove>
+    {
+        let own = self.occupancy(squares, Some(turn));
+        let occupied = self.occupancy(squares, None);
+        let mut moves = Vec::new();
+
+        for slot in 0..self.pieces.len() {
+            let piece = self.pieces[slot];
+            if piece.color != turn {
+                continue;
+            }
+            let from = squares[slot];
+
+            let mut targets = match piece.piece {
+                Pawn =>
+                    self.attacks_from(slot, squares, occupied) & occupied & !own,
+                _ => self.attacks_from(slot, squares, occupied) & !own,
+            };
+            if piece.piece == Pawn {
+                let (step, start_rank): (i8, u8) = match turn {
+                    White => (8, 1),
+                    Black => (-8, 6),
+                };
+                let single = (from as i8 + step) as u8;
+                if occupied & 1 << single == 0 {
+                    targets |= 1 << single;
+                    let double = (single as i8 + step) as u8;
+                    if from / 8 == start_rank && occupied & 1 << double == 0 {
+                        targets |= 1 << double;
+                    }
+                }
+            }
+
+            while targets != 0 {
+                let to = targets.trailing_zeros() as u8;
+                targets &= targets - 1;
+
+                let capture =
+                    (0..self.pieces.len()).find(|s| squares[*s] == to);
+                let promotions: &[Option<PieceType>] = match piece.piece {
+                    Pawn if !(8..56).contains(&to) =>
+                        &[Some(Queen), Some(Rook), Some(Bishop), Some(Knight)],
+                    _ => &[None],
+                };
+
+                for promotion in promotions {
+                    let mv =
+                        TableMove { slot, to, capture, promotion: *promotion };
+                    let after = self.apply(squares, &mv);
+                    let king = after[Self::king_slot(turn)];
+                    if !self.is_attacked(&after, king, turn.opposite()) {
+                        moves.push(mv);
+                    }
+                }
+            }
+        }
+
+        moves
+    }
+
+    /// Returns every legal position from which the opponent of `turn` could
+    /// have reached this one with a quiet move.
+    fn generate_unmoves(&self, squares: &[u8], turn: Color) -> Vec<usize>
+    {
+        let mover = turn.opposite();
+        let occupied = self.occupancy(squares, None);
+        let mut out = Vec::new();
+
+        for slot in 0..self.pieces.len() {
+            let piece = self.pieces[slot];
+            if piece.color != mover {
+                continue;
+            }
+            let to = squares[slot];
+
+            let mut sources = match piece.piece {
+                Pawn => {
+                    let (step, push_rank): (i8, u8) = match mover {
+                        White => (-8, 3),
+                        Black => (8, 4),
+                    };
+                    let mut sources = 0;
+                    let single = (to as i8 + step) as u8;
+                    if occupied & 1 << single == 0 && (8..56).contains(&single)
+                    {
+                        sources |= 1 << single;
+                        let double = (single as i8 + step) as u8;
+                        if to / 8 == push_rank && occupied & 1 << double == 0 {
+                            sources |= 1 << double;
+                        }
+                    }
+                    sources
+                },
+                _ => self.attacks_from(slot, squares, occupied) & !occupied,
+            };
+
+            while sources != 0 {
+                let from = sources.trailing_zeros() as u8;
+                sources &= sources - 1;
+
+                let mut previous = [NONE; MAX_PIECES];
+                previous[..self.pieces.len()]
+                    .copy_from_slice(&squares[..self.pieces.len()]);
+                previous[slot] = from;
+
+                if self.is_legal(&previous, mover) {
+                    self.canonicalize(&mut previous);
+                    out.push(self.encode(&previous));
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Looks up the result of the position reached by a move, either in this
+    /// table or in one of the tables it converts into.
+    fn child_value(
+        &self, tablebase: &Tablebase, squares: &[u8], turn: Color,
+        mv: &TableMove,
+    ) -> Option<Dtm>
+    {
+        let after = self.apply(squares, mv);
+        let next = turn.opposite();
+
+        if mv.capture.is_none() && mv.promotion.is_none() {
+            let mut after = after;
+            self.canonicalize(&mut after);
+            let value = self.values[side_index(next)][self.encode(&after)];
+
+            return match value {
+                UNKNOWN => None,
+                value => Dtm::decode(value),
+            };
+        }
+
+        let pieces: Vec<(Piece, u8)> = (0..self.pieces.len())
+            .filter(|slot| after[*slot] != NONE)
+            .map(|slot| {
+                let mut piece = self.pieces[slot];
+                if slot == mv.slot {
+                    piece.piece = mv.promotion.unwrap_or(piece.piece);
+                }
+                (piece, after[slot])
+            })
+            .collect();
+
+        tablebase.probe_pieces(&pieces, next)
+    }
+
+    /// Returns the distance to mate of a position whose every move is known to
+    /// lose, or None if any move does not.
+    fn resolve_loss(
+        &self, tablebase: &Tablebase, squares: &[u8], turn: Color,
+    ) -> Option<u8>
+    {
+        let mut longest = 0;
+
+        for mv in self.generate_moves(squares, turn) {
+            match self.child_value(tablebase, squares, turn, &mv)? {
+                Dtm::Win(plies) => longest = longest.max(plies),
+                _ => return None,
+            }
+        }
+
+        Some(longest + 1)
+    }
+
+    /// Computes the table by retrograde analysis. Every table this one
+    /// converts into must already be present in `tablebase`.
+    fn generate(material: Material, tablebase: &Tablebase) -> Self
+    {
+        let mut table = Table::new(material);
+        let mut buckets: Vec<Vec<(usize, usize)>> = vec![Vec::new()];
+        let push =
+            |buckets: &mut Vec<Vec<(usize, usize)>>, level: usize, entry| {
+                if buckets.len() <= level {
+                    buckets.resize(level + 1, Vec::new());
+                }
+                buckets[level].push(entry);
+            };
+
+        // mark broken positions and seed mates and conversions
+        for index in 0..table.size() {
+            let squares = table.decode(index);
+            let mut canonical = squares;
+            table.canonicalize(&mut canonical);
+
+            for turn in [White, Black] {
+                let side = side_index(turn);
+                if canonical != squares || !table.is_legal(&squares, turn) {
+                    table.values[side][index] = BROKEN;
+                    continue;
+                }
+
+                let moves = table.generate_moves(&squares, turn);
+                if moves.is_empty() {
+                    let king = squares[Table::king_slot(turn)];
+                    if table.is_attacked(&squares, king, turn.opposite()) {
+                        push(&mut buckets, 0, (side, index));
+                    }
+                    continue;
+                }
+
+                let mut quiet_moves = false;
+                let mut escapes = false;
+                let mut fastest_win = None;
+                let mut slowest_loss = 0;
+                for mv in moves.iter() {
+                    if mv.capture.is_none() && mv.promotion.is_none() {
+                        quiet_moves = true;
+                        continue;
+                    }
+                    match table.child_value(tablebase, &squares, turn, mv) {
+                        Some(Dtm::Loss(plies)) =>
+                            fastest_win =
+                                Some(fastest_win.map_or(plies + 1, |w: u8| {
+                                    w.min(plies + 1)
+                                })),
+                        Some(Dtm::Win(plies)) =>
+                            slowest_loss = slowest_loss.max(plies + 1),
+                        _ => escapes = true,
+                    }
+                }
+
+                if let Some(plies) = fastest_win {
+                    push(&mut buckets, plies as usize, (side, index));
+                }
+                else if !quiet_moves && !escapes {
+                    push(&mut buckets, slowest_loss as usize, (side, index));
+                }
+            }
+        }
+
+        let mut level = 0;
+        while level < buckets.len() {
+            assert!(
+                level < BROKEN as usize - 1,
+                "distance to mate is too long to store!"
+            );
+
+            for (side, index) in std::mem::take(&mut buckets[level]) {
+                if table.values[side][index] != UNKNOWN {
+                    continue;
+                }
+                table.values[side][index] = level as u8 + 1;
+
+                let turn = if side == 0 { White } else { Black };
+                let squares = table.decode(index);
+                for previous in table.generate_unmoves(&squares, turn) {
+                    if table.values[1 - side][previous] != UNKNOWN {
+                        continue;
+                    }
+                    if level % 2 == 0 {
+                        push(&mut buckets, level + 1, (1 - side, previous));
+                    }
+                    else {
+                        let previous_squares = table.decode(previous);
+                        let loss = table.resolve_loss(
+                            tablebase,
+                            &previous_squares,
+                            turn.opposite(),
+                        );
+                        if let Some(plies) = loss {
+                            push(
+                                &mut buckets,
+                                plies as usize,
+                                (1 - side, previous),
+                            );
+                        }
+                    }
+                }
+            }
+            level += 1;
+        }
+
+        table
+    }
+
+    /// Looks up a position given in this table's colors.
+    fn probe_pieces(&self, pieces: &[(Piece, u8)], turn: Color) -> Option<Dtm>
+    {
+        let mut squares = [NONE; MAX_PIECES];
+        let mut used = [false; MAX_PIECES];
+
+        for (slot, wanted) in self.pieces.iter().enumerate() {
+            let i = (0..pieces.len())
+                .find(|i| !used[*i] && pieces[*i].0 == *wanted)?;
+            used[i] = true;
+            squares[slot] = pieces[i].1;
+        }
+
+        self.canonicalize(&mut squares);
+        Dtm::decode(self.values[side_index(turn)][self.encode(&squares)])
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> Result<(), Box<dyn Error>>
+    {
+        let name = self.material.to_string();
+
+        w.write_all(FILE_MAGIC)?;
+        w.write_all(&[FILE_VERSION, name.len() as u8])?;
+        w.write_all(name.as_bytes())?;
+        w.write_all(&(self.size() as u64).to_le_bytes())?;
+
+        // each side is stored as runs of (value, varint length)
+        for values in self.values.iter() {
+            let mut i = 0;
+            while i < values.len() {
+                let value = values[i];
+                let mut run =
+                    values[i..].iter().take_while(|v| **v == value).count();
+                i += run;
+
+                w.write_all(&[value])?;
+                while run >= 0x80 {
+                    w.write_all(&[(run as u8 & 0x7f) | 0x80])?;
+                    run >>= 7;
+                }
+                w.write_all(&[run as u8])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> Result<Self, Box<dyn Error>>
+    {
+        let mut byte = [0; 1];
+        let mut next_byte = |r: &mut R| -> Result<u8, Box<dyn Error>> {
+            r.read_exact(&mut byte)?;
+            Ok(byte[0])
+        };
+
+        let mut magic = [0; 6];
+        r.read_exact(&mut magic)?;
+        if &magic != FILE_MAGIC {
+            return Err("not a tablebase file!".into());
+        }
+        let version = next_byte(r)?;
+        if version != FILE_VERSION {
+            return Err(
+                format!("unsupported tablebase version {}!", version).into()
+            );
+        }
+
+        let mut name = vec![0; next_byte(r)? as usize];
+        r.read_exact(&mut name)?;
+        let material = Material::from_str(std::str::from_utf8(&name)?)?;
+
+        let mut size = [0; 8];
+        r.read_exact(&mut size)?;
+        let mut table = Table::new(material);
+        if u64::from_le_bytes(size) != table.size() as u64 {
+            return Err(format!(
+                "tablebase {} has the wrong size!",
+                table.material
+            )
+            .into());
+        }
+
+        for side in 0..2 {
+            let mut i = 0;
+            while i < table.size() {
+                let value = next_byte(r)?;
+                let mut run = 0;
+                let mut shift = 0;
+                loop {
+                    let b = next_byte(r)?;
+                    run |= ((b & 0x7f) as usize) << shift;
+                    shift += 7;
+                    if b & 0x80 == 0 {
+                        break;
+                    }
+                }
+                if i + run > table.size() {
+                    return Err(format!(
+                        "tablebase {} is corrupt!",
+                        table.material
+                    )
+                    .into());
+                }
+
+                table.values[side][i..i + run]
+                    .iter_mut()
+                    .for_each(|v| *v = value);
+                i += run;
+            }
+        }
+
+        Ok(table)
+    }
+}
+
+impl Tablebase
+{
+    pub fn new() -> Self { Self::default() }
+
+    pub fn get(&self, material: &Material) -> Option<&Table>
+    {
+        self.tables.get(&material.canonical())
+    }
+
+    /// Generates the table for a signature, along with every table it converts
+    /// into that is not already present.
+    pub fn generate(&mut self, material: &Material)
+    {
+        let material = material.canonical();
+        if material.piece_count() == 2 || self.tables.contains_key(&material) {
+            return;
+        }
+
+        for successor in material.successors() {
+            self.generate(&successor);
+        }
+
+        let table = Table::generate(material.clone(), self);
+        self.tables.insert(material, table);
+    }
+
+    /// Loads every table file found in a directory.
+    pub fn load(&mut self, dir: &Path) -> Result<(), Box<dyn Error>>
+    {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == FILE_EXTENSION) {
+                let table =
+                    Table::read(&mut BufReader::new(File::open(&path)?))?;
+                self.tables.insert(table.material.clone(), table);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes every table to a directory, one file per signature.
+    pub fn save(&self, dir: &Path) -> Result<(), Box<dyn Error>>
+    {
+        fs::create_dir_all(dir)?;
+
+        for (material, table) in self.tables.iter() {
+            let path = dir.join(format!("{}.{}", material, FILE_EXTENSION));
+            let mut file = BufWriter::new(File::create(path)?);
+            table.write(&mut file)?;
+            file.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Looks up a position, returning None if it has castling rights, an en
+    /// passant target, or no table covering its material.
+    pub fn probe(&self, board: &BitBoardState) -> Option<Dtm>
+    {
+        if board.castle_availability().any()
+            || board.en_passant_target().is_some()
+        {
+            return None;
+        }
+
+        let pieces: Vec<(Piece, u8)> = board
+            .as_piece_array()
+            .iter()
+            .enumerate()
+            .filter_map(|(sq, piece)| piece.map(|p| (p, sq as u8)))
+            .collect();
+
+        self.probe_pieces(&pieces, board.turn())
+    }
+
+    /// Finds the move with the best result in a position: the quickest mate,
+    /// then a draw, then the slowest loss. Returns None unless the position
+    /// after every move can be looked up.
+    pub fn best_move(&self, board: &BitBoardState) -> Option<(Move, Dtm)>
+    {
+        self.probe(board)?;
+
+        // how good a result is for the side to move, to rank them by
+        let rank = |dtm: Dtm| match dtm {
+            Dtm::Win(plies) => 1000 - plies as i32,
+            Dtm::Draw => 0,
+            Dtm::Loss(plies) => -1000 + plies as i32,
+        };
+
+        let mut board = board.clone();
+        let mut best: Option<(Move, Dtm)> = None;
+        for mv in board.legal_moves() {
+            board.make_move(mv);
+            let after = self.probe(&board);
+            board.unmake_move();
+
+            let result = match after? {
+                Dtm::Win(plies) => Dtm::Loss(plies + 1),
+                Dtm::Loss(plies) => Dtm::Win(plies + 1),
+                Dtm::Draw => Dtm::Draw,
+            };
+            if best.is_none_or(|(_, dtm)| rank(result) > rank(dtm)) {
+                best = Some((mv, result));
+            }
+        }
+
+        best
+    }
+
+    fn probe_pieces(&self, pieces: &[(Piece, u8)], turn: Color) -> Option<Dtm>
+    {
+        if pieces.len() == 2 {
+            return Some(Dtm::Draw);
+        }
+
+        let material = Material::from_pieces(pieces);
+        let canonical = material.canonical();
+        let table = self.tables.get(&canonical)?;
+
+        if canonical == material {
+            table.probe_pieces(pieces, turn)
+        }
+        else {
+            let flipped: Vec<(Piece, u8)> = pieces
+                .iter()
+                .map(|(p, sq)| {
+                    (
+                        Piece { color: p.color.opposite(), piece: p.piece },
+                        sq ^ 56,
+                    )
+                })
+                .collect();
+            table.probe_pieces(&flipped, turn.opposite())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::str::FromStr;
+
+    use super::{Dtm, Material, Table, Tablebase};
+    use crate::board::{BitBoardState, GameState};
+
+    fn probe(tablebase: &Tablebase, fen: &str) -> Option<Dtm>
+    {
+        tablebase.probe(&BitBoardState::from_fen(fen).unwrap())
+    }
+
+    fn longest_win(table: &Table) -> u8
+    {
+        table.values[0]
+            .iter()
+            .filter(|v| **v != 0 && **v != 255 && **v % 2 == 0)
+            .max()
+            .unwrap()
+            - 1
+    }
+
+    #[test]
+    fn test_material_from_string()
+    {
+        let material = Material::from_str("KRKP").unwrap();
+        assert_eq!(material.to_string(), "KRKP");
+        assert_eq!(Material::from_str("kpkr").unwrap().canonical(), material);
+        assert_eq!(Material::from_str("KNBK").unwrap().to_string(), "KBNK");
+
+        assert!(Material::from_str("KQ").is_err());
+        assert!(Material::from_str("KQKKR").is_err());
+        assert!(Material::from_str("KQRBNK").is_err());
+    }
+
+    #[test]
+    fn test_generate_kqk_and_krk()
+    {
+        let mut tablebase = Tablebase::new();
+        tablebase.generate(&Material::from_str("KQK").unwrap());
+        tablebase.generate(&Material::from_str("KRK").unwrap());
+
+        // the longest mates are 10 and 16 moves long
+        let kqk = tablebase.get(&Material::from_str("KQK").unwrap()).unwrap();
+        let krk = tablebase.get(&Material::from_str("KRK").unwrap()).unwrap();
+        assert_eq!(longest_win(kqk), 19);
+        assert_eq!(longest_win(krk), 31);
+
+        assert_eq!(
+            probe(&tablebase, "k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"),
+            Some(Dtm::Loss(0))
+        );
+        assert_eq!(
+            probe(&tablebase, "k7/8/1K6/8/8/8/8/2Q5 w - - 0 1"),
+            Some(Dtm::Win(1))
+        );
+        assert_eq!(
+            probe(&tablebase, "8/8/8/8/8/8/k7/1Q5K b - - 0 1"),
+            Some(Dtm::Draw)
+        );
+        assert_eq!(
+            probe(&tablebase, "r7/8/8/8/8/8/2k5/K7 w - - 0 1"),
+            Some(Dtm::Loss(0))
+        );
+        assert_eq!(probe(&tablebase, "8/8/8/8/8/8/8/KBk5 w - - 0 1"), None);
+
+        let board = BitBoardState::from_fen("k7/8/1K6/8/8/8/8/2Q5 w - - 0 1");
+        let (mv, dtm) = tablebase.best_move(&board.unwrap()).unwrap();
+        assert_eq!(dtm, Dtm::Win(1));
+        assert_eq!(mv.to_string(), "c1c8");
+
+        // the rook can only be saved by taking it
+        let board = BitBoardState::from_fen("8/8/8/8/8/2k5/8/Kr6 w - - 0 1");
+        let (mv, dtm) = tablebase.best_move(&board.unwrap()).unwrap();
+        assert_eq!((mv.to_string().as_str(), dtm), ("a1b1", Dtm::Draw));
+    }
+
+    #[test]
+    fn test_generate_kpk()
+    {
+        let mut tablebase = Tablebase::new();
+        tablebase.generate(&Material::from_str("KPK").unwrap());
+
+        // the defending king reaches the corner in front of a rook pawn
+        assert_eq!(
+            probe(&tablebase, "k7/8/8/8/8/8/P7/K7 w - - 0 1"),
+            Some(Dtm::Draw)
+        );
+        assert!(matches!(
+            probe(&tablebase, "8/8/8/8/8/k7/4P3/4K3 w - - 0 1"),
+            Some(Dtm::Win(_))
+        ));
+        assert!(matches!(
+            probe(&tablebase, "4k3/4p3/K7/8/8/8/8/8 b - - 0 1"),
+            Some(Dtm::Win(_))
+        ));
+    }
+
+    #[test]
+    fn test_table_round_trip()
+    {
+        let mut tablebase = Tablebase::new();
+        tablebase.generate(&Material::from_str("KQK").unwrap());
+        let table = tablebase.get(&Material::from_str("KQK").unwrap()).unwrap();
+
+        let mut buffer = Vec::new();
+        table.write(&mut buffer).unwrap();
+        assert!(buffer.len() < 2 * table.size());
+
+        let read = Table::read(&mut buffer.as_slice()).unwrap();
+        assert_eq!(read.material, table.material);
+        assert_eq!(read.values, table.values);
+
+        assert!(Table::read(&mut &buffer[..20]).is_err());
+        assert!(Table::read(&mut &b"nonsense"[..]).is_err());
+    }
+}
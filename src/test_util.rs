@@ -0,0 +1,38 @@
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// A path in the temporary directory for one test, unique to this run so
+/// that concurrent runs don't collide, and removed once dropped even if the
+/// test fails.
+pub struct TempPath(PathBuf);
+
+impl TempPath
+{
+    pub fn new(name: &str) -> Self
+    {
+        let name = format!("bcld-{}-{}", std::process::id(), name);
+        TempPath(std::env::temp_dir().join(name))
+    }
+}
+
+impl Deref for TempPath
+{
+    type Target = Path;
+
+    fn deref(&self) -> &Path { &self.0 }
+}
+
+impl AsRef<Path> for TempPath
+{
+    fn as_ref(&self) -> &Path { &self.0 }
+}
+
+impl Drop for TempPath
+{
+    fn drop(&mut self)
+    {
+        let _ = fs::remove_file(&self.0);
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
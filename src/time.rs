@@ -0,0 +1,328 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::piece::Color;
+use crate::search::SearchLimits;
+
+/// Time kept back on every move for communication delays, unless told
+/// otherwise.
+pub const DEFAULT_MOVE_OVERHEAD: Duration = Duration::from_millis(10);
+/// The number of moves assumed to be left when the time control doesn't say.
+const DEFAULT_MOVES_TO_GO: u32 = 30;
+
+/// A source of the current time, which can be replaced for testing.
+pub trait Clock: Send + Sync
+{
+    /// Returns the time since some fixed point.
+    fn now(&self) -> Duration;
+}
+
+/// The real time.
+pub struct SystemClock
+{
+    start: Instant,
+}
+
+impl Default for SystemClock
+{
+    fn default() -> Self { SystemClock { start: Instant::now() } }
+}
+
+impl Clock for SystemClock
+{
+    fn now(&self) -> Duration { self.start.elapsed() }
+}
+
+/// A clock that only moves when told to, or by a fixed step each time it is
+/// read.
+#[derive(Default)]
+pub struct MockClock
+{
+    now:  Mutex<Duration>,
+    tick: Duration,
+}
+
+impl MockClock
+{
+    /// Creates a clock that moves forward by `tick` whenever it is read.
+    pub fn with_tick(tick: Duration) -> Self
+    {
+        MockClock { now: Mutex::new(Duration::ZERO), tick }
+    }
+
+    pub fn advance(&self, by: Duration) { *self.now.lock().unwrap() += by; }
+}
+
+impl Clock for MockClock
+{
+    fn now(&self) -> Duration
+    {
+        let mut now = self.now.lock().unwrap();
+        *now += self.tick;
+        *now
+    }
+}
+
+/// Decides how long to spend on a move.
+///
+/// The soft limit is checked between iterations of the search, and stretched
+/// when the best move keeps changing or the score drops. The hard limit stops
+/// the search wherever it is.
+///
+/// While pondering neither limit applies. The clock starts once the opponent
+/// plays the expected move, since until then the time is theirs.
+pub struct TimeManager
+{
+    clock:     Arc<dyn Clock>,
+    start:     Duration,
+    /// When the engine's own clock started running.
+    own_start: Mutex<Duration>,
+    soft:      Option<Duration>,
+    hard:      Option<Duration>,
+    /// Set until a ponderhit has been seen, when pondering.
+    pondering: AtomicBool,
+    ponderhit: Arc<AtomicBool>,
+}
+
+impl TimeManager
+{
+    /// Starts timing a search with the given limits for the side to move,
+    /// keeping back the overhead from any time allowed.
+    pub fn new(
+        limits: &SearchLimits, turn: Color, clock: Arc<dyn Clock>,
+        overhead: Duration,
+    ) -> Self
+    {
+        let start = clock.now();
+        let (time, increment) = match turn {
+            Color::White => (limits.wtime, limits.winc),
+            Color::Black => (limits.btime, limits.binc),
+        };
+
+        let (soft, hard) = match (limits.infinite, limits.movetime, time) {
+            (true, ..) => (None, None),
+            (false, Some(movetime), _) => {
+                let limit = movetime.saturating_sub(overhead);
+                (Some(limit), Some(limit))
+            },
+            (false, None, Some(time)) => {
+                let (soft, hard) = allocate(
+                    time.saturating_sub(overhead),
+                    increment.unwrap_or_default(),
+                    limits.movestogo,
+                );
+                (Some(soft), Some(hard))
+            },
+            (false, None, None) => (None, None),
+        };
+
+        TimeManager {
+            clock,
+            start,
+            own_start: Mutex::new(start),
+            soft,
+            hard,
+            pondering: AtomicBool::new(limits.ponder),
+            ponderhit: limits.ponderhit.clone(),
+        }
+    }
+
+    pub fn soft_limit(&self) -> Option<Duration> { self.soft }
+
+    pub fn hard_limit(&self) -> Option<Duration> { self.hard }
+
+    /// Returns true if the search is bound by the clock.
+    pub fn is_timed(&self) -> bool { self.hard.is_some() }
+
+    /// Returns the time since the search started, pondering included.
+    pub fn elapsed(&self) -> Duration
+    {
+        self.clock.now().saturating_sub(self.start)
+    }
+
+    /// Returns true while the opponent has yet to play the move being
+    /// pondered on, starting the engine's clock once they have.
+    pub fn is_pondering(&self) -> bool
+    {
+        if !self.pondering.load(Ordering::Relaxed) {
+            return false;
+        }
+        if !self.ponderhit.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        *self.own_start.lock().unwrap() = self.clock.now();
+        self.pondering.store(false, Ordering::Relaxed);
+        false
+    }
+
+    /// Returns the time spent on the engine's own clock.
+    fn used(&self) -> Duration
+    {
+        self.clock.now().saturating_sub(*self.own_start.lock().unwrap())
+    }
+
+    /// Returns true once the search must stop, even in the middle of an
+    /// iteration.
+    pub fn out_of_time(&self) -> bool
+    {
+        match self.hard {
+            Some(hard) => !self.is_pondering() && self.used() >= hard,
+            None => false,
+        }
+    }
+
+    /// Returns true if the search shouldn't start another iteration, given
+    /// how often the best move has changed lately and how far the score has
+    /// dropped since the last iteration in centipawns.
+    pub fn should_stop(&self, best_move_changes: f64, score_drop: i32) -> bool
+    {
+        let (soft, hard) = match (self.soft, self.hard) {
+            (Some(soft), Some(hard)) if !self.is_pondering() => (soft, hard),
+            _ => return false,
+        };
+
+        let instability = 1.0 + best_move_changes;
+        let falling = 1.0 + score_drop.clamp(0, 100) as f64 / 100.0;
+        let limit = soft.mul_f64(instability * falling).min(hard);
+
+        self.used() >= limit
+    }
+}
+
+/// Splits the time available into a soft and hard limit for this move.
+fn allocate(
+    available: Duration, increment: Duration, moves_to_go: Option<u32>,
+) -> (Duration, Duration)
+{
+    let moves = moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).clamp(1, 50);
+
+    let soft = (available / moves + increment.mul_f64(0.75)).min(available);
+    let hard = match moves {
+        1 => available,
+        _ => (soft * 4).min(available.mul_f64(0.75)),
+    };
+
+    (soft.min(hard), hard)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn manager(limits: SearchLimits, turn: Color) -> TimeManager
+    {
+        let clock = Arc::new(MockClock::default());
+        TimeManager::new(&limits, turn, clock, DEFAULT_MOVE_OVERHEAD)
+    }
+
+    fn ms(ms: u64) -> Duration { Duration::from_millis(ms) }
+
+    #[test]
+    fn test_allocation()
+    {
+        let limits = SearchLimits {
+            wtime: Some(ms(60_010)),
+            btime: Some(ms(3_010)),
+            winc: Some(ms(1000)),
+            ..Default::default()
+        };
+
+        let white = manager(limits.clone(), Color::White);
+        assert_eq!(white.soft_limit(), Some(ms(2750)));
+        assert_eq!(white.hard_limit(), Some(ms(11_000)));
+
+        let black = manager(limits, Color::Black);
+        assert_eq!(black.soft_limit(), Some(ms(100)));
+        assert_eq!(black.hard_limit(), Some(ms(400)));
+
+        let last_move = SearchLimits {
+            wtime: Some(ms(5_010)),
+            movestogo: Some(1),
+            ..Default::default()
+        };
+        let last_move = manager(last_move, Color::White);
+        assert_eq!(last_move.soft_limit(), Some(ms(5_000)));
+        assert_eq!(last_move.hard_limit(), Some(ms(5_000)));
+    }
+
+    #[test]
+    fn test_fixed_and_unlimited_time()
+    {
+        let movetime =
+            SearchLimits { movetime: Some(ms(510)), ..Default::default() };
+        let movetime = manager(movetime, Color::Black);
+        assert_eq!(movetime.soft_limit(), Some(ms(500)));
+        assert_eq!(movetime.hard_limit(), Some(ms(500)));
+
+        let limits =
+            SearchLimits { movetime: Some(ms(510)), ..Default::default() };
+        let clock = Arc::new(MockClock::default());
+        let slow_link = TimeManager::new(&limits, Color::White, clock, ms(110));
+        assert_eq!(slow_link.hard_limit(), Some(ms(400)));
+
+        let infinite = SearchLimits {
+            wtime: Some(ms(1000)),
+            infinite: true,
+            ..Default::default()
+        };
+        assert!(!manager(infinite, Color::White).is_timed());
+        assert!(!manager(SearchLimits::default(), Color::White).is_timed());
+    }
+
+    #[test]
+    fn test_soft_limit_is_extended()
+    {
+        let clock = Arc::new(MockClock::default());
+        let limits =
+            SearchLimits { wtime: Some(ms(30_010)), ..Default::default() };
+        let time = TimeManager::new(
+            &limits,
+            Color::White,
+            clock.clone(),
+            DEFAULT_MOVE_OVERHEAD,
+        );
+
+        clock.advance(ms(1200));
+        assert!(time.should_stop(0.0, 0));
+        assert!(!time.should_stop(1.0, 0));
+        assert!(!time.should_stop(0.0, 50));
+        assert!(!time.out_of_time());
+
+        clock.advance(ms(3000));
+        assert!(time.should_stop(1.0, 100));
+        assert!(time.out_of_time());
+    }
+
+    #[test]
+    fn test_ponderhit_starts_the_clock()
+    {
+        let clock = Arc::new(MockClock::default());
+        let limits = SearchLimits {
+            movetime: Some(ms(1010)),
+            ponder: true,
+            ..Default::default()
+        };
+        let time = TimeManager::new(
+            &limits,
+            Color::White,
+            clock.clone(),
+            DEFAULT_MOVE_OVERHEAD,
+        );
+
+        clock.advance(ms(5000));
+        assert!(time.is_pondering());
+        assert!(!time.out_of_time());
+        assert!(!time.should_stop(0.0, 0));
+
+        limits.ponderhit.store(true, Ordering::Relaxed);
+        assert!(!time.out_of_time());
+        assert!(!time.is_pondering());
+
+        clock.advance(ms(1000));
+        assert!(time.out_of_time());
+        assert_eq!(time.elapsed(), ms(6000));
+    }
+}
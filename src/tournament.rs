@@ -0,0 +1,922 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
+
+use crate::board::{BitBoardState, GameState};
+use crate::engine::Engine;
+use crate::moves::Move;
+use crate::options::Options;
+use crate::pgn::{parse_pgn, Game, GameResult};
+use crate::piece::Color;
+use crate::search::{SearchLimits, SearchScore};
+use crate::selfplay::MAX_GAME_PLIES;
+use crate::tablebase::{Dtm, Tablebase};
+use crate::uci_client::UciClient;
+
+/// The z-score of a 95% confidence interval.
+const CONFIDENCE: f64 = 1.96;
+/// Treats a mate score as this many centipawns when adjudicating.
+const MATE_CENTIPAWNS: i32 = 100_000;
+
+/// One side of a match, asked for its move with the clocks in the limits.
+pub trait Player
+{
+    fn name(&self) -> &str;
+
+    /// Forgets the previous game.
+    fn new_game(&mut self) -> Result<(), Box<dyn Error>>;
+
+    /// Returns the move to play at the end of a game, along with the score
+    /// the player gave it, for its own side.
+    fn go(
+        &mut self, game: &Game, limits: &SearchLimits,
+    ) -> Result<(Move, Option<SearchScore>), Box<dyn Error>>;
+}
+
+/// bcld itself, set up with engine options.
+struct BcldPlayer
+{
+    name:   String,
+    engine: Engine,
+}
+
+/// How to start one of the engines in a match.
+#[derive(Clone, Default, Debug)]
+pub struct EngineConfig
+{
+    pub name:    Option<String>,
+    /// A UCI executable to run, or `None` to play bcld.
+    pub command: Option<String>,
+    /// Engine options, by name, to set before the first game.
+    pub options: Vec<(String, String)>,
+}
+
+/// The time each side starts with, and gets back after every move.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimeControl
+{
+    pub base:      Duration,
+    pub increment: Duration,
+}
+
+/// When to end games early, rather than play them out.
+#[derive(Clone, Default)]
+pub struct Adjudication
+{
+    /// A side resigns after this many moves in a row scored at least this
+    /// many centipawns against it.
+    pub resign:    Option<(i32, u32)>,
+    /// A game is drawn once both sides have scored it within this many
+    /// centipawns for this many moves in a row each, from the given move
+    /// number on.
+    pub draw:      Option<(i32, u32, u32)>,
+    /// Games reaching a position in the tablebase are scored by it.
+    pub tablebase: Option<Arc<Tablebase>>,
+}
+
+/// A sequential probability ratio test between two hypotheses for the Elo
+/// difference, stopping the match as soon as one is likely enough.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Sprt
+{
+    pub elo0:  f64,
+    pub elo1:  f64,
+    /// The chances of wrongly accepting the second and first hypotheses.
+    pub alpha: f64,
+    pub beta:  f64,
+}
+
+/// Games won, lost and drawn by the first engine.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Stats
+{
+    pub wins:   u32,
+    pub losses: u32,
+    pub draws:  u32,
+}
+
+/// Everything describing a match.
+#[derive(Clone)]
+pub struct MatchConfig
+{
+    pub engines:      [EngineConfig; 2],
+    pub time_control: TimeControl,
+    /// Positions or move sequences each pair of games starts from.
+    pub openings:     Vec<Game>,
+    /// How many pairs of games to play, one with each color.
+    pub rounds:       usize,
+    /// How many games to play at once.
+    pub concurrency:  usize,
+    pub adjudication: Adjudication,
+    pub sprt:         Option<Sprt>,
+    pub pgn_out:      Option<PathBuf>,
+}
+
+impl EngineConfig
+{
+    /// Starts the engine and sets its options.
+    pub fn start(&self) -> Result<Box<dyn Player + Send>, Box<dyn Error>>
+    {
+        let mut player: Box<dyn Player + Send> = match &self.command {
+            Some(command) => {
+                let mut client = UciClient::spawn(command, &[])?;
+                for (name, value) in self.options.iter() {
+                    client.set_option(name, value)?;
+                }
+                client.sync()?;
+                Box::new(client)
+            },
+            None => {
+                let mut engine = Engine::default();
+                let mut options = Options::default();
+                for (name, value) in self.options.iter() {
+                    options.set(&mut engine, name, Some(value))?;
+                }
+                Box::new(BcldPlayer { name: "bcld".to_string(), engine })
+            },
+        };
+
+        if let Some(name) = &self.name {
+            player = Box::new(Renamed { name: name.clone(), player });
+        }
+        Ok(player)
+    }
+
+    /// Parses `name=NAME`, `cmd=COMMAND` and `OPTION=VALUE` arguments.
+    fn parse(args: &[&str]) -> Result<Self, Box<dyn Error>>
+    {
+        let mut config = EngineConfig::default();
+        for arg in args {
+            let (key, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, not {}!", arg))?;
+            match key {
+                "name" => config.name = Some(value.to_string()),
+                "cmd" => config.command = Some(value.to_string()),
+                _ => config.options.push((key.to_string(), value.to_string())),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// A player going by another name, to tell two configurations apart.
+struct Renamed
+{
+    name:   String,
+    player: Box<dyn Player + Send>,
+}
+
+impl Player for Renamed
+{
+    fn name(&self) -> &str { &self.name }
+
+    fn new_game(&mut self) -> Result<(), Box<dyn Error>>
+    {
+        self.player.new_game()
+    }
+
+    fn go(
+        &mut self, game: &Game, limits: &SearchLimits,
+    ) -> Result<(Move, Option<SearchScore>), Box<dyn Error>>
+    {
+        self.player.go(game, limits)
+    }
+}
+
+impl Player for BcldPlayer
+{
+    fn name(&self) -> &str { &self.name }
+
+    fn new_game(&mut self) -> Result<(), Box<dyn Error>>
+    {
+        self.engine.searcher().clear();
+        Ok(())
+    }
+
+    fn go(
+        &mut self, game: &Game, limits: &SearchLimits,
+    ) -> Result<(Move, Option<SearchScore>), Box<dyn Error>>
+    {
+        let result = self.engine.searcher().search(&game.board(), limits);
+        let mv = result.best_move.ok_or("search returned no move!")?;
+        Ok((mv, Some(result.score)))
+    }
+}
+
+impl Player for UciClient
+{
+    fn name(&self) -> &str { UciClient::name(self) }
+
+    fn new_game(&mut self) -> Result<(), Box<dyn Error>>
+    {
+        UciClient::new_game(self)
+    }
+
+    fn go(
+        &mut self, game: &Game, limits: &SearchLimits,
+    ) -> Result<(Move, Option<SearchScore>), Box<dyn Error>>
+    {
+        let search = self.search(game, limits)?;
+        let mv = search
+            .best_move
+            .ok_or_else(|| format!("{} has no move to play!", self.name()))?;
+        Ok((mv, search.score()))
+    }
+}
+
+impl FromStr for TimeControl
+{
+    type Err = Box<dyn Error>;
+
+    /// Parses the seconds each side starts with and the increment, as in
+    /// `10+0.1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let (base, increment) = s.split_once('+').unwrap_or((s, "0"));
+        let seconds = |value: &str| -> Result<Duration, Self::Err> {
+            match value.parse::<f64>() {
+                Ok(seconds) if seconds >= 0.0 =>
+                    Ok(Duration::from_secs_f64(seconds)),
+                _ => Err(format!("invalid time control {}!", s).into()),
+            }
+        };
+
+        Ok(TimeControl {
+            base:      seconds(base)?,
+            increment: seconds(increment)?,
+        })
+    }
+}
+
+impl fmt::Display for TimeControl
+{
+    /// Formats the time control as PGN's `TimeControl` tag does.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(
+            f,
+            "{}+{}",
+            self.base.as_secs_f64(),
+            self.increment.as_secs_f64()
+        )
+    }
+}
+
+impl Default for Sprt
+{
+    fn default() -> Self
+    {
+        Sprt { elo0: 0.0, elo1: 5.0, alpha: 0.05, beta: 0.05 }
+    }
+}
+
+impl Sprt
+{
+    /// Returns the log-likelihood ratios at which the first and second
+    /// hypotheses are accepted.
+    pub fn bounds(&self) -> (f64, f64)
+    {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+}
+
+/// Returns the expected score for an Elo difference.
+fn expected_score(elo: f64) -> f64 { 1.0 / (1.0 + 10f64.powf(-elo / 400.0)) }
+
+/// Returns the Elo difference giving an expected score.
+fn elo_difference(score: f64) -> f64 { -400.0 * (1.0 / score - 1.0).log10() }
+
+impl Stats
+{
+    pub fn games(&self) -> u32 { self.wins + self.losses + self.draws }
+
+    /// Records a game's result for the first engine. A game that never got
+    /// going, because an engine failed to start it, isn't counted.
+    pub fn add(&mut self, result: GameResult, first_is_white: bool)
+    {
+        match (result, first_is_white) {
+            (GameResult::WhiteWins, true) | (GameResult::BlackWins, false) =>
+                self.wins += 1,
+            (GameResult::WhiteWins, false) | (GameResult::BlackWins, true) =>
+                self.losses += 1,
+            (GameResult::Draw, _) => self.draws += 1,
+            (GameResult::Unfinished, _) => (),
+        }
+    }
+
+    /// Returns the first engine's mean score and its variance per game.
+    fn score(&self) -> (f64, f64)
+    {
+        let n = self.games().max(1) as f64;
+        let score = (self.wins as f64 + self.draws as f64 / 2.0) / n;
+        let variance = (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2))
+            / n;
+        (score, variance)
+    }
+
+    /// Returns the estimated Elo difference in the first engine's favour,
+    /// along with the half width of its 95% confidence interval.
+    pub fn elo(&self) -> (f64, f64)
+    {
+        let (score, variance) = self.score();
+        let error = CONFIDENCE * (variance / self.games().max(1) as f64).sqrt();
+        let clamp = |score: f64| score.clamp(1e-6, 1.0 - 1e-6);
+
+        (
+            elo_difference(clamp(score)),
+            (elo_difference(clamp(score + error))
+                - elo_difference(clamp(score - error)))
+                / 2.0,
+        )
+    }
+
+    /// Returns the log-likelihood ratio of the second hypothesis against the
+    /// first, approximating the results as normally distributed.
+    pub fn llr(&self, sprt: &Sprt) -> f64
+    {
+        let (score, variance) = self.score();
+        if variance == 0.0 {
+            return 0.0;
+        }
+
+        let (score0, score1) =
+            (expected_score(sprt.elo0), expected_score(sprt.elo1));
+        self.games() as f64
+            * (score1 - score0)
+            * (2.0 * score - score0 - score1)
+            / (2.0 * variance)
+    }
+}
+
+impl fmt::Display for Stats
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        let (elo, error) = self.elo();
+        write!(
+            f,
+            "{} - {} - {} [{:.3}] {}, elo {:.1} +/- {:.1}",
+            self.wins,
+            self.losses,
+            self.draws,
+            self.score().0,
+            self.games(),
+            elo,
+            error
+        )
+    }
+}
+
+/// Reads openings from an EPD file, one position per line, or from the games
+/// in a PGN file.
+pub fn load_openings(path: &Path) -> Result<Vec<Game>, Box<dyn Error>>
+{
+    let text = fs::read_to_string(path)?;
+    let openings = match path.extension().and_then(|e| e.to_str()) {
+        Some("pgn") => parse_pgn(&text)?
+            .into_iter()
+            .map(|game| Game { result: GameResult::Unfinished, ..game })
+            .collect(),
+        _ => text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                // epd has no move counters, and may have operations after
+                let fields: Vec<&str> =
+                    line.split_whitespace().take(4).collect();
+                let fen = format!("{} 0 1", fields.join(" "));
+                Ok(Game::new(*BitBoardState::from_fen(&fen)?))
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?,
+    };
+
+    if openings.is_empty() {
+        return Err(format!("no openings in {}!", path.display()).into());
+    }
+    Ok(openings)
+}
+
+/// Returns a score in centipawns, counting mates as huge.
+fn centipawns(score: SearchScore) -> i32
+{
+    match score {
+        SearchScore::Centipawns(cp) => cp,
+        SearchScore::Mate(moves) if moves > 0 => MATE_CENTIPAWNS,
+        SearchScore::Mate(_) => -MATE_CENTIPAWNS,
+    }
+}
+
+/// Returns the result of a side winning.
+fn win_for(color: Color) -> GameResult
+{
+    match color {
+        Color::White => GameResult::WhiteWins,
+        Color::Black => GameResult::BlackWins,
+    }
+}
+
+/// Returns the result and how the game ended, if it has.
+fn game_over(
+    board: &BitBoardState, plies: usize, tablebase: Option<&Tablebase>,
+) -> Option<(GameResult, &'static str)>
+{
+    let turn = board.turn();
+    if board.legal_moves().is_empty() {
+        return Some(match board.in_check() {
+            true => (win_for(turn.opposite()), "checkmate"),
+            false => (GameResult::Draw, "stalemate"),
+        });
+    }
+    if board.is_draw_by_rule() {
+        return Some((GameResult::Draw, "draw by rule"));
+    }
+    if plies >= MAX_GAME_PLIES {
+        return Some((GameResult::Draw, "adjudication"));
+    }
+
+    match tablebase?.probe(board)? {
+        Dtm::Win(_) => Some((win_for(turn), "tablebase adjudication")),
+        Dtm::Loss(_) =>
+            Some((win_for(turn.opposite()), "tablebase adjudication")),
+        Dtm::Draw => Some((GameResult::Draw, "tablebase adjudication")),
+    }
+}
+
+/// Plays a game from an opening, returning it with its result and how it
+/// ended in the `Termination` tag.
+pub fn play_game<'a>(
+    white: &'a mut dyn Player, black: &'a mut dyn Player, opening: &Game,
+    time_control: TimeControl, adjudication: &Adjudication,
+) -> Game
+{
+    let mut game = Game::new(opening.start.clone());
+    game.moves = opening.moves.clone();
+    game.set_tag("White", white.name());
+    game.set_tag("Black", black.name());
+    game.set_tag("TimeControl", &time_control.to_string());
+
+    let mut board = game.board();
+    let mut clocks = [time_control.base; 2];
+    // moves in a row each side has scored as lost, and plies in a row both
+    // sides have scored as drawn
+    let mut resigning = [0; 2];
+    let mut drawing = 0;
+
+    let started = white.new_game().and_then(|_| black.new_game());
+    let (result, termination) = match started {
+        Err(e) => (GameResult::Unfinished, format!("engine error: {}", e)),
+        Ok(()) => loop {
+            let turn = board.turn();
+            let side = match turn {
+                Color::White => 0,
+                Color::Black => 1,
+            };
+            if let Some((result, termination)) = game_over(
+                &board,
+                game.moves.len(),
+                adjudication.tablebase.as_deref(),
+            ) {
+                break (result, termination.to_string());
+            }
+
+            let limits = SearchLimits {
+                wtime: Some(clocks[0]),
+                btime: Some(clocks[1]),
+                winc: Some(time_control.increment),
+                binc: Some(time_control.increment),
+                ..Default::default()
+            };
+            let player = match turn {
+                Color::White => &mut *white,
+                Color::Black => &mut *black,
+            };
+
+            let start = Instant::now();
+            let (mv, score) = match player.go(&game, &limits) {
+                Ok(reply) => reply,
+                Err(e) =>
+                    break (
+                        win_for(turn.opposite()),
+                        format!("engine error: {}", e),
+                    ),
+            };
+            match clocks[side].checked_sub(start.elapsed()) {
+                Some(left) => clocks[side] = left + time_control.increment,
+                None =>
+                    break (
+                        win_for(turn.opposite()),
+                        "time forfeit".to_string(),
+                    ),
+            }
+            if !board.legal_moves().contains(&mv) {
+                break (
+                    win_for(turn.opposite()),
+                    format!("illegal move {}", mv),
+                );
+            }
+            board.make_move(mv);
+            game.moves.push(mv);
+
+            let score = score.map(centipawns);
+            if let (Some((threshold, moves)), Some(score)) =
+                (adjudication.resign, score)
+            {
+                resigning[side] = match score <= -threshold {
+                    true => resigning[side] + 1,
+                    false => 0,
+                };
+                if resigning[side] >= moves {
+                    break (
+                        win_for(turn.opposite()),
+                        "adjudication".to_string(),
+                    );
+                }
+            }
+            if let Some((threshold, moves, after)) = adjudication.draw {
+                let late = board.move_number() as u32 > after;
+                drawing = match score {
+                    Some(score) if late && score.abs() <= threshold =>
+                        drawing + 1,
+                    _ => 0,
+                };
+                if drawing >= 2 * moves {
+                    break (GameResult::Draw, "adjudication".to_string());
+                }
+            }
+        },
+    };
+
+    game.result = result;
+    game.set_tag("Result", &result.to_string());
+    game.set_tag("Termination", &termination);
+    game
+}
+
+impl MatchConfig
+{
+    /// Parses the arguments to `bcld match`.
+    pub fn parse(args: &[String]) -> Result<Self, Box<dyn Error>>
+    {
+        let mut engines = Vec::new();
+        let mut config = MatchConfig {
+            engines:      Default::default(),
+            time_control: TimeControl::from_str("10+0.1")?,
+            openings:     vec![Game::default()],
+            rounds:       50,
+            concurrency:  1,
+            adjudication: Adjudication::default(),
+            sprt:         None,
+            pgn_out:      None,
+        };
+
+        // each flag takes the arguments up to the next one
+        let mut args = args.iter().map(String::as_str).peekable();
+        while let Some(flag) = args.next() {
+            let mut values = Vec::new();
+            while let Some(value) = args.next_if(|arg| !arg.starts_with("--")) {
+                values.push(value);
+            }
+            let number = |i: usize| -> Result<f64, Box<dyn Error>> {
+                let value = values
+                    .get(i)
+                    .ok_or_else(|| format!("{} needs more values!", flag))?;
+                Ok(value.parse::<f64>()?)
+            };
+
+            match flag {
+                "--engine" => engines.push(EngineConfig::parse(&values)?),
+                "--tc" => config.time_control = values.join("").parse()?,
+                "--openings" =>
+                    config.openings =
+                        load_openings(Path::new(&values.join(" ")))?,
+                "--rounds" => config.rounds = number(0)? as usize,
+                "--concurrency" =>
+                    config.concurrency = number(0)?.max(1.0) as usize,
+                "--pgnout" =>
+                    config.pgn_out = Some(PathBuf::from(values.join(" "))),
+                "--sprt" =>
+                    config.sprt = Some(Sprt {
+                        elo0:  number(0)?,
+                        elo1:  number(1)?,
+                        alpha: number(2).unwrap_or(0.05),
+                        beta:  number(3).unwrap_or(0.05),
+                    }),
+                "--resign" =>
+                    config.adjudication.resign =
+                        Some((number(0)? as i32, number(1)? as u32)),
+                "--draw" =>
+                    config.adjudication.draw = Some((
+                        number(0)? as i32,
+                        number(1)? as u32,
+                        number(2).unwrap_or(0.0) as u32,
+                    )),
+                "--tb" => {
+                    let mut tablebase = Tablebase::new();
+                    tablebase.load(Path::new(&values.join(" ")))?;
+                    config.adjudication.tablebase = Some(Arc::new(tablebase));
+                },
+                _ => return Err(format!("unknown flag {}!", flag).into()),
+            }
+        }
+
+        config.engines = match <[EngineConfig; 2]>::try_from(engines) {
+            Ok(engines) => engines,
+            Err(_) =>
+                return Err("a match needs exactly two --engine flags!".into()),
+        };
+        Ok(config)
+    }
+
+    /// Plays the match on several threads, handing each finished game and
+    /// the results so far to a callback, and writing the games to the PGN
+    /// file. Stops early once the SPRT, if any, accepts either hypothesis.
+    pub fn run(
+        &self, on_game: &mut (dyn FnMut(&Game, &Stats) + Send),
+    ) -> Result<Stats, Box<dyn Error>>
+    {
+        let pgn = match &self.pgn_out {
+            Some(path) => Some(File::create(path)?),
+            None => None,
+        };
+        let state = Mutex::new((Stats::default(), pgn, on_game));
+        let next = AtomicUsize::new(0);
+        let finished = AtomicBool::new(false);
+        let games = self.rounds * 2;
+
+        let errors: Vec<String> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.concurrency.min(games))
+                .map(|_| {
+                    scope.spawn(|| -> Result<(), String> {
+                        let start = |config: &EngineConfig| {
+                            config.start().map_err(|e| e.to_string())
+                        };
+                        let mut players = [
+                            start(&self.engines[0])?,
+                            start(&self.engines[1])?,
+                        ];
+
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            if index >= games
+                                || finished.load(Ordering::Relaxed)
+                            {
+                                return Ok(());
+                            }
+
+                            let opening =
+                                &self.openings[index / 2 % self.openings.len()];
+                            let [first, second] = &mut players;
+                            let first_is_white = index.is_multiple_of(2);
+                            let (white, black) = match first_is_white {
+                                true => (first, second),
+                                false => (second, first),
+                            };
+                            let mut game = play_game(
+                                white.as_mut(),
+                                black.as_mut(),
+                                opening,
+                                self.time_control,
+                                &self.adjudication,
+                            );
+                            // the tags every game needs come first
+                            let round = (index / 2 + 1).to_string();
+                            let roster = [
+                                ("Event", "bcld match"),
+                                ("Site", "?"),
+                                ("Round", round.as_str()),
+                            ];
+                            let mut tags: Vec<(String, String)> = roster
+                                .iter()
+                                .map(|(tag, value)| {
+                                    (tag.to_string(), value.to_string())
+                                })
+                                .collect();
+                            tags.append(&mut game.tags);
+                            game.tags = tags;
+
+                            let mut state = state.lock().unwrap();
+                            let (stats, pgn, on_game) = &mut *state;
+                            stats.add(game.result, first_is_white);
+                            if let Some(pgn) = pgn {
+                                writeln!(pgn, "{}\n", game)
+                                    .map_err(|e| e.to_string())?;
+                            }
+                            on_game(&game, stats);
+
+                            if let Some(sprt) = &self.sprt {
+                                let (lower, upper) = sprt.bounds();
+                                let llr = stats.llr(sprt);
+                                if llr <= lower || llr >= upper {
+                                    finished.store(true, Ordering::Relaxed);
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().unwrap().err())
+                .collect()
+        });
+
+        if let Some(error) = errors.first() {
+            return Err(error.clone().into());
+        }
+        Ok(state.into_inner().unwrap().0)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::test_util::TempPath;
+
+    #[test]
+    fn test_stats()
+    {
+        let stats = Stats { wins: 30, losses: 20, draws: 50 };
+        let (elo, error) = stats.elo();
+        assert!((elo - 34.9).abs() < 0.1);
+        assert!(error > 30.0 && error < 60.0);
+
+        let even = Stats { wins: 10, losses: 10, draws: 10 };
+        assert!(even.elo().0.abs() < 1e-9);
+
+        // a game an engine never started is no draw
+        let mut counted = Stats::default();
+        counted.add(GameResult::WhiteWins, false);
+        counted.add(GameResult::Draw, true);
+        counted.add(GameResult::Unfinished, true);
+        assert_eq!(counted, Stats { wins: 0, losses: 1, draws: 1 });
+
+        let sprt = Sprt::default();
+        let (lower, upper) = sprt.bounds();
+        assert!((lower + 2.944).abs() < 0.001 && (upper - 2.944).abs() < 0.001);
+        assert!(stats.llr(&sprt) > 0.0);
+        assert!(Stats { wins: 20, losses: 30, draws: 50 }.llr(&sprt) < 0.0);
+        assert_eq!(Stats::default().llr(&sprt), 0.0);
+    }
+
+    #[test]
+    fn test_game_over()
+    {
+        // a position seen twice may still be left, but not one seen thrice
+        let mut board = BitBoardState::start_of_game();
+        for round in 0..2 {
+            for mv in ["g1f3", "g8f6", "f3g1", "f6g8"] {
+                assert_eq!(game_over(&board, 0, None), None);
+                board.make_move(mv.parse().unwrap());
+            }
+            let over = game_over(&board, 0, None);
+            assert_eq!(over.is_some(), round == 1);
+        }
+        assert_eq!(
+            game_over(&board, 0, None),
+            Some((GameResult::Draw, "draw by rule"))
+        );
+    }
+
+    #[test]
+    fn test_parse()
+    {
+        let tc: TimeControl = "10+0.1".parse().unwrap();
+        assert_eq!(tc.base, Duration::from_secs(10));
+        assert_eq!(tc.increment, Duration::from_millis(100));
+        assert_eq!(tc.to_string(), "10+0.1");
+        assert!("fast".parse::<TimeControl>().is_err());
+
+        let args: Vec<String> = "--engine name=new Hash=16 --engine \
+                                 cmd=/usr/bin/stockfish Threads=2 --tc 5+0.05 \
+                                 --rounds 10 --sprt 0 5 --draw 10 8 40"
+            .split(' ')
+            .map(String::from)
+            .collect();
+        let config = MatchConfig::parse(&args).unwrap();
+        assert_eq!(config.engines[0].name.as_deref(), Some("new"));
+        assert_eq!(config.engines[0].options, vec![(
+            "Hash".into(),
+            "16".into()
+        )]);
+        assert_eq!(
+            config.engines[1].command.as_deref(),
+            Some("/usr/bin/stockfish")
+        );
+        assert_eq!(config.rounds, 10);
+        assert_eq!(config.sprt.unwrap().elo1, 5.0);
+        assert_eq!(config.adjudication.draw, Some((10, 8, 40)));
+
+        assert!(MatchConfig::parse(&args[..3]).is_err());
+    }
+
+    #[test]
+    fn test_openings()
+    {
+        let epd = TempPath::new("test_openings.epd");
+        fs::write(&epd, "4k3/8/8/8/8/8/4P3/4K3 w - - id \"pawn\";\n\n")
+            .unwrap();
+        let openings = load_openings(&epd).unwrap();
+        assert_eq!(openings.len(), 1);
+        assert!(openings[0].moves.is_empty());
+
+        let pgn = TempPath::new("test_openings.pgn");
+        fs::write(&pgn, "1. e4 e5 2. Nf3 *\n\n1. d4 d5 1-0\n").unwrap();
+        let openings = load_openings(&pgn).unwrap();
+        assert_eq!(openings.len(), 2);
+        assert_eq!(openings[0].moves.len(), 3);
+        assert_eq!(openings[1].result, GameResult::Unfinished);
+    }
+
+    #[test]
+    fn test_match()
+    {
+        let pgn = TempPath::new("test_match.pgn");
+        let args: Vec<String> = [
+            "--engine",
+            "name=a",
+            "--engine",
+            "name=b",
+            "Contempt=20",
+            "--tc",
+            "2+0.05",
+            "--rounds",
+            "1",
+            "--concurrency",
+            "2",
+            "--resign",
+            "400",
+            "2",
+            "--draw",
+            "1000",
+            "2",
+            "0",
+            "--pgnout",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .chain([pgn.to_str().unwrap().to_string()])
+        .collect();
+        let config = MatchConfig::parse(&args).unwrap();
+
+        let mut played = 0;
+        let stats = config.run(&mut |game, _| {
+            assert_eq!(game.tag("Termination"), Some("adjudication"));
+            played += 1;
+        });
+        assert_eq!(stats.unwrap().draws, 2);
+        assert_eq!(played, 2);
+
+        let games = parse_pgn(&fs::read_to_string(&pgn).unwrap()).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[0].moves.len(), 4);
+        let names = [games[0].tag("White"), games[1].tag("White")];
+        assert!(names.contains(&Some("a")) && names.contains(&Some("b")));
+    }
+
+    #[test]
+    fn test_short_time_control()
+    {
+        // even with a tenth of a second for the game, there's always a move
+        let args: Vec<String> = [
+            "--engine",
+            "name=a",
+            "--engine",
+            "name=b",
+            "--tc",
+            "0.1+0",
+            "--rounds",
+            "2",
+            "--concurrency",
+            "2",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+        let config = MatchConfig::parse(&args).unwrap();
+
+        let mut played = 0;
+        config
+            .run(&mut |game, _| {
+                let termination = game.tag("Termination").unwrap();
+                assert!(!termination.starts_with("engine error"));
+                played += 1;
+            })
+            .unwrap();
+        assert_eq!(played, 4);
+    }
+}
@@ -0,0 +1,391 @@
+//! Refer to https://www.chessprogramming.org/Transposition_Table
+
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::moves::Move;
+use crate::piece::PieceType;
+use crate::search::{MATE, MAX_PLY};
+
+/// The table size used unless another is set, in megabytes.
+pub const DEFAULT_SIZE_MB: usize = 16;
+
+const SLOTS_PER_BUCKET: usize = 4;
+const GENERATION_MASK: u8 = 0x3f;
+const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+
+/// How a stored score relates to the true score of the position.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Bound
+{
+    Exact,
+    /// The true score is at least the stored one (the search failed high).
+    Lower,
+    /// The true score is at most the stored one (the search failed low).
+    Upper,
+}
+
+/// What the table knows about a position.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TtEntry
+{
+    pub best_move:  Option<Move>,
+    /// The score, with mates counted from the ply the table was probed at.
+    pub score:      i32,
+    pub depth:      i32,
+    pub bound:      Bound,
+    pub generation: u8,
+}
+
+/// A single entry. The key is stored xored with the data, so that an entry
+/// torn by two threads writing at once fails to match rather than returning
+/// another position's data.
+#[derive(Default)]
+struct Slot
+{
+    key:  AtomicU64,
+    data: AtomicU64,
+}
+
+/// A cache line of entries sharing an index.
+#[derive(Default)]
+#[repr(align(64))]
+struct Bucket
+{
+    slots: [Slot; SLOTS_PER_BUCKET],
+}
+
+/// A hash table of search results, keyed by position hash. It can be shared
+/// between threads without locking.
+pub struct TranspositionTable
+{
+    buckets:    Vec<Bucket>,
+    generation: AtomicU8,
+}
+
+impl Default for TranspositionTable
+{
+    fn default() -> Self { TranspositionTable::new(DEFAULT_SIZE_MB) }
+}
+
+impl TranspositionTable
+{
+    /// Creates an empty table taking up about the given number of megabytes.
+    pub fn new(size_mb: usize) -> Self
+    {
+        let count =
+            (size_mb * 1024 * 1024 / std::mem::size_of::<Bucket>()).max(1);
+
+        TranspositionTable {
+            buckets:    (0..count).map(|_| Bucket::default()).collect(),
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    /// Returns the size of the table in megabytes, rounded down.
+    pub fn size_mb(&self) -> usize
+    {
+        self.buckets.len() * std::mem::size_of::<Bucket>() / (1024 * 1024)
+    }
+
+    /// Replaces the table with an empty one of the given size.
+    pub fn resize(&mut self, size_mb: usize)
+    {
+        *self = TranspositionTable::new(size_mb);
+    }
+
+    /// Forgets every stored position.
+    pub fn clear(&self)
+    {
+        for slot in self.buckets.iter().flat_map(|b| b.slots.iter()) {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    /// Marks the start of a new search, so that entries from older ones are
+    /// replaced first.
+    pub fn new_search(&self)
+    {
+        let next =
+            (self.generation.load(Ordering::Relaxed) + 1) & GENERATION_MASK;
+        self.generation.store(next, Ordering::Relaxed);
+    }
+
+    fn generation(&self) -> u8 { self.generation.load(Ordering::Relaxed) }
+
+    fn bucket(&self, hash: u64) -> &Bucket
+    {
+        // maps the hash onto the table without a division
+        let index = (hash as u128 * self.buckets.len() as u128) >> 64;
+        &self.buckets[index as usize]
+    }
+
+    /// Looks up a position, given the ply it was reached at.
+    pub fn probe(&self, hash: u64, ply: usize) -> Option<TtEntry>
+    {
+        self.bucket(hash).slots.iter().find_map(|slot| {
+            let data = slot.data.load(Ordering::Relaxed);
+            let key = slot.key.load(Ordering::Relaxed);
+
+            match data != 0 && key ^ data == hash {
+                true => Some(unpack(data, ply)),
+                false => None,
+            }
+        })
+    }
+
+    /// Stores the result of searching a position, given the ply it was reached
+    /// at.
+    ///
+    /// An entry for the same position is always overwritten, though its move is
+    /// kept if the new result has none. Otherwise the entry replaced is the
+    /// shallowest, with entries from older searches counting as shallower.
+    pub fn store(
+        &self, hash: u64, ply: usize, depth: i32, bound: Bound, score: i32,
+        best_move: Option<Move>,
+    )
+    {
+        let generation = self.generation();
+        let slots = &self.bucket(hash).slots;
+        let entries = slots.iter().map(|slot| {
+            let data = slot.data.load(Ordering::Relaxed);
+            let key = slot.key.load(Ordering::Relaxed);
+            (slot, data, key ^ data == hash && data != 0)
+        });
+
+        let (slot, old, same) = entries
+            .min_by_key(|(_, data, same)| match (same, *data) {
+                (true, _) => i32::MIN,
+                (false, 0) => i32::MIN + 1,
+                (false, data) => {
+                    let entry = unpack(data, 0);
+                    let age = generation.wrapping_sub(entry.generation)
+                        & GENERATION_MASK;
+                    entry.depth - 8 * age as i32
+                },
+            })
+            .unwrap();
+
+        let best_move = match (best_move, same) {
+            (None, true) => unpack(old, 0).best_move,
+            _ => best_move,
+        };
+
+        let entry = TtEntry {
+            best_move,
+            score: score_to_tt(score, ply),
+            depth,
+            bound,
+            generation,
+        };
+        let data = pack(&entry);
+        slot.data.store(data, Ordering::Relaxed);
+        slot.key.store(hash ^ data, Ordering::Relaxed);
+    }
+
+    /// Estimates how full the table is in permille, by counting the entries
+    /// written during the current search in the first thousand.
+    pub fn hashfull(&self) -> u32
+    {
+        let generation = self.generation();
+        let sampled =
+            self.buckets.iter().flat_map(|b| b.slots.iter()).take(1000);
+        let mut total = 0;
+        let mut used = 0;
+
+        for slot in sampled {
+            let data = slot.data.load(Ordering::Relaxed);
+            total += 1;
+            if data != 0 && unpack(data, 0).generation == generation {
+                used += 1;
+            }
+        }
+
+        used * 1000 / total
+    }
+}
+
+/// Mate scores are stored relative to the stored position rather than the
+/// root, since the position may be reached again at another ply.
+fn score_to_tt(score: i32, ply: usize) -> i32
+{
+    match score {
+        s if s >= MATE_BOUND => s + ply as i32,
+        s if s <= -MATE_BOUND => s - ply as i32,
+        s => s,
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32
+{
+    match score {
+        s if s >= MATE_BOUND => s - ply as i32,
+        s if s <= -MATE_BOUND => s + ply as i32,
+        s => s,
+    }
+}
+
+/// Packs a move into 15 bits, with zero for no move.
+fn pack_move(mv: Option<Move>) -> u64
+{
+    let mv = match mv {
+        Some(mv) => mv,
+        None => return 0,
+    };
+    let promotion = match mv.promotion {
+        Some(piece) => piece as u64,
+        None => 0,
+    };
+
+    mv.from as u64 | (mv.to as u64) << 6 | promotion << 12
+}
+
+fn unpack_move(bits: u64) -> Option<Move>
+{
+    if bits == 0 {
+        return None;
+    }
+
+    let promotion = match bits >> 12 & 7 {
+        1 => Some(PieceType::Knight),
+        2 => Some(PieceType::Bishop),
+        3 => Some(PieceType::Rook),
+        4 => Some(PieceType::Queen),
+        _ => None,
+    };
+
+    Some(Move {
+        from: (bits & 63) as u8,
+        to: (bits >> 6 & 63) as u8,
+        promotion,
+    })
+}
+
+/// Lays an entry out as the move (16 bits), score (16), depth (8), bound (2)
+/// and generation (6). The bound is never zero, so neither is the data.
+fn pack(entry: &TtEntry) -> u64
+{
+    let bound = match entry.bound {
+        Bound::Exact => 1,
+        Bound::Lower => 2,
+        Bound::Upper => 3,
+    };
+
+    pack_move(entry.best_move)
+        | (entry.score as i16 as u16 as u64) << 16
+        | (entry.depth.clamp(0, 255) as u64) << 32
+        | bound << 40
+        | ((entry.generation & GENERATION_MASK) as u64) << 42
+}
+
+fn unpack(data: u64, ply: usize) -> TtEntry
+{
+    let bound = match data >> 40 & 3 {
+        1 => Bound::Exact,
+        2 => Bound::Lower,
+        _ => Bound::Upper,
+    };
+
+    TtEntry {
+        best_move: unpack_move(data & 0xffff),
+        score: score_from_tt((data >> 16) as u16 as i16 as i32, ply),
+        depth: (data >> 32 & 0xff) as i32,
+        bound,
+        generation: (data >> 42) as u8 & GENERATION_MASK,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_store_and_probe()
+    {
+        let tt = TranspositionTable::new(1);
+        let mv = Move::from_str("e7e8n").unwrap();
+
+        assert_eq!(tt.probe(0x1234, 0), None);
+        tt.store(0x1234, 3, 7, Bound::Lower, -250, Some(mv));
+
+        let entry = tt.probe(0x1234, 5).unwrap();
+        assert_eq!(entry.best_move, Some(mv));
+        assert_eq!(entry.score, -250);
+        assert_eq!(entry.depth, 7);
+        assert_eq!(entry.bound, Bound::Lower);
+
+        // the move is kept when the new result doesn't have one
+        tt.store(0x1234, 3, 8, Bound::Upper, 10, None);
+        assert_eq!(tt.probe(0x1234, 0).unwrap().best_move, Some(mv));
+
+        tt.clear();
+        assert_eq!(tt.probe(0x1234, 0), None);
+    }
+
+    #[test]
+    fn test_mate_scores_are_ply_adjusted()
+    {
+        let tt = TranspositionTable::new(1);
+
+        // mate in 5 plies from the root, found 2 plies in
+        tt.store(1, 2, 3, Bound::Exact, MATE - 5, None);
+        assert_eq!(tt.probe(1, 2).unwrap().score, MATE - 5);
+        assert_eq!(tt.probe(1, 4).unwrap().score, MATE - 7);
+
+        tt.store(2, 2, 3, Bound::Exact, -MATE + 4, None);
+        assert_eq!(tt.probe(2, 0).unwrap().score, -MATE + 2);
+    }
+
+    #[test]
+    fn test_replacement_prefers_old_and_shallow_entries()
+    {
+        let tt = TranspositionTable::new(1);
+        let buckets = tt.buckets.len() as u64;
+
+        // hashes that all map onto the first bucket
+        let hashes: Vec<u64> = (1..=5).collect();
+        assert!(hashes
+            .iter()
+            .all(|h| (*h as u128 * buckets as u128) >> 64 == 0));
+
+        for (depth, hash) in [10, 2, 12, 9].iter().zip(&hashes) {
+            tt.store(*hash, 0, *depth, Bound::Exact, 0, None);
+        }
+        tt.store(5, 0, 1, Bound::Exact, 0, None);
+        assert_eq!(tt.probe(2, 0), None);
+        assert!(tt.probe(5, 0).is_some());
+
+        // entries from earlier searches go first, however deep
+        tt.new_search();
+        tt.store(2, 0, 1, Bound::Exact, 0, None);
+        tt.store(6, 0, 1, Bound::Exact, 0, None);
+        assert_eq!(tt.probe(5, 0), None);
+        assert!(tt.probe(1, 0).is_some() && tt.probe(3, 0).is_some());
+    }
+
+    #[test]
+    fn test_hashfull()
+    {
+        let tt = TranspositionTable::new(1);
+        assert_eq!(tt.hashfull(), 0);
+
+        for hash in 0..100_000u64 {
+            tt.store(
+                hash.wrapping_mul(0x9e3779b97f4a7c15),
+                0,
+                1,
+                Bound::Exact,
+                0,
+                None,
+            );
+        }
+        assert!(tt.hashfull() > 500);
+
+        tt.new_search();
+        assert_eq!(tt.hashfull(), 0);
+    }
+}
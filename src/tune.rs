@@ -0,0 +1,398 @@
+use std::error::Error;
+use std::thread;
+
+use crate::board::{BitBoardState, GameState};
+use crate::eval::{coefficients, game_phase, EvalParams, Score, MAX_PHASE};
+
+/// How far each weight moves per step at most, in centipawns.
+const LEARNING_RATE: f64 = 1.0;
+const BETA1: f64 = 0.9;
+const BETA2: f64 = 0.999;
+/// The range searched for the scaling constant.
+const MIN_K: f64 = 0.1;
+const MAX_K: f64 = 4.0;
+
+/// A quiet position from a finished game, reduced to how often each weight
+/// of the evaluation counts towards it.
+#[derive(Clone, Debug)]
+pub struct TuningPosition
+{
+    coefficients: Vec<(u16, f32)>,
+    /// How much of the middlegame weights apply, from 0 to 1.
+    phase:        f32,
+    /// The points white went on to score.
+    result:       f32,
+}
+
+/// Optimises the handcrafted evaluation weights so that the evaluation of
+/// each position predicts its result, minimising the mean squared error of
+/// `1 / (1 + 10^(-k * eval / 400))` by gradient descent.
+pub struct Tuner
+{
+    positions: Vec<TuningPosition>,
+    /// The middlegame and endgame value of each weight.
+    weights:   Vec<[f64; 2]>,
+    k:         f64,
+    threads:   usize,
+    /// The moving averages of the gradient and its square, for Adam.
+    momentum:  Vec<[f64; 2]>,
+    velocity:  Vec<[f64; 2]>,
+    steps:     i32,
+}
+
+impl TuningPosition
+{
+    pub fn new(board: &BitBoardState, result: f64) -> Self
+    {
+        TuningPosition {
+            coefficients: coefficients(board)
+                .into_iter()
+                .map(|(i, coefficient)| (i as u16, coefficient as f32))
+                .collect(),
+            phase:        game_phase(board) as f32 / MAX_PHASE as f32,
+            result:       result as f32,
+        }
+    }
+
+    /// Parses a line holding a FEN followed by the game's result, as `1-0`,
+    /// `0-1` or `1/2-1/2`, or as 1.0, 0.0 or 0.5. The result may be wrapped
+    /// in brackets or quotes, and the FEN's move counters may be left out.
+    pub fn parse(line: &str) -> Result<Self, Box<dyn Error>>
+    {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (result, fen) = fields
+            .split_last()
+            .ok_or_else(|| format!("no result in {}!", line))?;
+
+        let result = match result.trim_matches(|c| "[]\"();".contains(c)) {
+            "1-0" | "1.0" => 1.0,
+            "0-1" | "0.0" => 0.0,
+            "1/2-1/2" | "0.5" => 0.5,
+            _ => return Err(format!("no result in {}!", line).into()),
+        };
+
+        // epd lines give the result with a c9 opcode instead of counters
+        let mut fen = fen.to_vec();
+        if fen.len() != 6 {
+            fen.truncate(4);
+            fen.extend(["0", "1"]);
+        }
+
+        Ok(TuningPosition::new(
+            BitBoardState::from_fen(&fen.join(" "))?.as_ref(),
+            result,
+        ))
+    }
+
+    /// Returns the evaluation in centipawns from white's perspective, given
+    /// the weights.
+    fn eval(&self, weights: &[[f64; 2]]) -> f64
+    {
+        let (mg, eg) = self.coefficients.iter().fold(
+            (0.0, 0.0),
+            |(mg, eg), &(i, coefficient)| {
+                let [weight_mg, weight_eg] = weights[i as usize];
+                (
+                    mg + coefficient as f64 * weight_mg,
+                    eg + coefficient as f64 * weight_eg,
+                )
+            },
+        );
+
+        let phase = self.phase as f64;
+        mg * phase + eg * (1.0 - phase)
+    }
+}
+
+/// Reads the positions in a file's worth of lines, skipping blank ones, on
+/// several threads.
+pub fn parse_positions(
+    text: &str, threads: usize,
+) -> Result<Vec<TuningPosition>, Box<dyn Error>>
+{
+    let lines: Vec<&str> =
+        text.lines().filter(|line| !line.trim().is_empty()).collect();
+    let chunk = lines.len() / threads.max(1) + 1;
+
+    let chunks = thread::scope(|scope| {
+        let handles: Vec<_> = lines
+            .chunks(chunk)
+            .map(|lines| {
+                scope.spawn(move || {
+                    lines
+                        .iter()
+                        .map(|line| {
+                            TuningPosition::parse(line)
+                                .map_err(|e| e.to_string())
+                        })
+                        .collect::<Result<Vec<_>, String>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Result<Vec<_>, String>>()
+    })?;
+
+    Ok(chunks.concat())
+}
+
+/// Returns the expected score for an evaluation.
+fn sigmoid(k: f64, eval: f64) -> f64
+{
+    1.0 / (1.0 + 10f64.powf(-k * eval / 400.0))
+}
+
+impl Tuner
+{
+    pub fn new(
+        positions: Vec<TuningPosition>, params: &EvalParams, threads: usize,
+    ) -> Self
+    {
+        let weights: Vec<[f64; 2]> = params
+            .to_vec()
+            .iter()
+            .map(|score| [score.mg as f64, score.eg as f64])
+            .collect();
+
+        Tuner {
+            positions,
+            momentum: vec![[0.0; 2]; weights.len()],
+            velocity: vec![[0.0; 2]; weights.len()],
+            weights,
+            k: 1.0,
+            threads: threads.max(1),
+            steps: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize { self.positions.len() }
+
+    pub fn is_empty(&self) -> bool { self.positions.is_empty() }
+
+    pub fn k(&self) -> f64 { self.k }
+
+    /// Returns the tuned parameters, rounded to whole centipawns.
+    pub fn params(&self) -> EvalParams
+    {
+        let weights: Vec<Score> = self
+            .weights
+            .iter()
+            .map(|[mg, eg]| Score::new(mg.round() as i32, eg.round() as i32))
+            .collect();
+
+        EvalParams::from_slice(&weights).unwrap()
+    }
+
+    /// Runs a function over the positions split between the threads.
+    fn parallel<T, F>(&self, f: F) -> Vec<T>
+    where
+        T: Send,
+        F: Fn(&[TuningPosition]) -> T + Sync,
+    {
+        let chunk = self.positions.len() / self.threads + 1;
+        let f = &f;
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .positions
+                .chunks(chunk)
+                .map(|positions| scope.spawn(move || f(positions)))
+                .collect();
+
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        })
+    }
+
+    /// Returns the mean squared error of the predicted results for a scaling
+    /// constant.
+    pub fn error_with(&self, k: f64) -> f64
+    {
+        let weights = &self.weights;
+        let total: f64 = self
+            .parallel(|positions| {
+                positions
+                    .iter()
+                    .map(|position| {
+                        let predicted = sigmoid(k, position.eval(weights));
+                        (position.result as f64 - predicted).powi(2)
+                    })
+                    .sum::<f64>()
+            })
+            .iter()
+            .sum();
+
+        total / self.positions.len().max(1) as f64
+    }
+
+    pub fn error(&self) -> f64 { self.error_with(self.k) }
+
+    /// Finds the scaling constant that best fits the current weights by
+    /// golden section search, and keeps it for the rest of the tuning.
+    pub fn fit_k(&mut self) -> f64
+    {
+        let ratio = (5f64.sqrt() - 1.0) / 2.0;
+        let (mut low, mut high) = (MIN_K, MAX_K);
+
+        while high - low > 1e-4 {
+            let a = high - ratio * (high - low);
+            let b = low + ratio * (high - low);
+            match self.error_with(a) < self.error_with(b) {
+                true => high = b,
+                false => low = a,
+            }
+        }
+
+        self.k = (low + high) / 2.0;
+        self.k
+    }
+
+    /// Moves every weight one step against the gradient of the error, using
+    /// Adam, and returns the error before the step.
+    pub fn step(&mut self) -> f64
+    {
+        let (k, weights) = (self.k, &self.weights);
+        // the derivative of the sigmoid's exponent with respect to the eval
+        let slope = k * 10f64.ln() / 400.0;
+
+        let (error, gradient) = self
+            .parallel(|positions| {
+                let mut gradient = vec![[0.0; 2]; weights.len()];
+                let mut error = 0.0;
+
+                for position in positions {
+                    let predicted = sigmoid(k, position.eval(weights));
+                    let difference = predicted - position.result as f64;
+                    error += difference * difference;
+
+                    let scale =
+                        difference * predicted * (1.0 - predicted) * slope;
+                    let phase = position.phase as f64;
+                    for &(i, coefficient) in position.coefficients.iter() {
+                        let term = scale * coefficient as f64;
+                        gradient[i as usize][0] += term * phase;
+                        gradient[i as usize][1] += term * (1.0 - phase);
+                    }
+                }
+
+                (error, gradient)
+            })
+            .into_iter()
+            .fold(
+                (0.0, vec![[0.0; 2]; weights.len()]),
+                |(error, mut total), (chunk_error, gradient)| {
+                    for (sum, part) in total.iter_mut().zip(gradient) {
+                        sum[0] += part[0];
+                        sum[1] += part[1];
+                    }
+                    (error + chunk_error, total)
+                },
+            );
+
+        self.steps += 1;
+        let correction1 = 1.0 - BETA1.powi(self.steps);
+        let correction2 = 1.0 - BETA2.powi(self.steps);
+        let n = self.positions.len().max(1) as f64;
+
+        let values = self
+            .weights
+            .iter_mut()
+            .flatten()
+            .zip(self.momentum.iter_mut().flatten())
+            .zip(self.velocity.iter_mut().flatten())
+            .zip(gradient.iter().flatten());
+        for (((weight, m), v), g) in values {
+            let g = 2.0 * g / n;
+            *m = BETA1 * *m + (1.0 - BETA1) * g;
+            *v = BETA2 * *v + (1.0 - BETA2) * g * g;
+            *weight -= LEARNING_RATE * (*m / correction1)
+                / ((*v / correction2).sqrt() + 1e-8);
+        }
+
+        error / n
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::eval::{eval_trace, PARAM_COUNT};
+
+    #[test]
+    fn test_parse()
+    {
+        let lines = [
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 [1.0]",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1 1-0",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - c9 \"1-0\";",
+        ];
+        for line in lines.iter() {
+            let position = TuningPosition::parse(line).unwrap();
+            assert_eq!(position.result, 1.0);
+            assert_eq!(position.phase, 0.0);
+        }
+
+        let position = TuningPosition::parse(
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 1/2-1/2",
+        )
+        .unwrap();
+        assert_eq!(position.result, 0.5);
+        assert_eq!(position.phase, 1.0);
+
+        assert!(TuningPosition::parse("4k3/8/8/8/8/8/8/4K3 w - - 0 1").is_err());
+        assert!(TuningPosition::parse("8/8/8/8/8/8/8/8 w - - 0 1 1-0").is_err());
+
+        let positions = parse_positions(&lines.join("\n\n"), 2).unwrap();
+        assert_eq!(positions.len(), 3);
+    }
+
+    #[test]
+    fn test_tune()
+    {
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - \
+             0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r1bq1rk1/pp2ppbp/2np1np1/8/3NP3/2N1BP2/PPPQ2PP/R3KB1R b KQ - 0 9",
+            "6k1/5ppp/8/8/8/8/1B3PPP/1B4K1 b - - 0 1",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+        ];
+        let positions: Vec<TuningPosition> = fens
+            .iter()
+            .zip([0.0, 0.5, 1.0, 1.0, 0.5])
+            .map(|(fen, result)| {
+                TuningPosition::new(
+                    &BitBoardState::from_fen(fen).unwrap(),
+                    result,
+                )
+            })
+            .collect();
+
+        let params = EvalParams::default();
+        let weights: Vec<[f64; 2]> = params
+            .to_vec()
+            .iter()
+            .map(|score| [score.mg as f64, score.eg as f64])
+            .collect();
+        assert_eq!(weights.len(), PARAM_COUNT);
+        for (fen, position) in fens.iter().zip(positions.iter()) {
+            let board = BitBoardState::from_fen(fen).unwrap();
+            let eval = eval_trace(&board).eval() as f64;
+            assert!((position.eval(&weights) - eval).abs() < 2.0);
+        }
+
+        let mut tuner = Tuner::new(positions, &params, 2);
+        let k = tuner.fit_k();
+        assert!((MIN_K..=MAX_K).contains(&k));
+
+        let before = tuner.error();
+        for _ in 0..100 {
+            tuner.step();
+        }
+        assert!(tuner.error() < before);
+        assert_ne!(tuner.params(), params);
+    }
+}
@@ -0,0 +1,509 @@
+//! Refer to https://www.shredderchess.com/chess-features/uci-universal-chess-interface.html
+
+use std::error::Error;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::board::{BitBoardState, GameState};
+use crate::engine::{Engine, SearchControl, SearchListener};
+use crate::moves::Move;
+use crate::options::{OptionKind, Options};
+use crate::search::{SearchInfo, SearchLimits, SearchResult};
+use crate::wdl::{material, Wdl, WdlModel};
+
+/// Speaks UCI, reading commands a line at a time and writing responses to
+/// the given output. Searches run on a background thread, so that `stop` and
+/// `isready` are answered while they do.
+pub struct Uci<W: Write + Send + 'static>
+{
+    board:     BitBoardState,
+    engine:    Engine,
+    out:       Arc<Mutex<W>>,
+    options:   Options,
+    /// Whether a search has yet to report its move.
+    searching: Arc<AtomicBool>,
+}
+
+/// Reports a search's progress as `info` lines, and its result as
+/// `bestmove`.
+struct Reporter<W: Write + Send + 'static>
+{
+    out:       Arc<Mutex<W>>,
+    control:   SearchControl,
+    /// Cleared just before the move is reported, so that the next `go` is
+    /// accepted as soon as the GUI has seen it.
+    searching: Arc<AtomicBool>,
+    infinite:  bool,
+    ponder:    bool,
+    /// The model and root material to report win, draw and loss chances
+    /// with, if asked to.
+    wdl:       Option<(WdlModel, u32)>,
+}
+
+impl<W: Write + Send + 'static> Uci<W>
+{
+    pub fn new(out: Arc<Mutex<W>>) -> Self
+    {
+        Uci {
+            board: BitBoardState::start_of_game(),
+            engine: Engine::default(),
+            out,
+            options: uci_options(),
+            searching: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Handles commands until `quit` or the end of the input.
+    pub fn run(&mut self, input: impl BufRead) -> Result<(), Box<dyn Error>>
+    {
+        for line in input.lines() {
+            if !self.handle(&line?) {
+                return Ok(());
+            }
+        }
+
+        self.handle("quit");
+        Ok(())
+    }
+
+    /// Handles a single command, returning false once it's time to quit.
+    pub fn handle(&mut self, line: &str) -> bool
+    {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match tokens.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return true,
+        };
+
+        let result = match command {
+            "uci" => {
+                self.identify();
+                Ok(())
+            },
+            "isready" => {
+                self.send("readyok");
+                Ok(())
+            },
+            "ucinewgame" => {
+                self.stop();
+                self.engine.searcher().clear();
+                Ok(())
+            },
+            "position" => self.set_position(args),
+            "go" => self.go(args),
+            "stop" => {
+                self.engine.stop();
+                Ok(())
+            },
+            "ponderhit" => {
+                self.engine.ponderhit();
+                Ok(())
+            },
+            "setoption" => self.set_option(args),
+            "quit" => {
+                self.engine.stop();
+                self.wait();
+                return false;
+            },
+            _ => Err(format!("unknown command {}!", command).into()),
+        };
+
+        if let Err(e) = result {
+            self.send(&format!("info string error: {}", e));
+        }
+        true
+    }
+
+    fn send(&self, line: &str) { send(&self.out, line); }
+
+    /// Waits for any running search to finish.
+    pub fn wait(&mut self) { self.engine.wait(); }
+
+    /// Stops any running search and waits for it, so that a command never
+    /// blocks the input behind a search that won't end by itself.
+    fn stop(&mut self)
+    {
+        if self.engine.is_searching() {
+            self.engine.stop();
+            self.wait();
+        }
+    }
+
+    fn identify(&mut self)
+    {
+        self.send(&format!("id name bcld {}", env!("CARGO_PKG_VERSION")));
+        self.send("id author nebulaeandstars");
+        for option in self.options.iter() {
+            self.send(&option.to_string());
+        }
+        self.send("uciok");
+    }
+
+    /// Handles `setoption name <name> [value <value>]`.
+    fn set_option(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>>
+    {
+        let value_at = args.iter().position(|arg| *arg == "value");
+        let name = args[..value_at.unwrap_or(args.len())]
+            .iter()
+            .skip_while(|arg| **arg == "name")
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        let value = value_at.map(|i| args[i + 1..].join(" "));
+
+        self.options.set(&mut self.engine, &name, value.as_deref())
+    }
+
+    /// Handles `position [startpos | fen <fen>] [moves <move>...]`.
+    fn set_position(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>>
+    {
+        let moves_at = args.iter().position(|arg| *arg == "moves");
+        let (setup, moves) = match moves_at {
+            Some(i) => (&args[..i], &args[i + 1..]),
+            None => (args, &[][..]),
+        };
+
+        let mut board = match setup.split_first() {
+            Some((&"startpos", _)) => BitBoardState::start_of_game(),
+            Some((&"fen", fen)) => *BitBoardState::from_fen(&fen.join(" "))?,
+            _ => return Err("expected startpos or fen!".into()),
+        };
+
+        for mv in moves {
+            let mv = Move::from_str(mv)?;
+            if !board.legal_moves().contains(&mv) {
+                return Err(format!("illegal move {}!", mv).into());
+            }
+            board.make_move(mv);
+        }
+
+        self.stop();
+        self.board = board;
+        Ok(())
+    }
+
+    /// Handles `go`, starting a search in the background.
+    fn go(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>>
+    {
+        let limits = parse_go(args)?;
+        if self.searching.load(Ordering::SeqCst) {
+            return Err("already searching!".into());
+        }
+        self.searching.store(true, Ordering::SeqCst);
+
+        let reporter = Reporter {
+            out:       self.out.clone(),
+            control:   self.engine.control(),
+            searching: self.searching.clone(),
+            infinite:  limits.infinite,
+            ponder:    limits.ponder,
+            wdl:       match self.options.check("UCI_ShowWDL") {
+                true => Some((WdlModel::default(), material(&self.board))),
+                false => None,
+            },
+        };
+        self.engine.start(&self.board, limits, reporter);
+        Ok(())
+    }
+}
+
+impl<W: Write + Send + 'static> SearchListener for Reporter<W>
+{
+    fn on_info(&mut self, info: &SearchInfo)
+    {
+        let wdl =
+            self.wdl.map(|(model, material)| model.wdl(info.score, material));
+        send(&self.out, &info_line(info, wdl));
+    }
+
+    fn on_finished(&mut self, result: &SearchResult)
+    {
+        // an infinite search only reports its move once stopped, and a
+        // pondering one once the opponent has moved
+        while (self.infinite || (self.ponder && !self.control.has_ponderhit()))
+            && !self.control.is_stopped()
+        {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        self.searching.store(false, Ordering::SeqCst);
+        match (result.best_move, result.ponder_move) {
+            (Some(mv), Some(reply)) =>
+                send(&self.out, &format!("bestmove {} ponder {}", mv, reply)),
+            (Some(mv), None) => send(&self.out, &format!("bestmove {}", mv)),
+            (None, _) => send(&self.out, "bestmove 0000"),
+        }
+    }
+}
+
+/// Returns the options UCI offers: every engine option, and those that only
+/// make sense here.
+fn uci_options() -> Options
+{
+    let mut options = Options::default();
+    // the GUI decides when to ponder, this only says that it may
+    options.add("Ponder", OptionKind::Check { default: false }, None);
+    options.add("UCI_ShowWDL", OptionKind::Check { default: false }, None);
+    options
+}
+
+/// Writes a line to the GUI straight away.
+fn send(out: &Mutex<impl Write>, line: &str)
+{
+    let mut out = out.lock().unwrap();
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+
+/// Parses the limits given to `go`.
+pub fn parse_go(args: &[&str]) -> Result<SearchLimits, Box<dyn Error>>
+{
+    let mut limits = SearchLimits::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || -> Result<u64, Box<dyn Error>> {
+            let value = args.next().ok_or(format!("no value for {}!", arg))?;
+            Ok(u64::from_str(value)?)
+        };
+        let millis = |ms: u64| Some(Duration::from_millis(ms));
+
+        match *arg {
+            "wtime" => limits.wtime = millis(value()?),
+            "btime" => limits.btime = millis(value()?),
+            "winc" => limits.winc = millis(value()?),
+            "binc" => limits.binc = millis(value()?),
+            "movestogo" => limits.movestogo = Some(value()? as u32),
+            "movetime" => limits.movetime = millis(value()?),
+            "depth" => limits.depth = Some(value()? as u32),
+            "nodes" => limits.nodes = Some(value()?),
+            "mate" => limits.mate = Some(value()? as u32),
+            "infinite" => limits.infinite = true,
+            "ponder" => limits.ponder = true,
+            _ => return Err(format!("unknown go argument {}!", arg).into()),
+        }
+    }
+
+    Ok(limits)
+}
+
+/// Formats progress as an `info` line, with the chances of winning, drawing
+/// and losing if given.
+fn info_line(info: &SearchInfo, wdl: Option<Wdl>) -> String
+{
+    let pv: Vec<String> = info.pv.iter().map(Move::to_string).collect();
+    let wdl = match wdl {
+        Some(wdl) => format!(" wdl {}", wdl),
+        None => String::new(),
+    };
+
+    format!(
+        "info depth {} seldepth {} multipv {} score {}{} nodes {} nps {} \
+         hashfull {} time {} pv {}",
+        info.depth,
+        info.seldepth,
+        info.multipv,
+        info.score,
+        wdl,
+        info.nodes,
+        info.nps(),
+        info.hashfull,
+        info.time.as_millis(),
+        pv.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn run(commands: &[&str]) -> Vec<String>
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let mut uci = Uci::new(out.clone());
+        for command in commands {
+            uci.handle(command);
+        }
+        uci.wait();
+
+        let out = out.lock().unwrap();
+        String::from_utf8(out.clone())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_handshake()
+    {
+        let out = run(&["uci", "isready"]);
+
+        assert!(out[0].starts_with("id name bcld"));
+        assert!(out
+            .iter()
+            .any(|l| l.starts_with("option name Hash type spin")));
+        assert_eq!(out[out.len() - 2], "uciok");
+        assert_eq!(out[out.len() - 1], "readyok");
+    }
+
+    #[test]
+    fn test_go()
+    {
+        let out = run(&[
+            "setoption name Threads value 2",
+            "position startpos moves e2e4 e7e5",
+            "go depth 3",
+            "isready",
+        ]);
+
+        assert!(out.iter().any(|l| l.starts_with("info depth 3 seldepth")));
+        // readyok may come before or after the move, as UCI allows
+        assert!(out.iter().any(|l| l == "readyok"));
+        assert!(out.iter().any(|l| l.starts_with("bestmove ")));
+
+        let out = run(&["setoption name UCI_ShowWDL value true", "go depth 1"]);
+        assert!(out[0].contains(" wdl "));
+
+        let out = run(&["setoption name MultiPV value 3", "go depth 3"]);
+        let depth_3: Vec<&String> =
+            out.iter().filter(|l| l.starts_with("info depth 3 ")).collect();
+        assert_eq!(depth_3.len(), 3);
+        assert!(depth_3[2].contains(" multipv 3 "));
+
+        let out = run(&[
+            "setoption name UCI_LimitStrength value true",
+            "setoption name UCI_Elo value 1000",
+            "go",
+        ]);
+        assert!(out.iter().all(|l| !l.starts_with("info depth 4 ")));
+        assert!(out.last().unwrap().starts_with("bestmove "));
+
+        let out =
+            run(&["position fen 7k/8/8/8/8/8/R7/1R4K1 w - - 0 1", "go mate 2"]);
+        assert!(out.iter().any(|l| l.contains("score mate 2")));
+
+        let out =
+            run(&["position fen k7/2Q5/1K6/8/8/8/8/8 b - - 0 1", "go depth 2"]);
+        assert_eq!(out.last().unwrap(), "bestmove 0000");
+    }
+
+    #[test]
+    fn test_go_twice()
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let bestmoves = || {
+            let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+            out.lines().filter(|l| l.starts_with("bestmove ")).count()
+        };
+
+        // a search that has reported its move doesn't hold up the next one
+        let mut uci = Uci::new(out.clone());
+        for searches in 1..=2 {
+            uci.handle("go depth 2");
+            for _ in 0..5000 {
+                if bestmoves() >= searches {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        uci.wait();
+
+        let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+        assert!(!out.contains("error"));
+        assert_eq!(bestmoves(), 2);
+    }
+
+    #[test]
+    fn test_stop_infinite_search()
+    {
+        let out = run(&["go infinite", "stop"]);
+        assert!(out.last().unwrap().starts_with("bestmove "));
+
+        // a new position ends the search rather than waiting for it
+        let out = run(&["go infinite", "position startpos", "isready"]);
+        assert!(out.iter().any(|l| l.starts_with("bestmove ")));
+        assert_eq!(out.last().unwrap(), "readyok");
+
+        let out =
+            run(&["go infinite", "setoption name Hash value 2", "isready"]);
+        assert!(out.iter().any(|l| l.starts_with("bestmove ")));
+        assert_eq!(out.last().unwrap(), "readyok");
+    }
+
+    #[test]
+    fn test_ponder()
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let last_line = || {
+            let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+            out.lines().last().unwrap_or_default().to_string()
+        };
+
+        // a finished search holds on to its move until told the opponent's
+        let mut uci = Uci::new(out.clone());
+        uci.handle("setoption name Ponder value true");
+        for command in ["stop", "ponderhit"] {
+            uci.handle("go ponder depth 3");
+            thread::sleep(Duration::from_millis(200));
+            assert!(!last_line().starts_with("bestmove"));
+
+            uci.handle(command);
+            uci.wait();
+            assert!(last_line().starts_with("bestmove "));
+            assert!(last_line().contains(" ponder "));
+        }
+    }
+
+    #[test]
+    fn test_errors()
+    {
+        let out = run(&[
+            "position startpos moves e2e5",
+            "position fen 8/8/8",
+            "go depth",
+            "setoption name Hash value 0",
+            "setoption name Threads value 0",
+            "setoption name UCI_Elo value 100",
+            "setoption name NullMove value false",
+            "setoption name Unknown value 3",
+            "bogus",
+        ]);
+
+        assert_eq!(out.len(), 8);
+        assert!(out.iter().all(|l| l.starts_with("info string error: ")));
+    }
+
+    #[test]
+    fn test_parse_go()
+    {
+        let limits = parse_go(&[
+            "wtime",
+            "1000",
+            "btime",
+            "2000",
+            "winc",
+            "10",
+            "binc",
+            "20",
+            "movestogo",
+            "5",
+        ])
+        .unwrap();
+
+        assert_eq!(limits.wtime, Some(Duration::from_millis(1000)));
+        assert_eq!(limits.binc, Some(Duration::from_millis(20)));
+        assert_eq!(limits.movestogo, Some(5));
+        assert!(!limits.infinite);
+
+        let ponder = parse_go(&["ponder", "movetime", "100"]).unwrap();
+        assert!(ponder.ponder && !ponder.infinite);
+        assert!(parse_go(&["depth", "x"]).is_err());
+    }
+}
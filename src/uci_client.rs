@@ -0,0 +1,668 @@
+//! Refer to https://www.shredderchess.com/chess-features/uci-universal-chess-interface.html
+
+use std::error::Error;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::board::{BitBoardState, GameState};
+use crate::moves::Move;
+use crate::pgn::Game;
+use crate::search::{SearchLimits, SearchScore};
+use crate::tt::Bound;
+use crate::wdl::Wdl;
+
+/// How long the engine has to answer anything but a search.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long past its limits a timed search may run before being stopped.
+const SEARCH_MARGIN: Duration = Duration::from_secs(1);
+
+/// A UCI engine running as a child process, driven over its stdin and
+/// stdout. An engine that crashes or stops answering is started again, with
+/// the same options, for the next command.
+pub struct UciClient
+{
+    program:        String,
+    args:           Vec<String>,
+    process:        Process,
+    name:           String,
+    author:         String,
+    /// The names of the options the engine offers.
+    options:        Vec<String>,
+    /// The options set so far, to set again after a restart.
+    settings:       Vec<(String, String)>,
+    timeout:        Duration,
+    /// How long a search without a time limit may take, if not forever.
+    search_timeout: Option<Duration>,
+    restarts:       u32,
+}
+
+/// A running engine process.
+struct Process
+{
+    child: Child,
+    stdin: ChildStdin,
+    /// The engine's output, a line at a time, read on another thread so
+    /// that waiting for it can be given up on.
+    lines: Receiver<String>,
+}
+
+/// Why no line came from the engine.
+enum ReadError
+{
+    TimedOut,
+    Exited,
+}
+
+/// What an engine reported in one `info` line. Anything it left out is
+/// `None`.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct UciInfo
+{
+    pub depth:    Option<u32>,
+    pub seldepth: Option<u32>,
+    pub multipv:  Option<u32>,
+    pub score:    Option<SearchScore>,
+    /// Whether the score is exact or only a bound.
+    pub bound:    Option<Bound>,
+    pub wdl:      Option<Wdl>,
+    pub nodes:    Option<u64>,
+    pub nps:      Option<u64>,
+    pub time:     Option<Duration>,
+    pub hashfull: Option<u32>,
+    pub tbhits:   Option<u64>,
+    pub pv:       Vec<Move>,
+    pub string:   Option<String>,
+}
+
+/// The result of a search: the move chosen, the reply expected, and every
+/// `info` line on the way.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct UciSearch
+{
+    /// The move to play, or `None` if there are no legal moves.
+    pub best_move: Option<Move>,
+    pub ponder:    Option<Move>,
+    pub infos:     Vec<UciInfo>,
+}
+
+impl Process
+{
+    fn spawn(program: &str, args: &[String]) -> Result<Self, Box<dyn Error>>
+    {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("could not start {}: {}!", program, e))?;
+
+        let stdout = child.stdout.take().unwrap();
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stdin = child.stdin.take().unwrap();
+        Ok(Process { child, stdin, lines })
+    }
+}
+
+impl Drop for Process
+{
+    fn drop(&mut self)
+    {
+        let _ = writeln!(self.stdin, "quit");
+        let _ = self.stdin.flush();
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl UciClient
+{
+    /// Starts an engine and waits for it to finish identifying itself.
+    pub fn spawn(program: &str, args: &[&str]) -> Result<Self, Box<dyn Error>>
+    {
+        let args: Vec<String> =
+            args.iter().map(|arg| arg.to_string()).collect();
+        let mut client = UciClient {
+            program: program.to_string(),
+            process: Process::spawn(program, &args)?,
+            args,
+            name: program.to_string(),
+            author: String::new(),
+            options: Vec::new(),
+            settings: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+            search_timeout: None,
+            restarts: 0,
+        };
+
+        client.handshake()?;
+        Ok(client)
+    }
+
+    /// Returns the name the engine gave for itself.
+    pub fn name(&self) -> &str { &self.name }
+
+    pub fn author(&self) -> &str { &self.author }
+
+    /// Returns the names of the options the engine offers.
+    pub fn options(&self) -> &[String] { &self.options }
+
+    /// Returns how many times the engine has been started again after
+    /// crashing or hanging.
+    pub fn restarts(&self) -> u32 { self.restarts }
+
+    /// Sets how long the engine has to answer anything but a search.
+    pub fn set_timeout(&mut self, timeout: Duration) { self.timeout = timeout; }
+
+    /// Sets how long a search without a time limit may take before it is
+    /// given up on, or `None` to wait for as long as it takes.
+    pub fn set_search_timeout(&mut self, timeout: Option<Duration>)
+    {
+        self.search_timeout = timeout;
+    }
+
+    /// Sends a command to the engine.
+    pub fn send(&mut self, command: &str) -> Result<(), Box<dyn Error>>
+    {
+        let stdin = &mut self.process.stdin;
+        writeln!(stdin, "{}", command)
+            .and_then(|_| stdin.flush())
+            .map_err(|_| format!("{} has exited!", self.name).into())
+    }
+
+    /// Waits for the next line from the engine until the deadline, if any.
+    fn read_line(&self, deadline: Option<Instant>)
+        -> Result<String, ReadError>
+    {
+        let lines = &self.process.lines;
+        let line = match deadline {
+            Some(deadline) => lines.recv_timeout(
+                deadline.saturating_duration_since(Instant::now()),
+            ),
+            None => lines.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        line.map_err(|e| match e {
+            RecvTimeoutError::Timeout => ReadError::TimedOut,
+            RecvTimeoutError::Disconnected => ReadError::Exited,
+        })
+    }
+
+    /// Sends `isready` and waits for `readyok`, within the usual timeout.
+    fn wait_ready(&mut self) -> Result<(), ReadError>
+    {
+        self.send("isready").map_err(|_| ReadError::Exited)?;
+        let deadline = Instant::now() + self.timeout;
+        while self.read_line(Some(deadline))?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    /// Starts the engine again after it crashed or hung, returning the error
+    /// to report for the command that found it out.
+    fn recover(&mut self, command: &str, error: ReadError) -> Box<dyn Error>
+    {
+        let error = match error {
+            ReadError::TimedOut =>
+                format!("{} did not answer {} in time!", self.name, command),
+            ReadError::Exited =>
+                format!("{} exited during {}!", self.name, command),
+        };
+
+        self.restarts += 1;
+        let restarted = Process::spawn(&self.program, &self.args)
+            .and_then(|process| {
+                self.process = process;
+                self.handshake()
+            })
+            .and_then(|_| {
+                for (name, value) in self.settings.clone() {
+                    self.send(&format!(
+                        "setoption name {} value {}",
+                        name, value
+                    ))?;
+                }
+                self.wait_ready()
+                    .map_err(|_| "it did not answer isready!".into())
+            });
+
+        match restarted {
+            Ok(()) => error.into(),
+            Err(e) =>
+                format!("{} and could not be restarted: {}", error, e).into(),
+        }
+    }
+
+    /// Sends `uci` and reads the engine's name, author and options.
+    fn handshake(&mut self) -> Result<(), Box<dyn Error>>
+    {
+        self.send("uci")?;
+        self.options.clear();
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let line = self.read_line(Some(deadline)).map_err(|e| match e {
+                ReadError::TimedOut =>
+                    format!("{} did not answer uci in time!", self.program),
+                ReadError::Exited =>
+                    format!("{} exited during uci!", self.program),
+            })?;
+            let line = line.trim();
+
+            if let Some(name) = line.strip_prefix("id name ") {
+                self.name = name.to_string();
+            }
+            else if let Some(author) = line.strip_prefix("id author ") {
+                self.author = author.to_string();
+            }
+            else if let Some(option) = line.strip_prefix("option name ") {
+                let name = option.split(" type ").next().unwrap_or(option);
+                self.options.push(name.trim().to_string());
+            }
+            else if line == "uciok" {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Waits until the engine has caught up with every command sent.
+    pub fn sync(&mut self) -> Result<(), Box<dyn Error>>
+    {
+        self.wait_ready().map_err(|e| self.recover("isready", e))
+    }
+
+    /// Sets one of the options the engine offers, keeping it across
+    /// restarts.
+    pub fn set_option(
+        &mut self, name: &str, value: &str,
+    ) -> Result<(), Box<dyn Error>>
+    {
+        let name = self
+            .options
+            .iter()
+            .find(|option| option.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("{} has no option {}!", self.name, name))?
+            .clone();
+
+        self.send(&format!("setoption name {} value {}", name, value))?;
+        self.settings.retain(|(set, _)| *set != name);
+        self.settings.push((name, value.to_string()));
+        Ok(())
+    }
+
+    /// Tells the engine the next search is from a new game.
+    pub fn new_game(&mut self) -> Result<(), Box<dyn Error>>
+    {
+        self.send("ucinewgame")?;
+        self.sync()
+    }
+
+    /// Searches a position, without the moves leading up to it.
+    pub fn search_board(
+        &mut self, board: &BitBoardState, limits: &SearchLimits,
+    ) -> Result<UciSearch, Box<dyn Error>>
+    {
+        self.search(&Game::new(board.clone()), limits)
+    }
+
+    /// Searches the position at the end of a game. A search running well
+    /// past its limits is stopped, and the engine restarted if it doesn't.
+    pub fn search(
+        &mut self, game: &Game, limits: &SearchLimits,
+    ) -> Result<UciSearch, Box<dyn Error>>
+    {
+        if self.send(&position_command(game)).is_err()
+            || self.send(&go_command(limits)).is_err()
+        {
+            return Err(self.recover("go", ReadError::Exited));
+        }
+
+        let mut deadline = search_time(game, limits)
+            .or(self.search_timeout)
+            .map(|time| Instant::now() + time);
+        let mut stopped = false;
+        let mut infos = Vec::new();
+
+        loop {
+            match self.read_line(deadline) {
+                Ok(line) =>
+                    if line.starts_with("info") {
+                        infos.extend(line.parse());
+                    }
+                    else if line.starts_with("bestmove") {
+                        let (best_move, ponder) = parse_bestmove(&line)?;
+                        return Ok(UciSearch { best_move, ponder, infos });
+                    },
+                // an engine that doesn't stop when asked has hung
+                Err(ReadError::TimedOut) if !stopped => {
+                    stopped = true;
+                    deadline = Some(Instant::now() + self.timeout);
+                    let _ = self.send("stop");
+                },
+                Err(e) => return Err(self.recover("go", e)),
+            }
+        }
+    }
+}
+
+/// Returns the longest a search may sensibly take, if it has a time limit.
+fn search_time(game: &Game, limits: &SearchLimits) -> Option<Duration>
+{
+    let clock = match game.board().turn() {
+        crate::piece::Color::White => limits.wtime,
+        crate::piece::Color::Black => limits.btime,
+    };
+    let time = match (limits.movetime, clock) {
+        _ if limits.infinite || limits.ponder => return None,
+        (Some(movetime), _) => movetime,
+        (None, Some(clock)) => clock,
+        (None, None) => return None,
+    };
+
+    Some(time + SEARCH_MARGIN)
+}
+
+/// Returns the `position` command setting up the end of a game.
+pub fn position_command(game: &Game) -> String
+{
+    let start = match game.start.as_fen() {
+        fen if fen == BitBoardState::start_of_game().as_fen() =>
+            "startpos".to_string(),
+        fen => format!("fen {}", fen),
+    };
+    let moves: Vec<String> = game.moves.iter().map(Move::to_string).collect();
+
+    match moves.is_empty() {
+        true => format!("position {}", start),
+        false => format!("position {} moves {}", start, moves.join(" ")),
+    }
+}
+
+/// Returns the `go` command for some limits.
+pub fn go_command(limits: &SearchLimits) -> String
+{
+    let mut command = "go".to_string();
+    let times = [
+        ("wtime", limits.wtime),
+        ("btime", limits.btime),
+        ("winc", limits.winc),
+        ("binc", limits.binc),
+        ("movetime", limits.movetime),
+    ];
+    for (name, time) in times {
+        if let Some(time) = time {
+            command += &format!(" {} {}", name, time.as_millis());
+        }
+    }
+    let counts = [
+        ("movestogo", limits.movestogo.map(u64::from)),
+        ("depth", limits.depth.map(u64::from)),
+        ("nodes", limits.nodes),
+        ("mate", limits.mate.map(u64::from)),
+    ];
+    for (name, count) in counts {
+        if let Some(count) = count {
+            command += &format!(" {} {}", name, count);
+        }
+    }
+    if limits.ponder {
+        command += " ponder";
+    }
+    if limits.infinite {
+        command += " infinite";
+    }
+
+    command
+}
+
+/// Parses `bestmove <move> [ponder <move>]`, where the move is `0000` or
+/// `(none)` if there are no legal moves.
+fn parse_bestmove(
+    line: &str,
+) -> Result<(Option<Move>, Option<Move>), Box<dyn Error>>
+{
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let best_move = match words.get(1) {
+        None | Some(&"0000") | Some(&"(none)") => None,
+        Some(mv) => Some(Move::from_str(mv)?),
+    };
+    let ponder = match words.get(2..4) {
+        Some(["ponder", mv]) => Move::from_str(mv).ok(),
+        _ => None,
+    };
+
+    Ok((best_move, ponder))
+}
+
+impl UciSearch
+{
+    /// Returns the last score reported for the best line.
+    pub fn score(&self) -> Option<SearchScore>
+    {
+        self.infos
+            .iter()
+            .rev()
+            .filter(|info| info.multipv.unwrap_or(1) == 1)
+            .find_map(|info| info.score)
+    }
+}
+
+impl FromStr for UciInfo
+{
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err>
+    {
+        let mut words = s.split_whitespace();
+        if words.next() != Some("info") {
+            return Err(format!("not an info line: {}!", s).into());
+        }
+
+        let mut info = UciInfo::default();
+        while let Some(word) = words.next() {
+            match word {
+                "depth" => info.depth = Some(number(&mut words, word)? as u32),
+                "seldepth" =>
+                    info.seldepth = Some(number(&mut words, word)? as u32),
+                "multipv" =>
+                    info.multipv = Some(number(&mut words, word)? as u32),
+                "nodes" => info.nodes = Some(number(&mut words, word)? as u64),
+                "nps" => info.nps = Some(number(&mut words, word)? as u64),
+                "hashfull" =>
+                    info.hashfull = Some(number(&mut words, word)? as u32),
+                "tbhits" =>
+                    info.tbhits = Some(number(&mut words, word)? as u64),
+                "time" =>
+                    info.time = Some(Duration::from_millis(number(
+                        &mut words, word,
+                    )?
+                        as u64)),
+                "score" => {
+                    let kind = words.next().unwrap_or_default();
+                    let value = number(&mut words, word)? as i32;
+                    info.score = match kind {
+                        "cp" => Some(SearchScore::Centipawns(value)),
+                        "mate" => Some(SearchScore::Mate(value)),
+                        _ =>
+                            return Err(
+                                format!("invalid score {}!", kind).into()
+                            ),
+                    };
+                    info.bound = Some(Bound::Exact);
+                },
+                "lowerbound" => info.bound = Some(Bound::Lower),
+                "upperbound" => info.bound = Some(Bound::Upper),
+                "wdl" =>
+                    info.wdl = Some(Wdl {
+                        win:  number(&mut words, word)? as u32,
+                        draw: number(&mut words, word)? as u32,
+                        loss: number(&mut words, word)? as u32,
+                    }),
+                "pv" => {
+                    // the moves run to the end of the line, or to a string
+                    for word in words.by_ref() {
+                        match Move::from_str(word) {
+                            Ok(mv) => info.pv.push(mv),
+                            Err(_) => break,
+                        }
+                    }
+                },
+                "string" => {
+                    info.string =
+                        Some(words.by_ref().collect::<Vec<_>>().join(" "));
+                },
+                "currmove" | "refutation" | "currline" => {
+                    words.next();
+                },
+                _ => (),
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+/// Parses the number following a keyword in an `info` line.
+fn number<'a>(
+    words: &mut impl Iterator<Item = &'a str>, word: &str,
+) -> Result<i64, Box<dyn Error>>
+{
+    let value =
+        words.next().ok_or_else(|| format!("no value for {}!", word))?;
+    Ok(value.parse::<i64>()?)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::test_util::TempPath;
+
+    /// Writes a shell script that speaks just enough UCI for the tests. It
+    /// exits on `go depth 2` and hangs on `go depth 3`, ignoring `stop`.
+    fn mock_engine(name: &str) -> TempPath
+    {
+        let path = TempPath::new(name);
+        fs::write(
+            &path,
+            r#"while read -r line; do
+    case "$line" in
+        uci)
+            echo "id name Mock Engine"
+            echo "id author Nobody"
+            echo "option name Hash type spin default 16 min 1 max 1024"
+            echo "option name Skill Level type spin default 20 min 0 max 20"
+            echo "uciok" ;;
+        isready) echo "readyok" ;;
+        "go depth 1")
+            echo "info string thinking hard"
+            echo "info depth 1 seldepth 3 multipv 1 score cp 17 lowerbound nodes 20 nps 1000 time 20 pv e2e4"
+            echo "info depth 2 multipv 2 score mate -3 wdl 0 10 990 pv d2d4 d7d5"
+            echo "info depth 2 multipv 1 score cp 23 nodes 90 hashfull 12 pv e2e4 e7e5"
+            echo "bestmove e2e4 ponder e7e5" ;;
+        "go depth 2") exit 1 ;;
+        "go depth 3") ;;
+        quit) exit 0 ;;
+    esac
+done
+"#,
+        )
+        .unwrap();
+        path
+    }
+
+    fn spawn(path: &Path) -> UciClient
+    {
+        UciClient::spawn("sh", &[path.to_str().unwrap()]).unwrap()
+    }
+
+    #[test]
+    fn test_handshake()
+    {
+        let path = mock_engine("test_handshake.sh");
+        let mut client = spawn(&path);
+        assert_eq!(client.name(), "Mock Engine");
+        assert_eq!(client.author(), "Nobody");
+        assert_eq!(client.options(), ["Hash", "Skill Level"]);
+
+        client.set_option("skill level", "3").unwrap();
+        assert!(client.set_option("Threads", "2").is_err());
+        client.new_game().unwrap();
+
+        assert!(UciClient::spawn("/nonexistent/engine", &[]).is_err());
+    }
+
+    #[test]
+    fn test_search()
+    {
+        let path = mock_engine("test_search.sh");
+        let mut client = spawn(&path);
+        let limits = SearchLimits { depth: Some(1), ..Default::default() };
+
+        let search = client
+            .search_board(&BitBoardState::start_of_game(), &limits)
+            .unwrap();
+        let e2e4 = Move::from_str("e2e4").unwrap();
+        assert_eq!(search.best_move, Some(e2e4));
+        assert_eq!(search.ponder, Some(Move::from_str("e7e5").unwrap()));
+        assert_eq!(search.infos.len(), 4);
+        assert_eq!(search.infos[0].string.as_deref(), Some("thinking hard"));
+        assert_eq!(search.infos[1].bound, Some(Bound::Lower));
+        assert_eq!(search.infos[1].time, Some(Duration::from_millis(20)));
+        assert_eq!(
+            search.infos[2].wdl,
+            Some(Wdl { win: 0, draw: 10, loss: 990 })
+        );
+        assert_eq!(search.infos[3].pv.len(), 2);
+        assert_eq!(search.score(), Some(SearchScore::Centipawns(23)));
+
+        let mut game = Game::default();
+        game.push(e2e4).unwrap();
+        assert_eq!(position_command(&game), "position startpos moves e2e4");
+        let limits = SearchLimits {
+            wtime: Some(Duration::from_secs(10)),
+            nodes: Some(500),
+            ..Default::default()
+        };
+        assert_eq!(go_command(&limits), "go wtime 10000 nodes 500");
+        assert_eq!(search_time(&game, &limits), None);
+    }
+
+    #[test]
+    fn test_recovery()
+    {
+        let path = mock_engine("test_recovery.sh");
+        let mut client = spawn(&path);
+        client.set_option("Hash", "64").unwrap();
+        client.set_timeout(Duration::from_millis(200));
+        client.set_search_timeout(Some(Duration::from_millis(200)));
+        let board = BitBoardState::start_of_game();
+        let depth =
+            |depth| SearchLimits { depth: Some(depth), ..Default::default() };
+
+        // a crash, and then a hang
+        assert!(client.search_board(&board, &depth(2)).is_err());
+        assert_eq!(client.restarts(), 1);
+        assert!(client.search_board(&board, &depth(3)).is_err());
+        assert_eq!(client.restarts(), 2);
+
+        let search = client.search_board(&board, &depth(1)).unwrap();
+        assert!(search.best_move.is_some());
+        assert_eq!(client.settings, [("Hash".to_string(), "64".to_string())]);
+    }
+}
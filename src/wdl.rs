@@ -0,0 +1,363 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::board::BitBoardState;
+use crate::eval::Evaluator;
+use crate::pgn::Game;
+use crate::piece::PieceType;
+use crate::search::SearchScore;
+
+/// The material counts the model distinguishes between, counting pawns as 1,
+/// minor pieces as 3, rooks as 5 and queens as 9. Both armies at the start
+/// of the game come to 78.
+const MIN_MATERIAL: u32 = 17;
+const MAX_MATERIAL: u32 = 78;
+/// The material count the model's parameters are scaled around.
+const MATERIAL_SCALE: f64 = 58.0;
+/// The fewest positions with a given material count worth fitting.
+const MIN_BUCKET_SIZE: usize = 50;
+
+/// Win, draw and loss probabilities in permille, for the side to move.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Wdl
+{
+    pub win:  u32,
+    pub draw: u32,
+    pub loss: u32,
+}
+
+/// Turns scores into the chances of winning, drawing and losing.
+///
+/// The chance of winning with a score `v` is `1 / (1 + exp((a - v) / b))`,
+/// and of losing is the same for `-v`, leaving the rest for a draw. The
+/// parameters `a` and `b` are cubics in the material on the board, since the
+/// same advantage is worth more as pieces come off.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct WdlModel
+{
+    /// The score at which winning is as likely as not, by material.
+    pub a: [f64; 4],
+    /// How quickly the chance of winning grows with the score, by material.
+    pub b: [f64; 4],
+}
+
+/// A position from a finished game.
+#[derive(Clone, Copy, Debug)]
+pub struct WdlSample
+{
+    /// The evaluation for the side to move, in centipawns.
+    pub eval:     i32,
+    pub material: u32,
+    /// The points the side to move went on to score.
+    pub outcome:  f64,
+}
+
+impl fmt::Display for Wdl
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{} {} {}", self.win, self.draw, self.loss)
+    }
+}
+
+impl Default for WdlModel
+{
+    /// Returns a model where a pawn up with most of the pieces left wins half
+    /// the time.
+    fn default() -> Self
+    {
+        WdlModel {
+            a: [-10.077, 32.608, -35.728, 113.197],
+            b: [24.286, -36.933, 19.131, 32.352],
+        }
+    }
+}
+
+impl fmt::Display for WdlModel
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        writeln!(f, "a = {:?}", self.a)?;
+        write!(f, "b = {:?}", self.b)
+    }
+}
+
+impl WdlModel
+{
+    /// Returns the model's parameters for the given material.
+    fn parameters(&self, material: u32) -> (f64, f64)
+    {
+        let m =
+            material.clamp(MIN_MATERIAL, MAX_MATERIAL) as f64 / MATERIAL_SCALE;
+        let cubic = |c: &[f64; 4]| ((c[0] * m + c[1]) * m + c[2]) * m + c[3];
+
+        (cubic(&self.a), cubic(&self.b))
+    }
+
+    /// Returns the chance of winning with a score in centipawns.
+    pub fn win_rate(&self, eval: i32, material: u32) -> f64
+    {
+        let (a, b) = self.parameters(material);
+        logistic(eval as f64, a, b)
+    }
+
+    pub fn wdl(&self, score: SearchScore, material: u32) -> Wdl
+    {
+        let eval = match score {
+            SearchScore::Centipawns(cp) => cp,
+            SearchScore::Mate(moves) if moves > 0 =>
+                return Wdl { win: 1000, draw: 0, loss: 0 },
+            SearchScore::Mate(_) =>
+                return Wdl { win: 0, draw: 0, loss: 1000 },
+        };
+
+        let win = (self.win_rate(eval, material) * 1000.0).round() as u32;
+        let loss = (self.win_rate(-eval, material) * 1000.0).round() as u32;
+        Wdl { win, draw: 1000u32.saturating_sub(win + loss), loss }
+    }
+
+    /// Fits a model to positions from finished games.
+    ///
+    /// The parameters are first fitted for each material count separately,
+    /// by maximum likelihood, and the cubics then fitted through them.
+    pub fn fit(samples: &[WdlSample]) -> Result<Self, Box<dyn Error>>
+    {
+        let mut buckets = vec![Vec::new(); (MAX_MATERIAL + 1) as usize];
+        for sample in samples {
+            let material = sample.material.clamp(MIN_MATERIAL, MAX_MATERIAL);
+            buckets[material as usize].push(*sample);
+        }
+
+        let mut a_points = Vec::new();
+        let mut b_points = Vec::new();
+        for (material, bucket) in buckets.iter().enumerate() {
+            if bucket.len() < MIN_BUCKET_SIZE {
+                continue;
+            }
+
+            let (a, b) = fit_parameters(bucket);
+            let m = material as f64 / MATERIAL_SCALE;
+            let weight = bucket.len() as f64;
+            a_points.push((m, a, weight));
+            b_points.push((m, b, weight));
+        }
+
+        if a_points.len() < 4 {
+            return Err("too few positions to fit a model!".into());
+        }
+
+        Ok(WdlModel { a: fit_cubic(&a_points), b: fit_cubic(&b_points) })
+    }
+}
+
+fn logistic(x: f64, a: f64, b: f64) -> f64 { 1.0 / (1.0 + ((a - x) / b).exp()) }
+
+/// Returns the mean log likelihood of the outcomes given the parameters.
+fn log_likelihood(samples: &[WdlSample], a: f64, b: f64) -> f64
+{
+    let total: f64 = samples
+        .iter()
+        .map(|sample| {
+            let eval = sample.eval as f64;
+            let win = logistic(eval, a, b);
+            let loss = logistic(-eval, a, b);
+            let p = match sample.outcome {
+                o if o > 0.75 => win,
+                o if o < 0.25 => loss,
+                _ => 1.0 - win - loss,
+            };
+            p.max(1e-12).ln()
+        })
+        .sum();
+
+    total / samples.len() as f64
+}
+
+/// Finds the parameters that best explain the outcomes of some positions with
+/// the same material, by a pattern search.
+fn fit_parameters(samples: &[WdlSample]) -> (f64, f64)
+{
+    let (mut a, mut b) = (100.0, 40.0);
+    let mut best = log_likelihood(samples, a, b);
+    let mut step = 16.0;
+
+    while step > 0.01 {
+        let mut improved = false;
+        for (da, db) in [(step, 0.0), (-step, 0.0), (0.0, step), (0.0, -step)] {
+            // both parameters must stay positive to leave room for draws
+            if a + da <= 0.0 || b + db <= 1.0 {
+                continue;
+            }
+
+            let likelihood = log_likelihood(samples, a + da, b + db);
+            if likelihood > best {
+                best = likelihood;
+                a += da;
+                b += db;
+                improved = true;
+            }
+        }
+
+        if !improved {
+            step /= 2.0;
+        }
+    }
+
+    (a, b)
+}
+
+/// Fits a cubic through weighted points by least squares, returning its
+/// coefficients from the highest power down.
+fn fit_cubic(points: &[(f64, f64, f64)]) -> [f64; 4]
+{
+    // the normal equations, as an augmented matrix
+    let mut m = [[0.0; 5]; 4];
+    for &(x, y, weight) in points {
+        let powers = [x * x * x, x * x, x, 1.0];
+        for i in 0..4 {
+            for j in 0..4 {
+                m[i][j] += weight * powers[i] * powers[j];
+            }
+            m[i][4] += weight * powers[i] * y;
+        }
+    }
+
+    for col in 0..4 {
+        let pivot = (col..4)
+            .max_by(|&i, &j| m[i][col].abs().total_cmp(&m[j][col].abs()))
+            .unwrap();
+        m.swap(col, pivot);
+
+        let pivot_row = m[col];
+        for (row, values) in m.iter_mut().enumerate() {
+            if row != col {
+                let factor = values[col] / pivot_row[col];
+                for (value, pivot) in values.iter_mut().zip(pivot_row).skip(col)
+                {
+                    *value -= factor * pivot;
+                }
+            }
+        }
+    }
+
+    [0, 1, 2, 3].map(|i| m[i][4] / m[i][i])
+}
+
+/// Counts the material on the board, as the model measures it.
+pub fn material(board: &BitBoardState) -> u32
+{
+    [
+        (PieceType::Pawn, 1),
+        (PieceType::Knight, 3),
+        (PieceType::Bishop, 3),
+        (PieceType::Rook, 5),
+        (PieceType::Queen, 9),
+    ]
+    .iter()
+    .map(|&(piece, value)| board.pieces_of_type(piece).count_ones() * value)
+    .sum()
+}
+
+/// Collects the quiet positions of a finished game for fitting a model,
+/// leaving out those in check or about to see a capture, where the static
+/// evaluation can't be trusted.
+pub fn samples(game: &Game, evaluator: &dyn Evaluator) -> Vec<WdlSample>
+{
+    let mut board = game.start.clone();
+    let mut samples = Vec::new();
+
+    for &mv in &game.moves {
+        let outcome = match game.result.score_for(board.turn()) {
+            Some(outcome) => outcome,
+            None => return Vec::new(),
+        };
+
+        if !board.in_check() && !board.is_noisy(mv) {
+            samples.push(WdlSample {
+                eval: evaluator.evaluate(&board),
+                material: material(&board),
+                outcome,
+            });
+        }
+        board.make_move(mv);
+    }
+
+    samples
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::board::GameState;
+
+    #[test]
+    fn test_wdl()
+    {
+        let model = WdlModel::default();
+
+        let even = model.wdl(SearchScore::Centipawns(0), 78);
+        assert_eq!(even.win, even.loss);
+        assert_eq!(even.win + even.draw + even.loss, 1000);
+
+        // a pawn up with most pieces on wins half the time
+        assert_eq!(model.wdl(SearchScore::Centipawns(100), 58).win, 500);
+
+        // the same advantage counts for more in an endgame
+        let endgame = model.win_rate(150, 20);
+        assert!(endgame > model.win_rate(150, 78));
+
+        let mate = model.wdl(SearchScore::Mate(-3), 30);
+        assert_eq!(mate, Wdl { win: 0, draw: 0, loss: 1000 });
+        assert_eq!(mate.to_string(), "0 0 1000");
+    }
+
+    #[test]
+    fn test_material()
+    {
+        assert_eq!(material(&BitBoardState::start_of_game()), 78);
+        let board = BitBoardState::from_fen("4k3/8/8/8/8/8/4P3/3QK3 w - - 0 1");
+        assert_eq!(material(&board.unwrap()), 10);
+    }
+
+    #[test]
+    fn test_fit()
+    {
+        // outcomes drawn from a known model should give it back
+        let truth = WdlModel::default();
+        let mut seed = 1u64;
+        let mut random = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 11) as f64 / (1u64 << 53) as f64
+        };
+
+        let mut samples = Vec::new();
+        for material in (20..=76).step_by(8) {
+            for i in 0..2000 {
+                let eval = (i % 101) * 8 - 400;
+                let win = truth.win_rate(eval, material);
+                let loss = truth.win_rate(-eval, material);
+                let roll = random();
+                let outcome = match roll {
+                    r if r < win => 1.0,
+                    r if r < win + loss => 0.0,
+                    _ => 0.5,
+                };
+                samples.push(WdlSample { eval, material, outcome });
+            }
+        }
+
+        let fitted = WdlModel::fit(&samples).unwrap();
+        for material in [28, 44, 60, 76] {
+            for eval in [50, 150, 300] {
+                let error = fitted.win_rate(eval, material)
+                    - truth.win_rate(eval, material);
+                assert!(error.abs() < 0.03, "{} {} {}", material, eval, error);
+            }
+        }
+
+        assert!(WdlModel::fit(&samples[..100]).is_err());
+    }
+}
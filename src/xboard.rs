@@ -0,0 +1,627 @@
+//! Refer to https://www.gnu.org/software/xboard/engine-intf.html
+
+use std::error::Error;
+use std::io::{BufRead, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::board::{BitBoardState, GameState};
+use crate::engine::{Engine, SearchListener};
+use crate::moves::Move;
+use crate::options::Options;
+use crate::piece::Color;
+use crate::search::{SearchInfo, SearchLimits, SearchResult, SearchScore};
+
+/// Speaks the Chess Engine Communication Protocol used by XBoard and
+/// WinBoard. Like the UCI front end, searches run on a background thread.
+pub struct Xboard<W: Write + Send + 'static>
+{
+    board:             BitBoardState,
+    engine:            Engine,
+    options:           Options,
+    /// Set while the engine is thinking about its own move, rather than
+    /// analyzing.
+    thinking:          bool,
+    /// Set once the running search's move has either been sent or thrown
+    /// away, whichever came first.
+    settled:           Arc<AtomicBool>,
+    out:               Arc<Mutex<W>>,
+    /// The side the engine plays, or `None` in force mode.
+    engine_color:      Option<Color>,
+    post:              bool,
+    analyzing:         bool,
+    moves_per_control: u32,
+    increment:         Duration,
+    engine_time:       Duration,
+    move_time:         Option<Duration>,
+    depth:             Option<u32>,
+}
+
+impl<W: Write + Send + 'static> Xboard<W>
+{
+    pub fn new(out: Arc<Mutex<W>>) -> Self
+    {
+        Xboard {
+            board: BitBoardState::start_of_game(),
+            engine: Engine::default(),
+            options: Options::default(),
+            thinking: false,
+            settled: Arc::new(AtomicBool::new(false)),
+            out,
+            engine_color: Some(Color::Black),
+            post: false,
+            analyzing: false,
+            moves_per_control: 40,
+            increment: Duration::ZERO,
+            engine_time: Duration::from_secs(300),
+            move_time: None,
+            depth: None,
+        }
+    }
+
+    /// Handles commands until `quit` or the end of the input.
+    pub fn run(&mut self, input: impl BufRead) -> Result<(), Box<dyn Error>>
+    {
+        for line in input.lines() {
+            if !self.handle(&line?) {
+                return Ok(());
+            }
+        }
+
+        self.handle("quit");
+        Ok(())
+    }
+
+    /// Handles a single command, returning false once it's time to quit.
+    pub fn handle(&mut self, line: &str) -> bool
+    {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match tokens.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return true,
+        };
+
+        let result = match command {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy"
+            | "computer" | "name" | "rating" | "." => Ok(()),
+            "protover" => {
+                self.send_features();
+                Ok(())
+            },
+            "ping" => {
+                if !self.analyzing {
+                    self.wait();
+                }
+                self.send(&format!("pong {}", args.join(" ")));
+                Ok(())
+            },
+            "new" => {
+                self.abandon();
+                self.board = BitBoardState::start_of_game();
+                self.engine_color = Some(Color::Black);
+                self.depth = None;
+                self.move_time = None;
+                self.engine.searcher().clear();
+                Ok(())
+            },
+            "setboard" => self.set_board(args),
+            "usermove" => self.user_move(args.first().copied().unwrap_or("")),
+            "go" => {
+                self.wait();
+                self.engine_color = Some(self.board.turn());
+                self.think();
+                Ok(())
+            },
+            "playother" => {
+                self.wait();
+                self.engine_color = Some(self.board.turn().opposite());
+                Ok(())
+            },
+            "force" | "result" => {
+                self.abandon();
+                self.engine_color = None;
+                Ok(())
+            },
+            "?" => {
+                self.engine.stop();
+                Ok(())
+            },
+            "cores" => self.set_option("Threads", args.first().copied()),
+            "memory" => self.set_option("Hash", args.first().copied()),
+            "option" => {
+                let option = args.join(" ");
+                match option.split_once('=') {
+                    Some((name, value)) => self.set_option(name, Some(value)),
+                    None => self.set_option(&option, None),
+                }
+            },
+            "level" => self.set_level(args),
+            "st" => self.set_move_time(args),
+            "sd" => parse_arg(args).map(|depth| self.depth = Some(depth)),
+            "time" =>
+                parse_arg(args).map(|cs| self.engine_time = centiseconds(cs)),
+            "otim" => parse_arg::<u64>(args).map(|_| ()),
+            "post" => {
+                self.post = true;
+                Ok(())
+            },
+            "nopost" => {
+                self.post = false;
+                Ok(())
+            },
+            "undo" => self.take_back(1),
+            "remove" => self.take_back(2),
+            "analyze" => {
+                self.abandon();
+                self.analyzing = true;
+                self.engine_color = None;
+                self.think();
+                Ok(())
+            },
+            "exit" => {
+                self.abandon();
+                self.analyzing = false;
+                Ok(())
+            },
+            "quit" => {
+                self.abandon();
+                return false;
+            },
+            // moves may be sent without `usermove` by hand
+            _ if Move::from_str(command).is_ok() => self.user_move(command),
+            _ => {
+                self.send(&format!("Error (unknown command): {}", command));
+                Ok(())
+            },
+        };
+
+        if let Err(e) = result {
+            self.send(&format!("Error ({}): {}", e, line));
+        }
+        true
+    }
+
+    fn send(&self, line: &str) { send(&self.out, line); }
+
+    fn send_features(&self)
+    {
+        self.send(
+            "feature myname=\"bcld\" setboard=1 usermove=1 ping=1 analyze=1 \
+             colors=0 smp=1 memory=1 sigint=0 sigterm=0",
+        );
+
+        // the hash size and threads are set with `memory` and `cores`
+        for option in self.options.iter() {
+            if !["Hash", "Threads"].contains(&option.name) {
+                self.send(&option.xboard_feature());
+            }
+        }
+        self.send("feature done=1");
+    }
+
+    /// Changes an option, pausing analysis while the engine is changed.
+    fn set_option(
+        &mut self, name: &str, value: Option<&str>,
+    ) -> Result<(), Box<dyn Error>>
+    {
+        if self.analyzing {
+            self.abandon();
+        }
+        let result = self.options.set(&mut self.engine, name, value);
+        if self.analyzing {
+            self.think();
+        }
+        result
+    }
+
+    /// Waits for any running search to finish, playing the move it found.
+    pub fn wait(&mut self) { self.finish(false); }
+
+    /// Stops any running search without playing its move, unless the move
+    /// has already been sent, since then the GUI has played it.
+    fn abandon(&mut self)
+    {
+        let sent = self.settled.swap(true, Ordering::SeqCst);
+        self.engine.stop();
+        self.finish(!sent);
+    }
+
+    /// Waits for any running search to finish, playing its move on the board
+    /// unless it was abandoned.
+    fn finish(&mut self, abandoned: bool)
+    {
+        let result = self.engine.wait();
+        let thinking = std::mem::take(&mut self.thinking);
+
+        if thinking && !abandoned {
+            if let Some(mv) = result.and_then(|result| result.best_move) {
+                self.board.make_move(mv);
+            }
+        }
+    }
+
+    fn set_board(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>>
+    {
+        let board = *BitBoardState::from_fen(&args.join(" "))
+            .map_err(|_| "illegal position")?;
+
+        self.abandon();
+        self.board = board;
+        self.restart_analysis();
+        Ok(())
+    }
+
+    fn user_move(&mut self, mv: &str) -> Result<(), Box<dyn Error>>
+    {
+        match self.analyzing {
+            true => self.abandon(),
+            false => self.wait(),
+        }
+
+        let legal = Move::from_str(mv)
+            .ok()
+            .filter(|mv| self.board.legal_moves().contains(mv));
+        let mv = match legal {
+            Some(mv) => mv,
+            None => {
+                self.send(&format!("Illegal move: {}", mv));
+                return Ok(());
+            },
+        };
+
+        self.board.make_move(mv);
+        if self.analyzing {
+            self.restart_analysis();
+        }
+        else if self.engine_color == Some(self.board.turn()) {
+            self.think();
+        }
+
+        Ok(())
+    }
+
+    /// Takes back moves, as long as they were made since the position was set
+    /// up.
+    fn take_back(&mut self, plies: usize) -> Result<(), Box<dyn Error>>
+    {
+        self.abandon();
+        if self.board.ply() < plies {
+            return Err("no move to undo".into());
+        }
+
+        for _ in 0..plies {
+            self.board.unmake_move();
+        }
+        self.restart_analysis();
+        Ok(())
+    }
+
+    /// Handles `level <moves> <base> <increment>`, where the base time is in
+    /// minutes or minutes:seconds and the increment is in seconds.
+    fn set_level(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>>
+    {
+        let (moves, base, increment) = match args {
+            [moves, base, increment] => (moves, base, increment),
+            _ => return Err("expected three arguments".into()),
+        };
+
+        let base = match base.split_once(':') {
+            Some((minutes, seconds)) =>
+                u64::from_str(minutes)? * 60 + u64::from_str(seconds)?,
+            None => u64::from_str(base)? * 60,
+        };
+
+        self.moves_per_control = u32::from_str(moves)?;
+        self.engine_time = Duration::from_secs(base);
+        self.increment = Duration::from_secs_f64(f64::from_str(increment)?);
+        self.move_time = None;
+        Ok(())
+    }
+
+    fn set_move_time(&mut self, args: &[&str]) -> Result<(), Box<dyn Error>>
+    {
+        let seconds: f64 = parse_arg(args)?;
+        self.move_time = Some(Duration::from_secs_f64(seconds));
+        Ok(())
+    }
+
+    /// Returns the limits for the engine's next move, or for analysis.
+    fn limits(&self) -> SearchLimits
+    {
+        let mut limits =
+            SearchLimits { depth: self.depth, ..Default::default() };
+
+        if self.analyzing {
+            limits.infinite = true;
+        }
+        else if let Some(move_time) = self.move_time {
+            limits.movetime = Some(move_time);
+        }
+        else {
+            let moves_made =
+                (self.board.move_number() as u32).saturating_sub(1);
+            limits.wtime = Some(self.engine_time);
+            limits.btime = Some(self.engine_time);
+            limits.winc = Some(self.increment);
+            limits.binc = Some(self.increment);
+            if self.moves_per_control > 0 {
+                limits.movestogo = Some(
+                    self.moves_per_control
+                        - moves_made % self.moves_per_control,
+                );
+            }
+        }
+
+        limits
+    }
+
+    fn restart_analysis(&mut self)
+    {
+        if self.analyzing {
+            self.abandon();
+            self.think();
+        }
+    }
+
+    /// Starts searching the current position in the background, playing the
+    /// move found unless analyzing.
+    fn think(&mut self)
+    {
+        self.wait();
+        self.settled = Arc::new(AtomicBool::new(false));
+        self.thinking = !self.analyzing;
+
+        let reporter = Reporter {
+            out:     self.out.clone(),
+            board:   self.board.clone(),
+            settled: self.settled.clone(),
+            post:    self.post || self.analyzing,
+            play:    self.thinking,
+        };
+        self.engine.start(&self.board, self.limits(), reporter);
+    }
+}
+
+/// Reports a search's progress as thinking output, and the move found if
+/// the engine is to play it.
+struct Reporter<W: Write + Send + 'static>
+{
+    out:     Arc<Mutex<W>>,
+    board:   BitBoardState,
+    settled: Arc<AtomicBool>,
+    post:    bool,
+    play:    bool,
+}
+
+impl<W: Write + Send + 'static> SearchListener for Reporter<W>
+{
+    fn on_info(&mut self, info: &SearchInfo)
+    {
+        if self.post {
+            send(&self.out, &thinking_line(info));
+        }
+    }
+
+    fn on_finished(&mut self, result: &SearchResult)
+    {
+        // once abandoned, the move must not be sent, and once sent it must
+        // not be abandoned
+        if !self.play || self.settled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        match result.best_move {
+            Some(mv) => send(&self.out, &format!("move {}", mv)),
+            None => send(&self.out, game_over(&self.board)),
+        }
+    }
+}
+
+/// Writes a line to the GUI straight away.
+fn send(out: &Mutex<impl Write>, line: &str)
+{
+    let mut out = out.lock().unwrap();
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+
+fn parse_arg<T: FromStr>(args: &[&str]) -> Result<T, Box<dyn Error>>
+where
+    T::Err: Error + 'static,
+{
+    let arg = args.first().ok_or("missing argument")?;
+    Ok(T::from_str(arg)?)
+}
+
+fn centiseconds(cs: u64) -> Duration { Duration::from_millis(cs * 10) }
+
+/// Returns the result to claim when the side to move has no legal moves.
+fn game_over(board: &BitBoardState) -> &'static str
+{
+    match (board.in_check(), board.turn()) {
+        (true, Color::White) => "0-1 {Black mates}",
+        (true, Color::Black) => "1-0 {White mates}",
+        (false, _) => "1/2-1/2 {Stalemate}",
+    }
+}
+
+/// Formats progress as a line of thinking output: depth, score in
+/// centipawns, time in centiseconds, nodes and the principal variation.
+fn thinking_line(info: &SearchInfo) -> String
+{
+    // mates are reported as 100000 plus the number of moves
+    let score = match info.score {
+        SearchScore::Centipawns(cp) => cp,
+        SearchScore::Mate(moves) if moves > 0 => 100000 + moves,
+        SearchScore::Mate(moves) => -100000 + moves,
+    };
+    let pv: Vec<String> = info.pv.iter().map(Move::to_string).collect();
+
+    format!(
+        "{} {} {} {} {}",
+        info.depth,
+        score,
+        info.time.as_millis() / 10,
+        info.nodes,
+        pv.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn run(commands: &[&str]) -> Vec<String>
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let mut xboard = Xboard::new(out.clone());
+        for command in commands {
+            xboard.handle(command);
+        }
+        xboard.wait();
+
+        let out = out.lock().unwrap();
+        String::from_utf8(out.clone())
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_handshake()
+    {
+        let out = run(&["xboard", "protover 2", "ping 7"]);
+
+        assert!(out.iter().all(|l| l.starts_with("feature ") || l == "pong 7"));
+        assert!(
+            out.contains(&"feature option=\"MultiPV -spin 1 1 256\"".into())
+        );
+        assert!(!out.iter().any(|l| l.contains("option=\"Hash ")));
+        assert_eq!(out[out.len() - 2], "feature done=1");
+        assert_eq!(out[out.len() - 1], "pong 7");
+    }
+
+    #[test]
+    fn test_options()
+    {
+        let out = run(&[
+            "memory 32",
+            "cores 2",
+            "option MultiPV=3",
+            "option NullMove=0",
+            "option Clear Hash",
+            "option MultiPV=0",
+            "option Contempt",
+            "cores many",
+        ]);
+        assert_eq!(out.len(), 3);
+        assert!(out.iter().all(|l| l.starts_with("Error (")));
+
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let mut xboard = Xboard::new(out);
+        xboard.handle("option MultiPV=3");
+        xboard.handle("option NullMove=0");
+        assert_eq!(xboard.engine.searcher().multi_pv(), 3);
+        assert!(!xboard.engine.searcher().options.null_move);
+    }
+
+    #[test]
+    fn test_plays_moves()
+    {
+        let out = run(&["new", "cores 2", "sd 2", "usermove e2e4", "ping 1"]);
+        assert!(out[0].starts_with("move "));
+        assert_eq!(out[1], "pong 1");
+
+        // the engine plays white after go, and shows its thinking when asked
+        let out = run(&["new", "post", "sd 2", "go"]);
+        assert!(out[0].starts_with("1 "));
+        assert!(out.last().unwrap().starts_with("move "));
+
+        let out = run(&[
+            "new",
+            "force",
+            "setboard 7k/R7/8/8/8/8/8/1R4K1 w - - 0 1",
+            "sd 4",
+            "go",
+        ]);
+        assert_eq!(out.last().unwrap(), "move b1b8");
+    }
+
+    #[test]
+    fn test_force_and_undo()
+    {
+        let out = run(&[
+            "new",
+            "force",
+            "usermove e2e4",
+            "usermove e7e5",
+            "remove",
+            "undo",
+            "usermove e2e5",
+        ]);
+
+        assert_eq!(out, vec![
+            "Error (no move to undo): undo",
+            "Illegal move: e2e5"
+        ]);
+    }
+
+    #[test]
+    fn test_move_sent_before_takeback()
+    {
+        // a move already sent stays on the board when the search is abandoned
+        for commands in
+            [["remove", "usermove d2d4"], ["force", "usermove g1f3"]]
+        {
+            let out = Arc::new(Mutex::new(Vec::new()));
+            let mut xboard = Xboard::new(out.clone());
+            for command in ["new", "sd 3", "usermove e2e4"] {
+                xboard.handle(command);
+            }
+            for _ in 0..5000 {
+                if out.lock().unwrap().starts_with(b"move ") {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            for command in commands {
+                xboard.handle(command);
+            }
+            xboard.wait();
+
+            let out = String::from_utf8(out.lock().unwrap().clone()).unwrap();
+            assert!(out.starts_with("move "));
+            assert!(!out.contains("Error") && !out.contains("Illegal"));
+        }
+    }
+
+    #[test]
+    fn test_analyze()
+    {
+        let out = run(&["new", "analyze", "usermove e2e4", "exit", "ping 2"]);
+
+        assert!(out.iter().all(|l| !l.starts_with("move ")));
+        assert_eq!(out.last().unwrap(), "pong 2");
+    }
+
+    #[test]
+    fn test_level_and_time()
+    {
+        let out = Arc::new(Mutex::new(Vec::new()));
+        let mut xboard = Xboard::new(out);
+
+        xboard.handle("level 40 0:30 2.5");
+        xboard.handle("time 1500");
+        let limits = xboard.limits();
+        assert_eq!(limits.wtime, Some(Duration::from_secs(15)));
+        assert_eq!(limits.winc, Some(Duration::from_millis(2500)));
+        assert_eq!(limits.movestogo, Some(40));
+
+        xboard.handle("st 2");
+        assert_eq!(xboard.limits().movetime, Some(Duration::from_secs(2)));
+    }
+}
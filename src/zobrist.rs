@@ -0,0 +1,47 @@
+//! Refer to https://www.chessprogramming.org/Zobrist_Hashing
+
+/// One key per piece kind (in `BitBoardType` order) and square.
+pub const PIECE_KEYS: [[u64; 64]; 12] = piece_keys();
+/// One key per castling right: white king side, white queen side, black king
+/// side and black queen side.
+pub const CASTLE_KEYS: [u64; 4] = keys(0x4ca5);
+/// One key per file of the en passant target.
+pub const EN_PASSANT_KEYS: [u64; 8] = keys(0xe9a5);
+/// Toggled whenever it is black to move.
+pub const SIDE_KEY: u64 = keys::<1>(0x51de)[0];
+
+/// Steps a splitmix64 generator, returning the new state and its output.
+const fn splitmix(state: u64) -> (u64, u64)
+{
+    let state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+
+    (state, z ^ (z >> 31))
+}
+
+const fn keys<const N: usize>(seed: u64) -> [u64; N]
+{
+    let mut out = [0; N];
+    let mut state = seed;
+    let mut i = 0;
+    while i < N {
+        let (next, key) = splitmix(state);
+        state = next;
+        out[i] = key;
+        i += 1;
+    }
+    out
+}
+
+const fn piece_keys() -> [[u64; 64]; 12]
+{
+    let mut out = [[0; 64]; 12];
+    let mut i = 0;
+    while i < 12 {
+        out[i] = keys(0x9b0a + i as u64 * 0x1000);
+        i += 1;
+    }
+    out
+}